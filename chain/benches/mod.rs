@@ -1,6 +1,7 @@
 #![feature(test)]
 
 extern crate chain;
+extern crate rand;
 extern crate test;
 
 #[cfg(test)]
@@ -36,3 +37,157 @@ mod benchmarks {
 		b.iter(|| merkle_root(&vec));
 	}
 }
+
+// XOR and zero-test kernels on coded symbols, and encoding and decoding of a two-layer tree with each kernel
+// Each benchmark selects its kernel with scoped_kernel, which restores the kernel of the process when it ends
+#[cfg(test)]
+mod symbol_benchmarks {
+	use std::sync::Arc;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use super::chain::{Block, Code, Decoder, LayerArena};
+	use super::chain::fixtures::{codes_dir, test_block};
+	use super::chain::constants::BASE_SYMBOL_SIZE;
+	use super::chain::codes::{read_codes, shared_codes};
+	use super::chain::decoder::TreeDecoder;
+	use super::chain::symbol_ops::{Kernel, scoped_kernel, xor_into, is_zero};
+	use super::test::{Bencher, black_box};
+
+	fn codes() -> (Vec<Code>, Vec<Code>) {
+		read_codes(&codes_dir(), vec![128, 64])
+	}
+
+	fn block() -> Block {
+		test_block(&mut StdRng::seed_from_u64(1))
+	}
+
+	fn bench_xor(b: &mut Bencher, kernel: Kernel) {
+		let _kernel = scoped_kernel(kernel);
+		let mut sum = [7u8; BASE_SYMBOL_SIZE];
+		let symbol = [9u8; BASE_SYMBOL_SIZE];
+		b.iter(|| xor_into(black_box(&mut sum), black_box(&symbol)));
+	}
+
+	fn bench_zero_test(b: &mut Bencher, kernel: Kernel) {
+		let _kernel = scoped_kernel(kernel);
+		let symbol = [0u8; BASE_SYMBOL_SIZE];
+		b.iter(|| is_zero(black_box(&symbol)));
+	}
+
+	// encoding of the base layer from its 128 systematic symbols
+	fn bench_encode(b: &mut Bencher, kernel: Kernel) {
+		let _kernel = scoped_kernel(kernel);
+		let (codes, _) = codes();
+		let mut decoder = Decoder::new(0, Arc::new(codes[0].clone()));
+		let mut systematic = LayerArena::with_capacity(true, decoder.n as usize);
+		for i in 0..decoder.k {
+			systematic.push(&[i as u8; BASE_SYMBOL_SIZE]);
+		}
+		b.iter(|| {
+			decoder.reset();
+			decoder.encode(systematic.clone(), true)
+		});
+	}
+
+	// decoding of the tree with a quarter of the base layer missing
+	fn bench_decode(b: &mut Bencher, kernel: Kernel) {
+		let _kernel = scoped_kernel(kernel);
+		let block = block();
+		let (_, codes) = codes();
+		let received: Vec<Vec<u64>> = (0..2).map(|lvl| (0..block.layer_length(lvl) as u64).filter(|i| lvl > 0 || i % 4 != 1).collect()).collect();
+		let (symbols, indices) = block.collect_samples(&received);
+		let mut decoder = TreeDecoder::new(&shared_codes(&codes), &block.block_header.coded_merkle_roots_hashes);
+		b.iter(|| {
+			decoder.reset(&block.block_header, &[]);
+			assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+		});
+	}
+
+	#[bench]
+	fn bench_encode_bytes(b: &mut Bencher) { bench_encode(b, Kernel::Bytes); }
+
+	#[bench]
+	fn bench_encode_words(b: &mut Bencher) { bench_encode(b, Kernel::Words); }
+
+	#[bench]
+	fn bench_encode_sse2(b: &mut Bencher) { bench_encode(b, Kernel::Sse2); }
+
+	#[bench]
+	fn bench_encode_avx2(b: &mut Bencher) { bench_encode(b, Kernel::Avx2); }
+
+	#[bench]
+	fn bench_decode_bytes(b: &mut Bencher) { bench_decode(b, Kernel::Bytes); }
+
+	#[bench]
+	fn bench_decode_words(b: &mut Bencher) { bench_decode(b, Kernel::Words); }
+
+	#[bench]
+	fn bench_decode_sse2(b: &mut Bencher) { bench_decode(b, Kernel::Sse2); }
+
+	#[bench]
+	fn bench_decode_avx2(b: &mut Bencher) { bench_decode(b, Kernel::Avx2); }
+
+	#[bench]
+	fn bench_xor_bytes(b: &mut Bencher) { bench_xor(b, Kernel::Bytes); }
+
+	#[bench]
+	fn bench_xor_words(b: &mut Bencher) { bench_xor(b, Kernel::Words); }
+
+	#[bench]
+	fn bench_xor_sse2(b: &mut Bencher) { bench_xor(b, Kernel::Sse2); }
+
+	#[bench]
+	fn bench_xor_avx2(b: &mut Bencher) { bench_xor(b, Kernel::Avx2); }
+
+	#[bench]
+	fn bench_zero_test_bytes(b: &mut Bencher) { bench_zero_test(b, Kernel::Bytes); }
+
+	#[bench]
+	fn bench_zero_test_words(b: &mut Bencher) { bench_zero_test(b, Kernel::Words); }
+
+	#[bench]
+	fn bench_zero_test_sse2(b: &mut Bencher) { bench_zero_test(b, Kernel::Sse2); }
+
+	#[bench]
+	fn bench_zero_test_avx2(b: &mut Bencher) { bench_zero_test(b, Kernel::Avx2); }
+}
+
+// Hashing a base symbol, and building a two-layer tree from its base symbols, with each hash function
+#[cfg(test)]
+mod hash_benchmarks {
+	use super::chain::{CmtHasher, HashFunction, SymbolBase};
+	use super::chain::constants::{BASE_SYMBOL_SIZE, RATE};
+	use super::chain::codes::read_codes;
+	use super::chain::fixtures::codes_dir;
+	use super::chain::coded_merkle_roots::coded_merkle_roots_with_errors;
+	use super::test::{Bencher, black_box};
+
+	fn bench_hash_symbol(b: &mut Bencher, hash_function: HashFunction) {
+		let symbol = [7u8; BASE_SYMBOL_SIZE];
+		b.iter(|| hash_function.hash(black_box(&symbol)));
+	}
+
+	fn bench_build_tree(b: &mut Bencher, hash_function: HashFunction) {
+		let (codes, _) = read_codes(&codes_dir(), vec![128, 64]);
+		let symbols: Vec<SymbolBase> = (0..128).map(|i| [i as u8; BASE_SYMBOL_SIZE]).collect();
+		b.iter(|| coded_merkle_roots_with_errors(&symbols, 256, RATE, codes.clone(), &[], &hash_function));
+	}
+
+	#[bench]
+	fn bench_hash_symbol_double_sha256(b: &mut Bencher) { bench_hash_symbol(b, HashFunction::DoubleSha256); }
+
+	#[bench]
+	fn bench_hash_symbol_sha256(b: &mut Bencher) { bench_hash_symbol(b, HashFunction::Sha256); }
+
+	#[bench]
+	fn bench_hash_symbol_blake2b(b: &mut Bencher) { bench_hash_symbol(b, HashFunction::Blake2b); }
+
+	#[bench]
+	fn bench_build_tree_double_sha256(b: &mut Bencher) { bench_build_tree(b, HashFunction::DoubleSha256); }
+
+	#[bench]
+	fn bench_build_tree_sha256(b: &mut Bencher) { bench_build_tree(b, HashFunction::Sha256); }
+
+	#[bench]
+	fn bench_build_tree_blake2b(b: &mut Bencher) { bench_build_tree(b, HashFunction::Blake2b); }
+}
@@ -0,0 +1,209 @@
+use std::slice::ChunksExact;
+use hash::H256;
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE};
+use decoder::Symbol;
+use SymbolBase;
+
+// Storage of a layer of CMT in a single contiguous byte arena
+// Symbol i occupies bytes [i * symbol_size, (i + 1) * symbol_size). Symbols are read as &[u8] views without copying,
+// and a symbol on an upper layer is read as its hashes of 32 bytes, AGGREGATE of them by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerArena {
+	base: bool, // base and upper symbols can have the same size, so the kind of layer is kept
+	symbol_size: usize,
+	bytes: Vec<u8>,
+}
+
+impl LayerArena {
+	// An empty layer with room for n symbols
+	pub fn with_capacity(base: bool, n: usize) -> Self {
+		LayerArena::with_symbol_size(base, if base { BASE_SYMBOL_SIZE } else { 32 * AGGREGATE }, n)
+	}
+
+	// An empty layer with room for n symbols of symbol_size bytes, e.g. for a tree with another aggregation factor
+	pub fn with_symbol_size(base: bool, symbol_size: usize, n: usize) -> Self {
+		LayerArena {
+			base: base,
+			symbol_size: symbol_size,
+			bytes: Vec::with_capacity(n * symbol_size),
+		}
+	}
+
+	// A layer of n zero symbols
+	pub fn zeroed(base: bool, n: usize) -> Self {
+		let mut arena = LayerArena::with_capacity(base, n);
+		arena.resize(n);
+		arena
+	}
+
+	// A layer holding symbols of the same kind, e.g. symbols received from the network
+	// Fails if a symbol is of another kind or empty
+	pub fn from_symbols(symbols: &[Symbol]) -> Result<Self, String> {
+		let base = match symbols.first() {
+			Some(&Symbol::Upper(_)) => false,
+			_ => true,
+		};
+		let mut arena = LayerArena::with_capacity(base, symbols.len());
+		for (i, symbol) in symbols.iter().enumerate() {
+			match (base, symbol) {
+				(true, &Symbol::Base(ref sym)) => arena.push(sym),
+				(false, &Symbol::Upper(ref sym)) => arena.push(sym),
+				_ => return Err(format!("symbol {} is not of the same kind as the first symbol of the layer", i)),
+			}
+		}
+		Ok(arena)
+	}
+
+	// A layer taking over bytes that hold whole symbols, e.g. the padded data of a block
+	// Fails if the bytes do not split into whole symbols
+	pub fn from_bytes(base: bool, bytes: Vec<u8>) -> Result<Self, String> {
+		let symbol_size = if base { BASE_SYMBOL_SIZE } else { 32 * AGGREGATE };
+		if bytes.len() % symbol_size != 0 {
+			return Err(format!("{} bytes are not a whole number of symbols of {} bytes", bytes.len(), symbol_size));
+		}
+		Ok(LayerArena {
+			base: base,
+			symbol_size: symbol_size,
+			bytes: bytes,
+		})
+	}
+
+	pub fn push(&mut self, symbol: &[u8]) {
+		assert_eq!(symbol.len(), self.symbol_size, "symbol of a wrong size");
+		self.bytes.extend_from_slice(symbol);
+	}
+
+	// Resize the layer to n symbols, the new symbols are zero
+	pub fn resize(&mut self, n: usize) {
+		self.bytes.resize(n * self.symbol_size, 0u8);
+	}
+
+	// Set every byte of the layer to zero, keeping its length
+	pub fn zero(&mut self) {
+		for b in self.bytes.iter_mut() {
+			*b = 0u8;
+		}
+	}
+
+	pub fn swap(&mut self, i: usize, j: usize) {
+		if i == j {
+			return;
+		}
+		let (low, high) = if i < j { (i, j) } else { (j, i) };
+		let size = self.symbol_size;
+		let (first, second) = self.bytes.split_at_mut(high * size);
+		first[low * size .. (low + 1) * size].swap_with_slice(&mut second[0..size]);
+	}
+
+	pub fn is_base(&self) -> bool {
+		self.base
+	}
+
+	pub fn symbol_size(&self) -> usize {
+		self.symbol_size
+	}
+
+	// number of symbols in the layer
+	pub fn len(&self) -> usize {
+		self.bytes.len() / self.symbol_size
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.bytes.is_empty()
+	}
+
+	pub fn symbol(&self, index: usize) -> &[u8] {
+		&self.bytes[index * self.symbol_size .. (index + 1) * self.symbol_size]
+	}
+
+	pub fn symbol_mut(&mut self, index: usize) -> &mut [u8] {
+		&mut self.bytes[index * self.symbol_size .. (index + 1) * self.symbol_size]
+	}
+
+	// all symbols in order
+	pub fn iter(&self) -> ChunksExact<u8> {
+		self.bytes.chunks_exact(self.symbol_size)
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes
+	}
+
+	pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+		&mut self.bytes
+	}
+
+	// hash in slot j of symbol index on an upper layer
+	pub fn hash(&self, index: usize, j: usize) -> &H256 {
+		&self.hashes(index)[j]
+	}
+
+	// the hashes of symbol index on an upper layer, viewed in place
+	pub fn hashes(&self, index: usize) -> &[H256] {
+		assert!(!self.base, "symbols on the base layer are not hashes");
+		H256::slice_from_bytes(self.symbol(index))
+	}
+
+	// copy of a symbol on the base layer
+	pub fn base_symbol(&self, index: usize) -> SymbolBase {
+		assert!(self.base, "not a symbol on the base layer");
+		let mut symbol = [0u8; BASE_SYMBOL_SIZE];
+		symbol.copy_from_slice(self.symbol(index));
+		symbol
+	}
+
+	// copy of a symbol, with the type used by the decoder
+	pub fn to_symbol(&self, index: usize) -> Symbol {
+		if self.base {
+			Symbol::Base(self.base_symbol(index))
+		} else {
+			let mut symbol = [0u8; 32 * AGGREGATE];
+			symbol.copy_from_slice(self.symbol(index));
+			Symbol::Upper(symbol)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_layer_arena_views() {
+		let mut upper = [0u8; 32 * AGGREGATE];
+		upper[32] = 1;
+		let layer = LayerArena::from_symbols(&[Symbol::Upper([0u8; 32 * AGGREGATE]), Symbol::Upper(upper)]).unwrap();
+		assert!(!layer.is_base());
+		assert_eq!(layer.len(), 2);
+		assert_eq!(layer.as_bytes().len(), 2 * 32 * AGGREGATE);
+		assert_eq!(layer.symbol(1), &upper[..]);
+		assert_eq!(*layer.hash(1, 1), H256::from(&upper[32..64]));
+		assert_eq!(layer.hashes(1).len(), AGGREGATE);
+		assert_eq!(&layer.hashes(1)[1], layer.hash(1, 1));
+		assert_eq!(layer.iter().count(), 2);
+		let mut wide = LayerArena::with_symbol_size(false, 32 * 16, 1);
+		wide.resize(1);
+		assert_eq!((wide.len(), wide.hashes(0).len()), (1, 16));
+		match layer.to_symbol(1) {
+			Symbol::Upper(sym) => assert_eq!(&sym[..], &upper[..]),
+			_ => panic!("symbols on an upper layer are upper symbols"),
+		}
+
+		let mut base = LayerArena::with_capacity(true, 1);
+		assert!(base.is_empty());
+		base.push(&[3u8; BASE_SYMBOL_SIZE]);
+		base.symbol_mut(0)[0] = 4;
+		assert_eq!(base.base_symbol(0)[0..2], [4u8, 3u8]);
+		base.resize(3);
+		base.swap(2, 0);
+		assert_eq!((base.symbol(0)[0], base.symbol(2)[0..2].to_vec()), (0u8, vec![4u8, 3u8]));
+		base.zero();
+		assert_eq!(LayerArena::zeroed(true, 3), base);
+
+		// a layer holds symbols of one kind
+		assert!(LayerArena::from_symbols(&[Symbol::Base([0u8; BASE_SYMBOL_SIZE]), Symbol::Upper(upper)]).is_err());
+		assert!(LayerArena::from_symbols(&[Symbol::Upper(upper), Symbol::Empty]).is_err());
+		assert_eq!(LayerArena::from_bytes(true, vec![0u8; 3 * BASE_SYMBOL_SIZE]), Ok(LayerArena::zeroed(true, 3)));
+		assert!(LayerArena::from_bytes(false, vec![0u8; 32]).is_err());
+	}
+}
@@ -1,64 +1,42 @@
+use std::io;
+use std::convert::TryInto;
 use hex::FromHex;
-//use ser::{deserialize};
-use ser::{deserialize, serialize};
+use ser::{deserialize, Serializable, Deserializable, Stream, Reader, Error, Deserialize, SliceReader};
 use {BlockHeader, Transaction};
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE};
-use {Symbols, SymbolBase, SymbolUp};
+use constants::{BASE_SYMBOL_SIZE, RATE, AGGREGATE, MAX_CMT_LAYERS, MAX_HEADER_HASHES};
+use {SymbolBase, SymbolUp};
+use arena::LayerArena;
+use layout::CmtLayout;
 use bytes::Bytes;
-use coded_merkle_roots::coded_merkle_roots;
+use coded_merkle_roots::{coded_merkle_tree, errors_from_correct, compute_hash};
+use cmt_hash::{CmtHasher, HashFunction};
 use hash::H256;
 use merkle_root::merkle_root;
-use decoder::{Code, Symbol};
+use decoder::{Code, Symbol, InjectedError};
+use rand::Rng;
 use rand::distributions::{Distribution, Bernoulli, Uniform};
-use CodingErr;
+use sampling::{SamplingParams, SamplingStats, draw_indices, dedup_indices};
+use top_root::{top_root, top_proof, top_proof_len};
 
-//#[derive(Debug, PartialEq, Clone, Serializable, Deserializable)]
-#[derive(Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Block {
 	pub block_header: BlockHeader,
 	pub transactions: Vec<Transaction>,
-	pub coded_tree: Vec<Symbols>, //Coded Merkle tree constructed from the transactions in the block
+	pub coded_tree: Vec<LayerArena>, //Coded Merkle tree constructed from the transactions in the block, one arena per layer
 	pub block_size_in_bytes: usize, // size of transactions in the block, used to specify block size for tests
 }
 
-// index of the parent symbol on the coded Merkle tree
-// k is number of systematic symbols in the current layer
-pub fn next_index(index: u32, k: u32, reduce_factor: u32) -> u32 {
-	if index <= k - 1 {
-		index / reduce_factor
-	}
-	else {
-		(index - k) / ((AGGREGATE as u32) - reduce_factor)
-	}
-}
-
-// randomly sample a parity sibling of a systematic symbols
-// a parity sibling refers to a parity symbol that shares the same parent symbol as the systematic symbol
-pub fn sample_parity_sibling(index: u32, n: u32, header_size: u32, reduce_factor: u32) -> u32 {
+// randomly sample a parity sibling of a symbol on layer lvl
+// a parity sibling refers to a parity symbol that shares the same parent symbol as the symbol, on the top layer any parity symbol
+// all randomness is drawn from rng, so that a sampling run can be replayed from its seed
+pub fn sample_parity_sibling<R: Rng>(layout: &CmtLayout, lvl: u32, index: u64, rng: &mut R) -> u64 {
     // Use the same symbol if v is true, otherwise use a random sibling sampled uniformly
-	let d = Bernoulli::new(RATE as f64).unwrap();
-    let v = d.sample(&mut rand::thread_rng());
+	let d = Bernoulli::new(layout.rate() as f64).unwrap();
+    let v = d.sample(rng);
     if v == true {return index;}
-    else {
-    	let k = ((n as f32) * RATE) as u32;
-        let mut siblings: Vec<u32> =  vec![];
-			if n > header_size { //if this is not the last layer
-				let parent: u32 = next_index(index, k, reduce_factor);
-			    for i in k..n {
-				    if next_index(i as u32, k, reduce_factor) == parent {
-					    siblings.push(i as u32);
-				    }
-			    }
-			} else {
-			    for i in k..n {
-				    siblings.push(i as u32);
-			    }
-		    }
-    	let l = siblings.len();
-        let die = Uniform::from(0..l); //uniformly pick one sibling symbol to sample
-        let throw = die.sample(&mut rand::thread_rng());
-        return siblings[throw];
-    }    
+    let siblings = layout.parity_siblings(lvl, index);
+    let die = Uniform::from(0..(siblings.end - siblings.start)); //uniformly pick one sibling symbol to sample
+    siblings.start + die.sample(rng)
 }
 
 // pub fn next_index<T>(index: T, k: T, reduce_factor: T) -> T {
@@ -70,24 +48,106 @@ pub fn sample_parity_sibling(index: u32, n: u32, header_size: u32, reduce_factor
 // 	}
 // }
 
-// impl From<&'static str> for Block {
-// 	fn from(s: &'static str) -> Self {
-// 		deserialize(&s.from_hex::<Vec<u8>>().unwrap() as &[u8]).unwrap()
-// 	}
-// }
+// Version of the wire format of blocks, the first byte of a serialized block
+pub const BLOCK_WIRE_VERSION: u8 = 2;
+
+// The part of the coded Merkle tree sent with a block
+// The numbers of symbols on the layers are committed by the header, so they are not sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TreeEncoding {
+	None, // header and transactions only, the coded tree of the received block is empty
+	Full, // every coded symbol of every layer
+	// the base layer and the parity symbols of the upper layers
+	// the systematic symbols of an upper layer are recomputed from the hashes of the layer below, as for a correctly built tree
+	Parity,
+}
+
+impl Serializable for TreeEncoding {
+	fn serialize(&self, stream: &mut Stream) {
+		let code: u8 = match *self {
+			TreeEncoding::None => 0,
+			TreeEncoding::Full => 1,
+			TreeEncoding::Parity => 2,
+		};
+		stream.append(&code);
+	}
+}
+
+impl Deserializable for TreeEncoding {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		match reader.read::<u8>()? {
+			0 => Ok(TreeEncoding::None),
+			1 => Ok(TreeEncoding::Full),
+			2 => Ok(TreeEncoding::Parity),
+			_ => Err(Error::MalformedData),
+		}
+	}
+}
+
+// A sampled base symbol read from a received buffer, borrowing the symbol and its proofs from it, see Block::base_sample_bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaseSampleRef<'a> {
+	pub symbol: &'a SymbolBase,
+	pub proof: &'a [SymbolUp],
+	pub top_proof: &'a [H256],
+}
+
+impl<'a> Deserialize<'a> for BaseSampleRef<'a> {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let sample = BaseSampleRef {
+			symbol: reader.read()?,
+			proof: reader.read()?,
+			top_proof: reader.read()?,
+		};
+		if sample.proof.len() >= MAX_CMT_LAYERS || sample.top_proof.len() > top_proof_len(MAX_HEADER_HASHES as u64) {
+			return Err(Error::MalformedData);
+		}
+		Ok(sample)
+	}
+}
+
+impl From<&'static str> for Block {
+	fn from(s: &'static str) -> Self {
+		deserialize(&s.from_hex::<Vec<u8>>().unwrap() as &[u8]).unwrap()
+	}
+}
 
 impl Block {
 	// construct a block 
 	// correct indicates if we will perform coding correctly or not on each level of the CMT
-	pub fn new(header: BlockHeader, transactions: &Vec<Transaction>, block_size: usize, header_size: u32, 
-		codes: &Vec<Code>, correct: Vec<bool>) -> Self {
+	// rng generates the random bytes used to pad the transactions up to block_size
+	pub fn new<R: Rng>(header: BlockHeader, transactions: &Vec<Transaction>, block_size: usize, header_size: u32, 
+		codes: &Vec<Code>, correct: Vec<bool>, rng: &mut R) -> Self {
+		let errors = errors_from_correct(&correct, codes, RATE);
+		Block::new_with_errors(header, transactions, block_size, header_size, codes, &errors, rng)
+	}
+
+	// construct a block whose coded symbols are corrupted as specified by errors after encoding
+	pub fn new_with_errors<R: Rng>(header: BlockHeader, transactions: &Vec<Transaction>, block_size: usize, header_size: u32, 
+		codes: &Vec<Code>, errors: &[InjectedError], rng: &mut R) -> Self {
+		Block::new_with_hash_function(header, transactions, block_size, header_size, codes, errors, HashFunction::default(), rng)
+	}
+
+	// construct a block whose coded Merkle tree is hashed with hash_function, which is committed in the parameters of the header
+	pub fn new_with_hash_function<R: Rng>(header: BlockHeader, transactions: &Vec<Transaction>, block_size: usize, header_size: u32, 
+		codes: &Vec<Code>, errors: &[InjectedError], hash_function: HashFunction, rng: &mut R) -> Self {
+		Block::new_with_coding(header, transactions, block_size, header_size, codes, errors, hash_function, RATE, AGGREGATE, rng)
+	}
+
+	// construct a block whose coded Merkle tree has another rate and aggregation factor, committed in the parameters of the header,
+	// e.g. for a sweep over reference designs. The codes must have the rate, and the tree has the layout of CmtLayout::for_block_with_coding
+	// Only trees aggregating AGGREGATE hashes can be verified and sent in proofs by this crate, see BlockHeader::layout
+	pub fn new_with_coding<R: Rng>(header: BlockHeader, transactions: &Vec<Transaction>, block_size: usize, header_size: u32, 
+		codes: &Vec<Code>, errors: &[InjectedError], hash_function: HashFunction, rate: f32, aggregate: usize, rng: &mut R) -> Self {
 		// let block = Block {block_header: header.clone(), transactions: transactions.clone(), 
 		// coded_tree: vec![], block_size_in_bytes: block_size};
 
+		let mut header = header;
+		header.cmt_params.hash_function = hash_function;
 		let block = Block {block_header: header.clone(), transactions: transactions.to_vec(), 
 			coded_tree: vec![], block_size_in_bytes: block_size};
 		//Compute coded Merkle tree and hashes of the last layer from the transactions	
-		let (_, root_hashes, tree) = block.coded_merkle_roots(header_size, RATE, codes.to_vec(), correct);
+		let (_, root_hashes, tree) = block.coded_merkle_roots_with_errors(header_size, rate, aggregate, codes.to_vec(), errors, rng);
 		let mut new_header = header;
 		// Merkle root from transactions
 		// base unit is transaction
@@ -95,7 +155,37 @@ impl Block {
 		// Root hashes of CMT from transactions
 		// base unit is symbol
 		new_header.coded_merkle_roots_hashes = root_hashes;
-		Block { block_header: new_header, transactions: transactions.to_vec(), coded_tree: tree, block_size_in_bytes: block_size}
+		// Parameters of CMT, so that its layout is authenticated by the header
+		new_header.cmt_params = CmtLayout::from_coded_lengths(tree.iter().map(|layer| layer.len() as u64).collect(), rate, aggregate as u64)
+			.params(hash_function);
+		Block { block_header: new_header, transactions: transactions.to_vec(), coded_tree: tree, block_size_in_bytes: block_size }
+	}
+
+	// hash function of the coded Merkle tree, committed by the header
+	pub fn hash_function(&self) -> HashFunction {
+		self.block_header.cmt_params.hash_function
+	}
+
+	// the same block with its header in single-root mode: the header stores a single root over the hashes of the top layer
+	pub fn with_single_root(mut self) -> Self {
+		let root = top_root(&self.hash_function(), &self.top_hashes());
+		self.block_header.coded_merkle_roots_hashes = vec![root];
+		self
+	}
+
+	// hashes of the symbols on the top layer of CMT, served with the block in single-root mode
+	pub fn top_hashes(&self) -> Vec<H256> {
+		compute_hash(&self.coded_tree[self.coded_tree.len() - 1], &self.hash_function())
+	}
+
+	// Inclusion proof under the single root of the top-layer symbol of the Merkle proof of symbol index on layer lvl,
+	// or of the symbol itself on the top layer. Empty if the header stores all hashes of the top layer
+	pub fn top_proof(&self, lvl: usize, index: u64) -> Vec<H256> {
+		if self.block_header.single_root().is_none() {
+			return vec![];
+		}
+		let top_index = self.layout().proof_indices(lvl as u32, index).last().cloned().unwrap_or(index);
+		top_proof(&self.hash_function(), &self.top_hashes(), top_index)
 	}
 
 	/// Returns block's merkle root.
@@ -121,7 +211,16 @@ impl Block {
 
 	//Returns hashes of the symbols on the top layer of coded Merkle tree 
 	//#[cfg(any(test, feature = "test-helpers"))]
-	pub fn coded_merkle_roots(&self, header_size: u32, rate: f32, codes: Vec<Code>, correct: Vec<bool>) -> (usize, Vec<H256>, Vec<Symbols>) {
+	pub fn coded_merkle_roots<R: Rng>(&self, header_size: u32, rate: f32, codes: Vec<Code>, correct: Vec<bool>, rng: &mut R) 
+	-> (usize, Vec<H256>, Vec<LayerArena>) {
+		let errors = errors_from_correct(&correct, &codes, rate);
+		self.coded_merkle_roots_with_errors(header_size, rate, AGGREGATE, codes, &errors, rng)
+	}
+
+	//Returns hashes of the symbols on the top layer of a coded Merkle tree corrupted as specified by errors
+	//Upper symbols aggregate the hashes of aggregate symbols
+	pub fn coded_merkle_roots_with_errors<R: Rng>(&self, header_size: u32, rate: f32, aggregate: usize, codes: Vec<Code>, 
+		errors: &[InjectedError], rng: &mut R) -> (usize, Vec<H256>, Vec<LayerArena>) {
 		//Convert transactions into bytes and concatenate them into a Vec<u8>
 		let mut trans_byte = self.transactions.iter().map(Transaction::bytes).collect::<Vec<Bytes>>();
 		let mut data: Vec<u8> = vec![];
@@ -133,9 +232,9 @@ impl Block {
 
 		//Append random data to meet target BLOCK_SIZE
 		if transactions_size_in_bytes < self.block_size_in_bytes {
+			let die = Uniform::from(0u8..=255u8);
 			for _ in 0..(self.block_size_in_bytes - transactions_size_in_bytes) {
-				let die = Uniform::from(0u8..=255u8);
-				let new_byte = die.sample(&mut rand::thread_rng());
+				let new_byte = die.sample(rng);
 				data.push(new_byte);
 			}
 		}
@@ -155,123 +254,119 @@ impl Block {
 				data.push(0x00);
 			}
 		} 
-		let k = data.len()/BASE_SYMBOL_SIZE;
-		let mut symbols: Vec<SymbolBase> = Vec::<SymbolBase>::with_capacity(k);
-		for l in 0..k {
-			let mut symbol: SymbolBase = [0x00; BASE_SYMBOL_SIZE];
-			symbol.copy_from_slice(&data[l * BASE_SYMBOL_SIZE .. (l + 1) * BASE_SYMBOL_SIZE]);
-			symbols.push(symbol);
-		}
+		// the padded data is the base layer of CMT as it is
+		let base = LayerArena::from_bytes(true, data).expect("the data is padded to whole base symbols");
 		// construct CMT and the root hashes
-		let (roots, tree) = coded_merkle_roots(&symbols, header_size, rate, codes, correct);
+		let (roots, tree) = coded_merkle_tree(base, header_size, rate, aggregate, codes, errors, &self.hash_function());
 		(original_size, roots, tree)
 	}
 
 	//Returns a Merkle proof for some symbol index at some level of the coded merkle tree
     //A proof for a particular symbol is a list of symbols in the upper levels
 	//#[cfg(any(test, feature = "test-helpers"))]
-	pub fn merkle_proof(&self, lvl: usize, index: u32) -> (Vec<SymbolUp>, Vec<u32>) {
-		//Construct the coded Merkle tree
-		//let header_size = self.block_header.coded_merkle_roots_hashes.len();
-		//let (_, _, tree) = self.coded_merkle_roots((header_size as u32), RATE, codes);
-        
-        // A proof is a vector of symbols on the upper layers of CMT, one from each layer
-		let mut proof = Vec::<SymbolUp>::new();
-		let mut proof_indices: Vec<u32> = vec![];
-		let mut moving_index = index;
-		let mut moving_k = 0;
-		let reduce_factor = ((AGGREGATE as f32) * RATE) as u32;
-		//match &tree[lvl] {
-		match &self.coded_tree[lvl] {
-			Symbols::Base(syms) => {
-				moving_k = ((syms.len() as f32) * RATE) as u32;
-			}
-			Symbols::Upper(syms) => {
-				moving_k = ((syms.len() as f32) * RATE) as u32;
-			}
-		}
-		// find the index of next symbol in the proof on the next layer of CMT
-		for i in lvl..(self.coded_tree.len() - 1) {
-			moving_index = next_index(moving_index, moving_k, reduce_factor);
-			proof_indices.push(moving_index.clone()); // add the index of a symbol in the proof
-            if let Symbols::Upper(syms) = &self.coded_tree[i + 1] {
-                proof.push(syms[(moving_index as usize)]); //add a new symbol to proof
-            }
-            moving_k = moving_k / reduce_factor;
-		}
-		(proof, proof_indices)
+	pub fn merkle_proof(&self, lvl: usize, index: u32) -> (Vec<&SymbolUp>, Vec<u32>) {
+        // A proof is a vector of symbols on the upper layers of CMT, one from each layer, viewed in the arenas
+		let proof_indices = self.layout().proof_indices(lvl as u32, index as u64);
+		let proof: Vec<&SymbolUp> = proof_indices.iter().enumerate()
+			.map(|(i, moving_index)| self.coded_tree[lvl + i + 1].hashes(*moving_index as usize).try_into()
+				.expect("an upper symbol has AGGREGATE hashes")).collect();
+		(proof, proof_indices.iter().map(|i| *i as u32).collect())
+	}
+
+	// geometry of the coded Merkle tree of the block, with the rate and aggregation factor committed by its header,
+	// or RATE and AGGREGATE if the header does not commit to a valid tree
+	pub fn layout(&self) -> CmtLayout {
+		CmtLayout::from_params(&self.block_header.cmt_params)
+			.unwrap_or_else(|| CmtLayout::from_lengths(self.coded_tree.iter().map(|layer| layer.len() as u64).collect()))
 	}
     
-    //take s random symbols from the base layer, and their Merkle proofs as symbols from other layers
-	pub fn sampling_to_decode(&self, s: u32) -> (Vec<Vec<Symbol>>, Vec<Vec<u64>>) {
-		let mut symbols_all_levels: Vec<Vec<Symbol>> = vec![];
-		let mut indices_all_levels: Vec<Vec<u64>> = vec![];
-		let reduce_factor = ((AGGREGATE as f32) * RATE) as u32;
-		let header_size = self.block_header.coded_merkle_roots_hashes.len();
-
-		if let Symbols::Base(syms) = &self.coded_tree[0] { // get the symbols on the base layer, syms is a vector of base symbols
-			let n = syms.len();
-
-            //Create random seed
-			let mut rng = rand::thread_rng();
-			//Create a random variable uniform between 0 to n-1
-			let die = Uniform::from(0..n);
-
-			let throw = die.sample(&mut rng); //sample a symbol index on the base layer
-			let (up_symbols, up_indices) = self.merkle_proof(0, throw as u32); //obtain symbols on the upper layers and their indices
-			symbols_all_levels.push(vec![Symbol::Base(syms[throw].clone())]);
-			indices_all_levels.push(vec![throw.clone() as u64]);
-             
-            // Each sample in the proof is a symbol for its own layer 
-            // To uniformly sample upper layer, we randomly choose the sampled symbol on the upper layer
-            // as either the proof itself or one of its parity sibling (chosen uniformly at random)
-			for j in 0..up_symbols.len() { 
-				if let Symbols::Upper(syms_up) = &self.coded_tree[j+1] {
-					let chosen_index = sample_parity_sibling(up_indices[j], syms_up.len() as u32, header_size as u32, reduce_factor);
-					let chosen_symbol = syms_up[chosen_index as usize]; //this symbols has type [H256; AGGREGATE]
-					//convert chosen_symbol to type "Symbol" 
-					let mut sym_byte = [0u8; 32 * AGGREGATE];
-					for t in 0..AGGREGATE {
-						let temp: [u8; 32] = chosen_symbol[t].clone().into();
-        		        sym_byte[t * 32 .. (t+1) * 32].copy_from_slice(&temp);
-        		    }
-        		    // push to symbols and indices
-					symbols_all_levels.push(vec![Symbol::Upper(sym_byte)]);
-			        indices_all_levels.push(vec![chosen_index as u64]);
-				}
-			}
+	// number of coded symbols on layer lvl of CMT
+	pub fn layer_length(&self, lvl: usize) -> usize {
+		self.coded_tree[lvl].len()
+	}
 
-			for i in 1..s { //sample s times with replacement uniformly at random
-			    let die = Uniform::from(0..n);
-				let throw = die.sample(&mut rand::thread_rng()); //sample a base index
-				let (up_symbols, up_indices) = self.merkle_proof(0, throw as u32);
+	// symbol with some index on layer lvl of CMT, converted to the type used by the decoder
+	pub fn tree_symbol(&self, lvl: usize, index: usize) -> Symbol {
+		self.coded_tree[lvl].to_symbol(index)
+	}
 
-				//push to base level if not seen before
-				if !indices_all_levels[0].contains(&(throw as u64)) {
-					symbols_all_levels[0].push(Symbol::Base(syms[throw].clone()));
-			        indices_all_levels[0].push(throw.clone() as u64);
-				}
+	// A symbol on the base layer, its Merkle proof and the inclusion proof of its top-layer symbol, as returned to a light client sampling it
+	pub fn base_sample(&self, index: usize) -> (SymbolBase, Vec<SymbolUp>, Vec<H256>) {
+		let (proof, _) = self.merkle_proof(0, index as u32);
+		(self.coded_tree[0].base_symbol(index), proof.into_iter().cloned().collect(), self.top_proof(0, index as u64))
+	}
 
-				//push to upper levels if not seen before
-				for j in 0..up_symbols.len() {
-					if let Symbols::Upper(syms_up) = &self.coded_tree[j+1] {
-						let chosen_index = sample_parity_sibling(up_indices[j], syms_up.len() as u32, header_size as u32, reduce_factor);
-						if !indices_all_levels[j+1].contains(&(chosen_index as u64)) {
-							let chosen_symbol = syms_up[chosen_index as usize]; //this symbols has type [H256; AGGREGATE]
-					        //convert chosen_symbol to type Symbol 
-					        let mut sym_byte = [0u8; 32 * AGGREGATE];
-			                for t in 0..AGGREGATE {
-				                let temp: [u8; 32] = chosen_symbol[t].clone().into();
-		                        sym_byte[t * 32 .. (t+1) * 32].copy_from_slice(&temp);
-		                    }
-		                    // push to symbols and indices
-			                symbols_all_levels[j+1].push(Symbol::Upper(sym_byte));
-	                        indices_all_levels[j+1].push(chosen_index as u64);
-	                    }
-			        }
-			    }
+	// base_sample as it is sent to a light client: the symbol, then the lists of its Merkle proof and inclusion proof
+	pub fn base_sample_bytes(&self, index: usize) -> Bytes {
+		let (proof, _) = self.merkle_proof(0, index as u32);
+		let mut stream = Stream::default();
+		stream
+			.append_slice(self.coded_tree[0].symbol(index))
+			.append_list::<SymbolUp, _>(&proof)
+			.append_list::<H256, _>(&self.top_proof(0, index as u64));
+		stream.out()
+	}
+
+	// Draw the indices of the symbols to sample on each layer of CMT
+	// Returned are all draws on each layer in the order they are made, repeated draws included
+	pub fn sample_indices<R: Rng>(&self, params: &SamplingParams, rng: &mut R) -> Vec<Vec<u64>> {
+		let height = self.coded_tree.len();
+		let mut draws: Vec<Vec<u64>> = vec![vec![]; height];
+		draws[0] = draw_indices(self.layer_length(0) as u64, params.base_samples, params.with_replacement, rng);
+
+		if params.layer_budgets.is_some() {
+			// each upper layer is sampled on its own with an explicit budget
+			for lvl in 1..height {
+				draws[lvl] = draw_indices(self.layer_length(lvl) as u64, params.budget(lvl).unwrap(), 
+					params.with_replacement, rng);
+			}
+		} else {
+			// Each sample in the proof is a symbol for its own layer 
+			// To uniformly sample upper layer, we randomly choose the sampled symbol on the upper layer
+			// as either the proof itself or one of its parity sibling (chosen uniformly at random)
+			let layout = self.layout();
+			for i in 0..draws[0].len() {
+				let up_indices = layout.proof_indices(0, draws[0][i]);
+				for j in 0..up_indices.len() {
+					let chosen_index = sample_parity_sibling(&layout, (j + 1) as u32, up_indices[j], rng);
+					draws[j + 1].push(chosen_index);
+				}
 			}
 		}
+		draws
+	}
+
+	// Obtain the symbols drawn on each layer of CMT, a symbol drawn more than once is only taken once
+	// The symbols are views into the layers of CMT, which the decoder reads without copying
+	pub fn collect_samples(&self, draws: &Vec<Vec<u64>>) -> (Vec<Vec<&[u8]>>, Vec<Vec<u64>>) {
+		let mut symbols_all_levels: Vec<Vec<&[u8]>> = vec![];
+		let mut indices_all_levels: Vec<Vec<u64>> = vec![];
+		for lvl in 0..draws.len() {
+			let indices = dedup_indices(&draws[lvl]);
+			symbols_all_levels.push(indices.iter().map(|i| self.coded_tree[lvl].symbol(*i as usize)).collect());
+			indices_all_levels.push(indices);
+		}
+		(symbols_all_levels, indices_all_levels)
+	}
+
+	// Sample symbols on all layers of CMT according to params
+	// Returned are the distinct sampled symbols and their indices on each layer, and the statistics of the run
+	pub fn sample<R: Rng>(&self, params: &SamplingParams, rng: &mut R) -> (Vec<Vec<&[u8]>>, Vec<Vec<u64>>, SamplingStats) {
+		let draws = self.sample_indices(params, rng);
+		let (symbols_all_levels, indices_all_levels) = self.collect_samples(&draws);
+		let mut stats = SamplingStats::from_layers(&self.layout(), &draws, &indices_all_levels);
+		// in single-root mode, a symbol on the top layer comes with its inclusion proof under the root
+		if self.block_header.single_root().is_some() {
+			let top = self.coded_tree.len() - 1;
+			stats.bytes_downloaded += (indices_all_levels[top].len() * 32 * top_proof_len(self.layer_length(top) as u64)) as u64;
+		}
+		(symbols_all_levels, indices_all_levels, stats)
+	}
+
+	//take s random symbols from the base layer, and their Merkle proofs as symbols from other layers
+	//the same rng state always produces the same samples
+	pub fn sampling_to_decode<R: Rng>(&self, s: u32, rng: &mut R) -> (Vec<Vec<&[u8]>>, Vec<Vec<u64>>) {
+		let (symbols_all_levels, indices_all_levels, _) = self.sample(&SamplingParams::with_replacement(s), rng);
 		(symbols_all_levels, indices_all_levels)
 	}
 
@@ -287,6 +382,196 @@ impl Block {
 	pub fn hash(&self) -> H256 {
 		self.block_header.hash()
 	}
+
+	// Append the block to a stream in the wire format, with the part of its coded tree given by tree
+	pub fn serialize_with_tree(&self, stream: &mut Stream, tree: TreeEncoding) {
+		stream
+			.append(&BLOCK_WIRE_VERSION)
+			.append(&self.block_header)
+			.append_list(&self.transactions)
+			.append(&(self.block_size_in_bytes as u64))
+			.append(&tree);
+		if tree == TreeEncoding::None {
+			return;
+		}
+		let layout = self.layout();
+		for (lvl, layer) in self.coded_tree.iter().enumerate() {
+			if tree == TreeEncoding::Parity && lvl > 0 {
+				stream.append_slice(&layer.as_bytes()[(layout.k(lvl as u32) as usize) * layer.symbol_size() ..]);
+			} else {
+				stream.append_slice(layer.as_bytes());
+			}
+		}
+	}
+
+	// Serialized block with the part of its coded tree given by tree
+	pub fn to_bytes(&self, tree: TreeEncoding) -> Bytes {
+		let mut stream = Stream::default();
+		self.serialize_with_tree(&mut stream, tree);
+		stream.out()
+	}
+}
+
+// A block is serialized with its full coded tree, if it has one
+impl Serializable for Block {
+	fn serialize(&self, stream: &mut Stream) {
+		let tree = if self.coded_tree.is_empty() { TreeEncoding::None } else { TreeEncoding::Full };
+		self.serialize_with_tree(stream, tree);
+	}
+}
+
+impl Deserializable for Block {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		let version: u8 = reader.read()?;
+		if version != BLOCK_WIRE_VERSION {
+			return Err(Error::MalformedData);
+		}
+		let block_header: BlockHeader = reader.read()?;
+		let transactions: Vec<Transaction> = reader.read_list()?;
+		let block_size: u64 = reader.read()?;
+		let tree: TreeEncoding = reader.read()?;
+
+		let mut coded_tree: Vec<LayerArena> = vec![];
+		if tree != TreeEncoding::None {
+			let layout = match block_header.layout() {
+				Some(layout) => layout,
+				None => return Err(Error::MalformedData),
+			};
+			for lvl in 0..(layout.height() as u32) {
+				// symbols are pushed as they are read, so that a forged layout does not allocate more than the data received
+				let mut layer = LayerArena::with_capacity(lvl == 0, 0);
+				if tree == TreeEncoding::Parity && lvl > 0 {
+					let below = &coded_tree[(lvl - 1) as usize];
+					for index in 0..layout.k(lvl) {
+						let mut symbol: Vec<u8> = Vec::with_capacity(layer.symbol_size());
+						for child in layout.children(lvl, index) {
+							symbol.extend_from_slice(&*block_header.hasher().hash(below.symbol(child as usize)));
+						}
+						layer.push(&symbol);
+					}
+				}
+				let mut symbol = vec![0u8; layer.symbol_size()];
+				while (layer.len() as u64) < layout.n(lvl) {
+					reader.read_slice(&mut symbol)?;
+					layer.push(&symbol);
+				}
+				coded_tree.push(layer);
+			}
+		}
+
+		Ok(Block {
+			block_header: block_header,
+			transactions: transactions,
+			coded_tree: coded_tree,
+			block_size_in_bytes: block_size as usize,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use ser::serialize;
+	use constants::AGGREGATE;
+	use codes::read_codes;
+	use fixtures::{codes_dir, header, transactions, test_block};
+	use super::*;
+
+	// Block padding and sampling only depend on the seed, so a run can be replayed
+	#[test]
+	fn test_block_sampling_is_replayable_from_seed() {
+		let (codes, _) = read_codes(&codes_dir(), vec![128, 64]);
+		let run = |seed: u64| {
+			let mut rng = StdRng::seed_from_u64(seed);
+			let block = Block::new(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes, vec![true; 2], &mut rng);
+			let (_, indices) = block.sampling_to_decode(100, &mut rng);
+			(block.block_header.coded_merkle_roots_hashes, indices)
+		};
+
+		assert!(run(7) == run(7));
+		assert!(run(7) != run(8));
+	}
+
+	#[test]
+	fn test_sampling_without_replacement_and_layer_budgets() {
+		let mut rng = StdRng::seed_from_u64(1);
+		let block = test_block(&mut rng);
+
+		// without replacement every draw on the base layer is a new symbol
+		let (symbols, indices, stats) = block.sample(&SamplingParams::without_replacement(300), &mut rng);
+		assert_eq!(stats.draws, vec![300, 300]);
+		assert_eq!(stats.unique_symbols[0], 300);
+		assert_eq!(indices[0].len(), 300);
+		assert_eq!(symbols[1].len() as u64, stats.unique_symbols[1]);
+		assert_eq!(stats.bytes_downloaded, 300 * BASE_SYMBOL_SIZE as u64 + stats.unique_symbols[1] * 32 * AGGREGATE as u64);
+
+		// the number of draws is capped by the number of symbols on a layer
+		let params = SamplingParams { base_samples: 600, with_replacement: false, layer_budgets: Some(vec![40]) };
+		let (_, indices, stats) = block.sample(&params, &mut rng);
+		assert_eq!(stats.draws, vec![512, 40]);
+		assert_eq!(stats.unique_symbols, vec![512, 40]);
+		assert_eq!(indices[0].len(), 512);
+
+		// with replacement, repeated draws are only downloaded once
+		let params = SamplingParams { base_samples: 600, with_replacement: true, layer_budgets: Some(vec![40]) };
+		let (_, indices, stats) = block.sample(&params, &mut rng);
+		assert_eq!(stats.draws, vec![600, 40]);
+		assert!(stats.unique_symbols[0] < 512);
+		assert_eq!(indices[0].len() as u64, stats.unique_symbols[0]);
+
+		// in single-root mode each distinct top-layer symbol also comes with its inclusion proof of 9 hashes
+		let block = block.with_single_root();
+		let (_, _, stats) = block.sample(&SamplingParams { layer_budgets: Some(vec![40]), ..SamplingParams::without_replacement(10) }, &mut rng);
+		assert_eq!(stats.bytes_downloaded, 10 * BASE_SYMBOL_SIZE as u64 + 40 * (32 * AGGREGATE as u64 + 9 * 32));
+	}
+
+	// A block and its coded tree are restored from the wire format, with the full tree or only the parity symbols of upper layers
+	#[test]
+	fn test_block_wire_format() {
+		let mut rng = StdRng::seed_from_u64(12);
+		let block = test_block(&mut rng);
+		let full = block.to_bytes(TreeEncoding::Full);
+		assert_eq!(full, serialize(&block));
+		assert_eq!(deserialize::<_, Block>(&full as &[u8]), Ok(block.clone()));
+
+		// the 64 systematic symbols of layer 1 are recomputed from the hashes of the base layer
+		let parity = block.to_bytes(TreeEncoding::Parity);
+		assert_eq!(parity.len() + 64 * 32 * AGGREGATE, full.len());
+		assert_eq!(deserialize::<_, Block>(&parity as &[u8]), Ok(block.clone()));
+
+		let light = deserialize::<_, Block>(&block.to_bytes(TreeEncoding::None) as &[u8]).unwrap();
+		assert!(light.coded_tree.is_empty());
+		assert_eq!((&light.block_header, &light.transactions), (&block.block_header, &block.transactions));
+		assert_eq!(serialize(&light), block.to_bytes(TreeEncoding::None));
+
+		// a header in single-root mode commits to the same layout
+		let single_root = block.with_single_root();
+		assert_eq!(deserialize::<_, Block>(&single_root.to_bytes(TreeEncoding::Parity) as &[u8]), Ok(single_root.clone()));
+
+		let block: Block = "02010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004000000050000000600000000000000000000000000000000000200004000000000000000".into();
+		assert_eq!((block.block_size_in_bytes, block.hash_function(), block.block_header.time), (16384, HashFunction::Blake2b, 4));
+	}
+
+	#[test]
+	fn test_malformed_block_wire_format() {
+		let mut rng = StdRng::seed_from_u64(13);
+		let block = test_block(&mut rng);
+		let mut bytes: Vec<u8> = block.to_bytes(TreeEncoding::Full).into();
+		assert_eq!(deserialize::<_, Block>(&bytes[..bytes.len() - 1]), Err(Error::UnexpectedEnd));
+		bytes[0] = BLOCK_WIRE_VERSION + 1;
+		assert_eq!(deserialize::<_, Block>(&bytes as &[u8]), Err(Error::MalformedData));
+
+		// a coded tree is only read with the layout committed by a valid header
+		let mut light = block.clone();
+		light.block_header.cmt_params.aggregate = 4;
+		let bytes = light.to_bytes(TreeEncoding::Full);
+		assert_eq!(deserialize::<_, Block>(&bytes as &[u8]), Err(Error::MalformedData));
+		let mut bytes: Vec<u8> = light.to_bytes(TreeEncoding::None).into();
+		let last = bytes.len() - 1;
+		bytes[last] = 3;
+		assert_eq!(deserialize::<_, Block>(&bytes as &[u8]), Err(Error::MalformedData));
+	}
 }
 
 // #[cfg(test)]
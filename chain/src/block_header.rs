@@ -1,16 +1,19 @@
-use std::fmt;
-use std::ops::BitXor;
+use std::{fmt, io};
+use std::borrow::Borrow;
 use hex::FromHex;
-use ser::{deserialize, serialize};
+use ser::{deserialize, serialize, Deserializable, Reader, Error};
 use crypto::dhash256;
+use cmt_hash::CmtHasher;
 use compact::Compact;
 use hash::H256;
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE};
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE, MAX_HEADER_HASHES};
 use {Symbols, SymbolBase, SymbolUp};
-use block::next_index;
-use CodingErr;
+use layout::{CmtLayout, CmtParams};
+use decoder::{Code, CodingErr, IncorrectCodingProof, IncorrectCodingProofRef, SymbolRef, up_to_bytes};
+use symbol_ops::{xor_into, is_zero};
+use top_root::{top_root, verify_top_proof};
 
-#[derive(PartialEq, Clone, Serializable, Deserializable)]
+#[derive(PartialEq, Clone, Serializable)]
 pub struct BlockHeader {
 	pub version: u32,
 	pub previous_header_hash: H256,
@@ -18,7 +21,10 @@ pub struct BlockHeader {
 	pub time: u32,
 	pub bits: Compact,
 	pub nonce: u32,
-	pub coded_merkle_roots_hashes: Vec<H256>,//hashes of the symbols on the top layer of coded Merkle tree
+	// hashes of the symbols on the top layer of coded Merkle tree
+	// or, in single-root mode, a single root over these hashes
+	pub coded_merkle_roots_hashes: Vec<H256>,
+	pub cmt_params: CmtParams, //parameters of the coded Merkle tree, from which its layout is derived
 }
 
 impl BlockHeader {
@@ -28,57 +34,109 @@ impl BlockHeader {
 		block_header_hash(self)
 	}
 
+	// Geometry of the coded Merkle tree committed by the header
+	// None if the parameters do not describe a valid tree with the symbol types of this crate, 
+	// or if the number of hashes in the header is neither the length of the top layer nor a single root
+	pub fn layout(&self) -> Option<CmtLayout> {
+		let params = &self.cmt_params;
+		if params.symbol_size as usize != BASE_SYMBOL_SIZE || params.aggregate as usize != AGGREGATE {
+			return None;
+		}
+		match CmtLayout::from_params(params) {
+			Some(layout) if layout.header_size() == self.coded_merkle_roots_hashes.len() as u64 => Some(layout),
+			Some(layout) if self.coded_merkle_roots_hashes.len() == 1 => Some(layout),
+			_ => None,
+		}
+	}
+
+	// Hash function of the coded Merkle tree committed by the header, with which all proofs against the header are verified
+	pub fn hasher(&self) -> &'static dyn CmtHasher {
+		self.cmt_params.hash_function.hasher()
+	}
+
+	// Root over the hashes of the top layer if the header is in single-root mode, i.e. it stores one hash for a top layer
+	// of several symbols (a valid top layer always has at least one systematic and one parity symbol)
+	pub fn single_root(&self) -> Option<H256> {
+		match self.cmt_params.layer_lengths.last() {
+			Some(n) if *n > 1 && self.coded_merkle_roots_hashes.len() == 1 => Some(self.coded_merkle_roots_hashes[0]),
+			_ => None,
+		}
+	}
+
+	// Hashes of the top layer: the hashes in the header, or in single-root mode the hashes served with the block,
+	// None if they do not match the root
+	pub fn top_hashes(&self, served: &[H256]) -> Option<Vec<H256>> {
+		let layout = match self.layout() {
+			Some(layout) => layout,
+			None => return None,
+		};
+		match self.single_root() {
+			Some(root) => {
+				if served.len() as u64 == layout.header_size() && top_root(self.hasher(), served) == root {
+					Some(served.to_vec())
+				} else {
+					None
+				}
+			},
+			None => Some(self.coded_merkle_roots_hashes.clone()),
+		}
+	}
+
+	// Hash committed for the symbol with some index on the top layer, None if it is not proven
+	// top_proof is the inclusion proof of the hash under the single root, and must be empty if the header stores all hashes
+	fn committed_top_hash(&self, layout: &CmtLayout, index: u64, top_proof: &[H256]) -> Option<H256> {
+		match self.single_root() {
+			Some(root) => verify_top_proof(self.hasher(), &root, index, layout.header_size(), top_proof),
+			None if top_proof.is_empty() => self.coded_merkle_roots_hashes.get(index as usize).cloned(),
+			None => None,
+		}
+	}
+
 	// Verify the Merkle proof of an upper symbol using the hashes in the block header
 	// Proof is a vector of symbols all the way to the top layer of CMT
+	// top_proof is the inclusion proof of the top-layer symbol of the proof in single-root mode, empty otherwise
 	// Return true if the symbol passes the Merkle proof check, false otherwise
 	//#[cfg(any(test, feature = "test-helpers"))]
-	pub fn verify_up(&self, symbol: SymbolUp, lvl: u32, index: u32, block_size: u32, proof: &Vec<SymbolUp>) -> bool {
-		let reduce_factor = ((AGGREGATE as f32) * RATE) as u32;
+	pub fn verify_up(&self, symbol: SymbolUp, lvl: u32, index: u32, proof: &[SymbolUp], top_proof: &[H256]) -> bool {
+		self.verify_up_ref(&symbol, lvl, index, proof, top_proof)
+	}
+
+	// verify_up for a symbol and a Merkle proof borrowed from a received buffer
+	pub fn verify_up_ref<P: Borrow<SymbolUp>>(&self, symbol: &SymbolUp, lvl: u32, index: u32, proof: &[P], 
+		top_proof: &[H256]) -> bool {
+		match self.layout() {
+			Some(layout) => self.verify_up_in(&layout, symbol, lvl, index as u64, proof, top_proof),
+			None => false,
+		}
+	}
+
+	fn verify_up_in<P: Borrow<SymbolUp>>(&self, layout: &CmtLayout, symbol: &SymbolUp, lvl: u32, index: u64, 
+		proof: &[P], top_proof: &[H256]) -> bool {
+		// a proof has one symbol on each layer above the symbol
+		if (lvl as usize) + proof.len() + 1 != layout.height() || index >= layout.n(lvl) {
+			println!("Failed at level {} with a proof that does not reach the top layer.", lvl);
+			return false;
+		}
 		let mut current_symbol = symbol;
 		let mut current_index = index;
 		let mut current_lvl = lvl;
-		let mut current_k = block_size / u32::pow(reduce_factor, lvl);
 
 		for s in proof.iter() {
+			let s: &SymbolUp = s.borrow();
 			// hash_index is the index of next hash to compare with in the next symbol in the proof
-			let mut hash_index = 0;
-
-			// current symbol is a systematic symbol
-			if current_index <= current_k - 1 {
-				hash_index = current_index % reduce_factor;
-			}
-			// current symbol is a parity symbol
-			else {
-				hash_index = (current_index - current_k) % ((AGGREGATE as u32) -
-                                                       reduce_factor) + reduce_factor;
-			}
-
-            //convert a symbol to a byte stream
-			let mut sym = [0u8; 32 * AGGREGATE];
-        	for j in 0..AGGREGATE {
-        		let temp: [u8; 32] = current_symbol[j].clone().into();
-        		sym[j * 32 .. (j+1) * 32].copy_from_slice(&temp);
-        	}
-
-			if dhash256(&sym) != s[(hash_index as usize)]{
+			let hash_index = layout.hash_slot(current_lvl, current_index);
+			if self.hasher().hash(&up_to_bytes(current_symbol)) != s[hash_index]{
 			    println!("Failed at level {} with symbol index {}.", current_lvl, current_index);
                 return false;
 			}
 			else {
-				current_symbol = *s;
-				current_index = next_index(current_index, current_k, reduce_factor);
-				current_k = current_k / reduce_factor;
+				current_symbol = s;
+				current_index = layout.parent(current_lvl, current_index);
 				current_lvl = current_lvl + 1;
 			}
 		}
 
-		let mut sym = [0u8; 32 * AGGREGATE];
-        for j in 0..AGGREGATE {
-        	let temp: [u8; 32] = current_symbol[j].clone().into();
-        	sym[j * 32 .. (j+1) * 32].copy_from_slice(&temp);
-        }
-
-        if dhash256(&sym) != self.coded_merkle_roots_hashes[(current_index as usize)] {
+        if self.committed_top_hash(layout, current_index, top_proof) != Some(self.hasher().hash(&up_to_bytes(current_symbol))) {
         	println!("Failed at level {} with symbol index {}.", current_lvl, current_index);
             false
         } else{
@@ -88,143 +146,272 @@ impl BlockHeader {
 
     // Verify the Merkle proof of a base symbol using the hashes in the block header
     //#[cfg(any(test, feature = "test-helpers"))]
-	pub fn verify_base(&self, symbol: SymbolBase, index: u32, block_size: u32, proof: &Vec<SymbolUp>) -> bool {
-		let reduce_factor = ((AGGREGATE as f32) * RATE) as u32;
-		let mut hash_index = 0;
-			// current base symbol is a systematic symbol
-			if index <= block_size - 1 {
-				hash_index = index % reduce_factor;
-			}
-			// current base symbol is a parity symbol
-			else {
-				hash_index = (index - block_size) % ((AGGREGATE as u32) -
-                                                       reduce_factor) + reduce_factor;
-			}
+	pub fn verify_base(&self, symbol: SymbolBase, index: u32, proof: &[SymbolUp], top_proof: &[H256]) -> bool {
+		self.verify_base_ref(&symbol, index, proof, top_proof)
+	}
 
-		if dhash256(&symbol) != proof[0][(hash_index as usize)]{
+	// verify_base for a symbol and a Merkle proof borrowed from a received buffer
+	pub fn verify_base_ref<P: Borrow<SymbolUp>>(&self, symbol: &SymbolBase, index: u32, proof: &[P], 
+		top_proof: &[H256]) -> bool {
+		let layout = match self.layout() {
+			Some(layout) => layout,
+			None => return false,
+		};
+		let index = index as u64;
+		if proof.len() + 1 != layout.height() || index >= layout.n(0) {
+			println!("Failed at base level with a proof that does not reach the top layer.");
+			return false;
+		}
+		// a single-layer CMT has the hashes of base symbols in the header
+		if proof.is_empty() {
+			return self.committed_top_hash(&layout, index, top_proof) == Some(self.hasher().hash(symbol));
+		}
+		if self.hasher().hash(symbol) != proof[0].borrow()[layout.hash_slot(0, index)]{
 			    println!("Failed at base level with symbol index {}.", index);
                 false
 			}
 			else {
-				self.verify_up(proof[0], 1, next_index(index, block_size, reduce_factor), 
-					block_size, &proof[1..].to_vec())
+				self.verify_up_in(&layout, proof[0].borrow(), 1, layout.parent(0, index), &proof[1..], top_proof)
 			}
 		}
 
+    // Hash of the symbol with some index on layer lvl committed by the CMT, read from the first symbol of its Merkle proof
+    // Returns None if the Merkle proof does not pass
+	fn committed_hash<P: Borrow<SymbolUp>>(&self, lvl: u32, index: u32, proof: &[P], top_proof: &[H256]) -> Option<H256> {
+		let layout = match self.layout() {
+			Some(layout) => layout,
+			None => return None,
+		};
+		let index = index as u64;
+		if (lvl as usize) + proof.len() + 1 != layout.height() || index >= layout.n(lvl) {
+			return None;
+		}
+		if proof.is_empty() {
+			return self.committed_top_hash(&layout, index, top_proof);
+		}
+		if !self.verify_up_in(&layout, proof[0].borrow(), lvl + 1, layout.parent(lvl, index), &proof[1..], top_proof) {
+			return None;
+		}
+		Some(proof[0].borrow()[layout.hash_slot(lvl, index)])
+	}
+
+    // Check that the symbols of an incorrect-coding proof are exactly the symbols in parity equation parity_index of code,
+    // so that a proof cannot be made up from arbitrary symbols of a correctly coded layer
+	fn check_parity_equation(code: &Code, parity_index: u64, lvl: u32, proof: &LayerSymbols, index: &[u32], 
+		merkle_proofs: &[Vec<&SymbolUp>], error_type: CodingErr) -> bool {
+		let parity = match code.parities.get(parity_index as usize) {
+			Some(parity) => parity,
+			None => {
+				println!("Invalid incorrect-coding proof. No parity equation #{} in the code.", parity_index);
+				return false;
+			},
+		};
+		let num_symbols = match *proof {
+			LayerSymbols::Base(ref syms) if lvl == 0 => syms.len(),
+			LayerSymbols::Upper(ref syms) if lvl > 0 => syms.len(),
+			_ => {
+				println!("Invalid incorrect-coding proof. Symbols do not belong to layer {}.", lvl);
+				return false;
+			},
+		};
+		// for NotHash error, the index of the to-be-decoded symbol is the last element of index
+		let num_indices = match error_type {
+			CodingErr::NotZero => num_symbols,
+			CodingErr::NotHash => num_symbols + 1,
+			CodingErr::Stopped => {
+				println!("Invalid incorrect-coding proof. A stopping set is not proved by a parity equation.");
+				return false;
+			},
+		};
+		if index.len() != num_indices || merkle_proofs.len() != num_indices {
+			println!("Invalid incorrect-coding proof. Numbers of symbols, indices and Merkle proofs do not match.");
+			return false;
+		}
+		if index.iter().any(|i| (*i as usize) >= code.symbols.len()) {
+			println!("Invalid incorrect-coding proof. Symbol index out of range.");
+			return false;
+		}
+		let mut claimed: Vec<u64> = index.iter().map(|i| *i as u64).collect();
+		let mut equation = parity.clone();
+		claimed.sort();
+		equation.sort();
+		if claimed != equation {
+			println!("Invalid incorrect-coding proof. Symbols do not form parity equation #{}.", parity_index);
+			return false;
+		}
+		true
+	}
+
     // This is a functionality at the light nodes
     // Verify that a malicious block producer does not do coding correctly, return true if the verification passes (or equivalently the coding is not done correct)
     // Proof is a set of symbols in the same layer that violate some encoding rules. merkle_proofs are their Merkle proofs
+    // code is the code of layer lvl, and the symbols must be exactly those of its parity equation parity_index
     // Error NotZero means all symbols of a parity equation does not sum up to zero. 
     // Error NotHash measn a symbol decoded from a parity equation does not match its hash.
-	pub fn verify_incorrect_coding(&self, proof: Symbols, lvl: u32, index: Vec<u32>, block_size: u32, merkle_proofs: Vec<Vec<SymbolUp>>, error_type: CodingErr) -> bool {
+	pub fn verify_incorrect_coding(&self, code: &Code, parity_index: u64, proof: Symbols, lvl: u32, index: Vec<u32>, 
+		merkle_proofs: Vec<Vec<SymbolUp>>, error_type: CodingErr) -> bool {
+		self.verify_incorrect_coding_with_top_proofs(code, parity_index, proof, lvl, index, merkle_proofs, vec![], error_type)
+	}
+
+    // Verify an incorrect-coding proof against a header in either mode
+    // In single-root mode, top_proofs are the inclusion proofs of the top-layer symbols of the Merkle proofs, one for each index
+    // If the header stores all hashes of the top layer, top_proofs must be empty
+	pub fn verify_incorrect_coding_with_top_proofs(&self, code: &Code, parity_index: u64, proof: Symbols, lvl: u32, 
+		index: Vec<u32>, merkle_proofs: Vec<Vec<SymbolUp>>, top_proofs: Vec<Vec<H256>>, error_type: CodingErr) -> bool {
+		let symbols = match proof {
+			Symbols::Base(ref syms) => LayerSymbols::Base(syms.iter().collect()),
+			Symbols::Upper(ref syms) => LayerSymbols::Upper(syms.iter().collect()),
+		};
+		let merkle_proofs: Vec<Vec<&SymbolUp>> = merkle_proofs.iter().map(|p| p.iter().collect()).collect();
+		let top_proofs: Vec<&[H256]> = top_proofs.iter().map(|p| &p[..]).collect();
+		self.verify_layer_coding(code, parity_index, symbols, lvl, &index, &merkle_proofs, &top_proofs, error_type)
+	}
+
+	// Verification of an incorrect-coding proof on symbols borrowed from an owned proof or from a received buffer
+	fn verify_layer_coding(&self, code: &Code, parity_index: u64, proof: LayerSymbols, lvl: u32, 
+		index: &[u32], merkle_proofs: &[Vec<&SymbolUp>], top_proofs: &[&[H256]], error_type: CodingErr) -> bool {
+		if !top_proofs.is_empty() && top_proofs.len() != index.len() {
+			println!("Invalid incorrect-coding proof. Numbers of indices and inclusion proofs do not match.");
+			return false;
+		}
+		// the code must be the code of layer lvl of the tree committed by the header
+		match self.layout() {
+			Some(ref layout) if (lvl as usize) < layout.height() && layout.n(lvl) == code.symbols.len() as u64 => {},
+			_ => {
+				println!("Invalid incorrect-coding proof. The code does not match layer {} of the tree.", lvl);
+				return false;
+			},
+		}
+		if !BlockHeader::check_parity_equation(code, parity_index, lvl, &proof, index, merkle_proofs, error_type) {
+			return false;
+		}
 		match proof {
-			Symbols::Base(err_symbols) => {
+			LayerSymbols::Base(err_symbols) => {
 				// first check the Merkle proofs of all symbols in the incorrect-coding proof
 				for i in 0..err_symbols.len() {
-					if !self.verify_base(err_symbols[i], index[i], block_size, &merkle_proofs[i]) {
+					if !self.verify_base_ref(err_symbols[i], index[i], &merkle_proofs[i], top_proof_of(top_proofs, i)) {
 						println!("Invalid incorrect-coding proof. Merkle proof of a symbol does not pass.");
 						return false;
 					}
 				}
 				match error_type {
-					NotZero => {
+					CodingErr::NotZero => {
 						let mut sum = [0u8; BASE_SYMBOL_SIZE];
-						for j in 0..BASE_SYMBOL_SIZE {
-							for i in 0..err_symbols.len() {
-								sum[j] = sum[j].bitxor(err_symbols[i][j]);
-							}
+						for i in 0..err_symbols.len() {
+							xor_into(&mut sum, err_symbols[i]);
 						}
-						for j in 0..BASE_SYMBOL_SIZE {
-							if sum[j] != 0u8 {
-								return true;
-							}
+						if !is_zero(&sum) {
+							return true;
 						}
 						println!("Invalid incorrect-coding proof. Symbols sum up to zero.");
 						return false;
 					},
-					NotHash => {
+					CodingErr::NotHash => {
 						let mut missing = [0u8; BASE_SYMBOL_SIZE];
-						for j in 0..BASE_SYMBOL_SIZE {
-							for i in 0..err_symbols.len() {
-								missing[j] = missing[j].bitxor(err_symbols[i][j]);
-							}
+						for i in 0..err_symbols.len() {
+							xor_into(&mut missing, err_symbols[i]);
 						}
-						// We put the index of the to-be-decoded symbol as the last element of index
-						// So for NotHash error, the size of index & merkle_proofs is one larger than the size of proof
-						if !self.verify_base(missing, index[index.len()-1], block_size, &merkle_proofs[merkle_proofs.len()-1]) {
-						    return true;
-						} else {
-							println!("Invalid incorrect-coding proof. Decoded symbol passes Merkle proof verification.");
-						    return false;
+						// The Merkle proof of the decoded symbol must pass up to its own hash, which the decoded symbol does not match
+						match self.committed_hash(0, index[index.len()-1], &merkle_proofs[merkle_proofs.len()-1], 
+							top_proof_of(top_proofs, index.len()-1)) {
+							Some(hash) if hash != self.hasher().hash(&missing) => return true,
+							Some(_) => println!("Invalid incorrect-coding proof. Decoded symbol passes Merkle proof verification."),
+							None => println!("Invalid incorrect-coding proof. Merkle proof of the decoded symbol does not pass."),
 						}
+						return false;
 					},
-					_ => {unreachable!();}
+					CodingErr::Stopped => false,
 				}
 			}
-			Symbols::Upper(err_symbols) => {
+			LayerSymbols::Upper(err_symbols) => {
 				// first check the Merkle proofs of all symbols in the incorrect-coding proof
 				for i in 0..err_symbols.len() {
-					if !self.verify_up(err_symbols[i], lvl, index[i], block_size, &merkle_proofs[i]) {
+					if !self.verify_up_ref(err_symbols[i], lvl, index[i], &merkle_proofs[i], top_proof_of(top_proofs, i)) {
 						println!("Invalid incorrect-coding proof. Merkle proof of a symbol does not pass.");
 						return false;
 					}
 				}
-				let symbol_size = 32 * AGGREGATE;
 				match error_type {
-					NotZero => {
+					CodingErr::NotZero => {
 						let mut sum = [0u8; 32 * AGGREGATE];
 						for i in 0..err_symbols.len() {
-							//Create a byte vector from an symbol on upper level
-							let mut sym = [0u8; 32 * AGGREGATE];
-							for k in 0..AGGREGATE {
-        	                    let temp: [u8; 32] = err_symbols[i][k].clone().into();
-        		                sym[k * 32 .. (k+1) * 32].copy_from_slice(&temp);
-        		            }
-							for j in 0..symbol_size {								
-								sum[j] = sum[j].bitxor(sym[j]);
-							}
+							xor_into(&mut sum, &up_to_bytes(err_symbols[i]));
 						}
-						for j in 0..symbol_size {
-							if sum[j] != 0u8 {
-								return true;
-							}
+						if !is_zero(&sum) {
+							return true;
 						}
 						println!("Invalid incorrect-coding proof. Symbols sum up to zero.");
 						return false;
 					}
-					NotHash => {
+					CodingErr::NotHash => {
 						let mut missing = [0u8; 32 * AGGREGATE];
 						for i in 0..err_symbols.len() {
-							//Create a byte vector from an symbol on upper level
-							let mut sym = [0u8; 32 * AGGREGATE];
-							for k in 0..AGGREGATE {
-        	                    let temp: [u8; 32] = err_symbols[i][k].clone().into();
-        		                sym[k * 32 .. (k+1) * 32].copy_from_slice(&temp);
-        		            }
-							for j in 0..symbol_size {								
-								missing[j] = missing[j].bitxor(sym[j]);
-							}
-						}
-
-						let mut decode = [H256::default(); AGGREGATE];
-						for k in 0..AGGREGATE {
-							let mut h = [0u8; 32];
-							h.copy_from_slice(&missing[k..k + 32]);
-							decode[k] = H256::from(h); 
+							xor_into(&mut missing, &up_to_bytes(err_symbols[i]));
 						}
 
 						// We put the index of the to-be-decoded symbol as the last element of index
-						if !self.verify_up(decode, lvl, index[index.len()-1], block_size, &merkle_proofs[merkle_proofs.len()-1]) {
-						    return true;
-						} else {
-							println!("Invalid incorrect-coding proof. Decoded symbol passes Merkle proof verification.");
-						    return false;
+						match self.committed_hash(lvl, index[index.len()-1], &merkle_proofs[merkle_proofs.len()-1], 
+							top_proof_of(top_proofs, index.len()-1)) {
+							Some(hash) if hash != self.hasher().hash(&missing) => return true,
+							Some(_) => println!("Invalid incorrect-coding proof. Decoded symbol passes Merkle proof verification."),
+							None => println!("Invalid incorrect-coding proof. Merkle proof of the decoded symbol does not pass."),
 						}
+						return false;
 					},
-					_ => {unreachable!();}
+					CodingErr::Stopped => false,
+				}
+			}
+		}
+	}
+
+    // Verify an incorrect-coding proof constructed by a full node, with codes the codes of all layers of CMT
+	pub fn verify_incorrect_coding_proof(&self, codes: &Vec<Code>, proof: &IncorrectCodingProof) -> bool {
+		self.verify_incorrect_coding_proof_ref(codes, &proof.as_borrowed())
+	}
+
+    // Verify an incorrect-coding proof read from a received buffer, without copying its symbols
+	pub fn verify_incorrect_coding_proof_ref(&self, codes: &[Code], proof: &IncorrectCodingProofRef) -> bool {
+		let code = match codes.get(proof.level as usize) {
+			Some(code) => code,
+			None => return false,
+		};
+		let symbols = match layer_symbols(proof.level, &proof.symbols) {
+			Some(symbols) => symbols,
+			None => return false,
+		};
+		if proof.indices.iter().any(|i| *i > (u32::max_value() as u64)) {
+			return false;
+		}
+		let mut merkle_proofs: Vec<Vec<&SymbolUp>> = vec![];
+		for p in proof.proofs.iter() {
+			let mut merkle_proof: Vec<&SymbolUp> = vec![];
+			for symbol in p.iter() {
+				match symbol.up() {
+					Some(sym) => merkle_proof.push(sym),
+					None => return false,
 				}
 			}
+			merkle_proofs.push(merkle_proof);
 		}
+		let index: Vec<u32> = proof.indices.iter().map(|i| *i as u32).collect();
+		self.verify_layer_coding(code, proof.parity_index, symbols, proof.level, &index, &merkle_proofs, &proof.top_proofs, 
+			proof.error_type)
+	}
+}
+
+// A header has at most MAX_HEADER_HASHES hashes
+impl Deserializable for BlockHeader {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		Ok(BlockHeader {
+			version: reader.read()?,
+			previous_header_hash: reader.read()?,
+			merkle_root_hash: reader.read()?,
+			time: reader.read()?,
+			bits: reader.read()?,
+			nonce: reader.read()?,
+			coded_merkle_roots_hashes: reader.read_list_max(MAX_HEADER_HASHES)?,
+			cmt_params: reader.read()?,
+		})
 	}
 }
 
@@ -238,6 +425,7 @@ impl fmt::Debug for BlockHeader {
 			.field("time", &self.time)
 			.field("bits", &self.bits)
 			.field("nonce", &self.nonce)
+			.field("cmt_params", &self.cmt_params)
 			//.field("coded_merkle_roots_hashes", &self.coded_merkle_roots_hashes.reversed())
 			.finish()
 	}
@@ -249,6 +437,40 @@ impl From<&'static str> for BlockHeader {
 	}
 }
 
+// inclusion proof of symbol i of an incorrect-coding proof, empty if the proof has none
+fn top_proof_of<'a>(top_proofs: &[&'a [H256]], i: usize) -> &'a [H256] {
+	top_proofs.get(i).cloned().unwrap_or(&[])
+}
+
+// Symbols of a layer in an incorrect-coding proof, borrowed from the proof
+enum LayerSymbols<'a> {
+	Base(Vec<&'a SymbolBase>),
+	Upper(Vec<&'a SymbolUp>),
+}
+
+// None if the symbols are not all base symbols on the base layer or all upper symbols on an upper layer
+fn layer_symbols<'a>(lvl: u32, symbols: &[SymbolRef<'a>]) -> Option<LayerSymbols<'a>> {
+	if lvl == 0 {
+		let mut base = vec![];
+		for symbol in symbols.iter() {
+			match *symbol {
+				SymbolRef::Base(sym) => base.push(sym),
+				_ => return None,
+			}
+		}
+		Some(LayerSymbols::Base(base))
+	} else {
+		let mut upper = vec![];
+		for symbol in symbols.iter() {
+			match symbol.up() {
+				Some(sym) => upper.push(sym),
+				None => return None,
+			}
+		}
+		Some(LayerSymbols::Upper(upper))
+	}
+}
+
 // Compute hash of the block header.
 pub(crate) fn block_header_hash(block_header: &BlockHeader) -> H256 {
 	dhash256(&serialize(block_header))
@@ -256,9 +478,20 @@ pub(crate) fn block_header_hash(block_header: &BlockHeader) -> H256 {
 
 #[cfg(test)]
 mod tests {
+	use std::convert::TryInto;
 	use ser::{Reader, Error as ReaderError, Stream};
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
 	//use super::BlockHeader;
 	use super::*;
+	use block::Block;
+	use fixtures::{codes_dir, header, transactions, test_block};
+	use test_helpers::{samples_without, decode_without};
+	use codes::{read_codes, shared_codes};
+	use decoder::{ErrorPattern, InjectedError, TreeDecoder};
+	use cmt_hash::HashFunction;
+	use cmt_hash::DoubleSha256;
+	use constants::MAX_CMT_LAYERS;
 
 	#[test]
 	fn test_block_header_stream() {
@@ -270,6 +503,7 @@ mod tests {
 			bits: 5.into(),
 			nonce: 6,
 			coded_merkle_roots_hashes: vec![H256::default(); 8],
+			cmt_params: CmtParams { base_k: 128, layer_lengths: vec![512, 256], symbol_size: 256, aggregate: 8, hash_function: HashFunction::DoubleSha256 },
 		};
 
 		let mut stream = Stream::default();
@@ -291,6 +525,11 @@ mod tests {
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+			128, 0, 0, 0,
+			2, 0, 2, 0, 0, 0, 1, 0, 0,
+			0, 1, 0, 0,
+			8, 0, 0, 0,
+			0,
 		].into();
 
 		assert_eq!(stream.out(), expected);
@@ -314,6 +553,11 @@ mod tests {
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 			0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+			128, 0, 0, 0,
+			2, 0, 2, 0, 0, 0, 1, 0, 0,
+			0, 1, 0, 0,
+			8, 0, 0, 0,
+			0,
 		];
 
 		let mut reader = Reader::new(&buffer);
@@ -326,9 +570,181 @@ mod tests {
 			bits: 5.into(),
 			nonce: 6,
 			coded_merkle_roots_hashes: vec![H256::default(); 8],
+			cmt_params: CmtParams { base_k: 128, layer_lengths: vec![512, 256], symbol_size: 256, aggregate: 8, hash_function: HashFunction::DoubleSha256 },
 		};
 
 		assert_eq!(expected, reader.read().unwrap());
 		assert_eq!(ReaderError::UnexpectedEnd, reader.read::<BlockHeader>().unwrap_err());
 	}
+
+	// Length prefixes beyond the protocol maxima are rejected before anything is allocated
+	#[test]
+	fn test_block_header_maxima() {
+		let mut block_header = header();
+		block_header.coded_merkle_roots_hashes = vec![H256::default(); MAX_HEADER_HASHES];
+		let bytes = serialize(&block_header);
+		assert_eq!(deserialize::<_, BlockHeader>(&bytes as &[u8]), Ok(block_header.clone()));
+
+		block_header.coded_merkle_roots_hashes.push(H256::default());
+		let bytes = serialize(&block_header);
+		assert_eq!(deserialize::<_, BlockHeader>(&bytes as &[u8]), Err(ReaderError::MalformedData));
+
+		// a header claiming a billion hashes, beyond the limits of the reader
+		let mut bytes = serialize(&header()).take()[0..80].to_vec();
+		bytes.extend_from_slice(&[0xfe, 0x00, 0xca, 0x9a, 0x3b]);
+		assert_eq!(deserialize::<_, BlockHeader>(&bytes as &[u8]), Err(ReaderError::LimitExceeded));
+
+		let mut block_header = header();
+		block_header.cmt_params.layer_lengths = vec![1; MAX_CMT_LAYERS + 1];
+		let bytes = serialize(&block_header);
+		assert_eq!(deserialize::<_, BlockHeader>(&bytes as &[u8]), Err(ReaderError::MalformedData));
+	}
+
+	// The layout of CMT is derived from the header alone, and changing it changes the hash of the header
+	#[test]
+	fn test_header_commits_to_layout() {
+		let mut rng = StdRng::seed_from_u64(9);
+		let block = test_block(&mut rng);
+		let header = block.block_header.clone();
+		assert_eq!(header.layout(), Some(block.layout()));
+		assert_eq!(header.layout().unwrap().k(0), 128);
+
+		let mut other = header.clone();
+		other.cmt_params.symbol_size = 512;
+		assert!(other.hash() != header.hash());
+		assert_eq!(other.layout(), None);
+		let (symbol, proof, top_proof) = block.base_sample(5);
+		assert!(!other.verify_base(symbol, 5, &proof, &top_proof));
+
+		// the number of hashes in the header must be the length of the top layer
+		let mut other = header.clone();
+		other.coded_merkle_roots_hashes.pop();
+		assert_eq!(other.layout(), None);
+	}
+
+	// In single-root mode the header stores one root, and the top-layer symbol of a Merkle proof comes with its inclusion proof
+	#[test]
+	fn test_single_root_header() {
+		let mut rng = StdRng::seed_from_u64(10);
+		let full = test_block(&mut rng);
+		let block = full.clone().with_single_root();
+		let header = &block.block_header;
+		let hashes = &full.block_header.coded_merkle_roots_hashes;
+		assert_eq!(header.single_root(), Some(top_root(&DoubleSha256, hashes)));
+		assert_eq!(full.block_header.single_root(), None);
+		assert_eq!(header.layout(), Some(block.layout()));
+		// 255 hashes fewer, and the number of hashes fits in one byte instead of three
+		assert_eq!(serialize(header).len() + 255 * 32 + 2, serialize(&full.block_header).len());
+
+		let (symbol, proof, top_proof) = block.base_sample(5);
+		assert_eq!(top_proof.len(), 9);
+		assert!(header.verify_base(symbol, 5, &proof, &top_proof));
+		assert!(!header.verify_base(symbol, 5, &proof, &[]));
+		assert!(!full.block_header.verify_base(symbol, 5, &proof, &top_proof));
+		// the inclusion proof of another top-layer symbol
+		let (_, _, other) = block.base_sample(200);
+		assert!(!header.verify_base(symbol, 5, &proof, &other));
+
+		// a symbol on the top layer has an empty Merkle proof
+		assert!(header.verify_up(block.coded_tree[1].hashes(7).try_into().unwrap(), 1, 7, &[], &block.top_proof(1, 7)));
+		assert!(!header.verify_up(block.coded_tree[1].hashes(7).try_into().unwrap(), 1, 8, &[], &block.top_proof(1, 7)));
+
+		// the hashes of the top layer are served with the block and checked against the root
+		assert_eq!(header.top_hashes(&block.top_hashes()), Some(hashes.clone()));
+		assert_eq!(full.block_header.top_hashes(&[]), Some(hashes.clone()));
+		let mut served = block.top_hashes();
+		served[3] = H256::default();
+		assert_eq!(header.top_hashes(&served), None);
+		assert_eq!(header.top_hashes(&served[1..]), None);
+	}
+
+	// A decoder working from the single root decodes the block, and its incorrect-coding proofs carry inclusion proofs
+	#[test]
+	fn test_decoding_from_single_root() {
+		let mut rng = StdRng::seed_from_u64(11);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes = shared_codes(&codes_for_decoding);
+
+		let block = test_block(&mut rng).with_single_root();
+		let block_header = &block.block_header;
+		assert!(TreeDecoder::from_header(&codes, block_header, &block.top_hashes()[1..]).is_none());
+		let mut decoder = TreeDecoder::from_header(&codes, block_header, &block.top_hashes()).unwrap();
+		let (symbols, indices) = samples_without(&block, Some((0, 5)));
+		assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+
+		for (layer, index, missing) in vec![(0, 0, None), (1, 100, Some((1, 100)))].into_iter() {
+			let errors = vec![InjectedError {layer: layer, index: index, pattern: ErrorPattern::FlipAll}];
+			let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+				&errors, &mut rng).with_single_root();
+			let block_header = &block.block_header;
+			let proof = match decode_without(&block, &codes_for_decoding, missing) {
+				Err(proof) => proof,
+				Ok(()) => panic!("incorrect coding is not detected"),
+			};
+			assert_eq!(proof.top_proofs.len(), proof.indices.len());
+			assert!(block_header.verify_incorrect_coding_proof(&codes_for_decoding, &proof));
+			let mut without_inclusion = proof.clone();
+			without_inclusion.top_proofs.clear();
+			assert!(!block_header.verify_incorrect_coding_proof(&codes_for_decoding, &without_inclusion));
+		}
+	}
+
+	#[test]
+	fn test_incorrect_coding_proofs_from_decoder_are_accepted() {
+		let mut rng = StdRng::seed_from_u64(7);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let cases = vec![(0, 0, None), (0, 0, Some((0, 0))), (1, 3, None), (1, 100, Some((1, 100)))];
+		for (layer, index, missing) in cases.into_iter() {
+			let errors = vec![InjectedError {layer: layer, index: index, pattern: ErrorPattern::FlipAll}];
+			let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+				&errors, &mut rng);
+			let proof = match decode_without(&block, &codes_for_decoding, missing) {
+				Err(proof) => proof,
+				Ok(()) => panic!("incorrect coding is not detected"),
+			};
+			assert!(block.block_header.verify_incorrect_coding_proof(&codes_for_decoding, &proof));
+		}
+	}
+
+	#[test]
+	fn test_forged_incorrect_coding_proofs_are_rejected() {
+		let mut rng = StdRng::seed_from_u64(8);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let header = &block.block_header;
+		let base = |indices: &Vec<u32>| {
+			let samples: Vec<(SymbolBase, Vec<SymbolUp>, Vec<H256>)> = indices.iter().map(|i| block.base_sample(*i as usize)).collect();
+			(Symbols::Base(samples.iter().map(|s| s.0).collect()), samples.iter().map(|s| s.1.clone()).collect::<Vec<_>>())
+		};
+
+		// symbols with valid Merkle proofs that do not sum up to zero, but are not a parity equation of the code
+		let indices = vec![0, 1, 2];
+		let (symbols, proofs) = base(&indices);
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols, 0, indices, proofs, CodingErr::NotZero));
+
+		// an actual parity equation of a correctly coded layer
+		let equation: Vec<u32> = codes[0].parities[0].iter().map(|i| *i as u32).collect();
+		let (symbols, proofs) = base(&equation);
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols, 0, equation.clone(), proofs, CodingErr::NotZero));
+
+		// malformed proofs are rejected without panicking
+		let (symbols, proofs) = base(&equation);
+		let p = codes[0].parities.len() as u64;
+		assert!(!header.verify_incorrect_coding(&codes[0], p, symbols.clone(), 0, equation.clone(), proofs.clone(), CodingErr::NotZero));
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols.clone(), 1, equation.clone(), proofs.clone(), CodingErr::NotZero));
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols.clone(), 0, equation.clone(), proofs.clone(), CodingErr::Stopped));
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols.clone(), 0, equation.clone(), proofs[1..].to_vec(), CodingErr::NotZero));
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols.clone(), 0, equation.clone(), proofs.clone(), CodingErr::NotHash));
+		let mut out_of_range = equation.clone();
+		out_of_range[0] = 100000;
+		assert!(!header.verify_incorrect_coding(&codes[0], 0, symbols, 0, out_of_range, proofs, CodingErr::NotZero));
+		assert!(!header.verify_base([0u8; BASE_SYMBOL_SIZE], 100000, &[], &[]));
+
+		// a Merkle proof longer than the tree is rejected
+		let (symbol, mut proof, _) = block.base_sample(5);
+		assert!(header.verify_base(symbol, 5, &proof, &[]));
+		let top = proof[0];
+		proof.push(top);
+		assert!(!header.verify_base(symbol, 5, &proof, &[]));
+	}
 }
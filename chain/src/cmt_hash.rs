@@ -0,0 +1,172 @@
+use std::io;
+use std::str::FromStr;
+use hash::H256;
+use ser::{Serializable, Deserializable, Stream, Reader, Error};
+use crypto::{dhash256, sha256, blake2b256};
+use decoder::Symbol;
+
+// Hash function of the coded Merkle tree
+// It hashes the coded symbols of every layer: the hashes of a layer form the systematic symbols of the next layer,
+// and the hashes of the top layer are stored in the block header. Hashing is the main cost of building the upper layers,
+// so the hash function is a parameter of the tree, which the encoder, the decoders and the verifiers must agree on.
+pub trait CmtHasher {
+	fn hash(&self, data: &[u8]) -> H256;
+
+	// hash of a coded symbol, None for an empty symbol
+	fn hash_symbol(&self, symbol: &Symbol) -> Option<H256> {
+		match *symbol {
+			Symbol::Base(ref sym) => Some(self.hash(sym)),
+			Symbol::Upper(ref sym) => Some(self.hash(sym)),
+			Symbol::Empty => None,
+		}
+	}
+}
+
+// SHA-256 applied twice, as for Bitcoin transactions and headers
+pub struct DoubleSha256;
+
+pub struct Sha256;
+
+// BLAKE2b with a 256-bit output
+pub struct Blake2b;
+
+impl CmtHasher for DoubleSha256 {
+	fn hash(&self, data: &[u8]) -> H256 {
+		dhash256(data)
+	}
+}
+
+impl CmtHasher for Sha256 {
+	fn hash(&self, data: &[u8]) -> H256 {
+		sha256(data)
+	}
+}
+
+impl CmtHasher for Blake2b {
+	fn hash(&self, data: &[u8]) -> H256 {
+		blake2b256(data)
+	}
+}
+
+// The hash function selected by the parameters of a tree
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashFunction {
+	DoubleSha256,
+	Sha256,
+	Blake2b,
+}
+
+impl Default for HashFunction {
+	fn default() -> Self {
+		HashFunction::DoubleSha256
+	}
+}
+
+impl HashFunction {
+	pub fn hasher(&self) -> &'static dyn CmtHasher {
+		match *self {
+			HashFunction::DoubleSha256 => &DoubleSha256,
+			HashFunction::Sha256 => &Sha256,
+			HashFunction::Blake2b => &Blake2b,
+		}
+	}
+}
+
+impl CmtHasher for HashFunction {
+	fn hash(&self, data: &[u8]) -> H256 {
+		self.hasher().hash(data)
+	}
+}
+
+impl FromStr for HashFunction {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"double-sha256" => Ok(HashFunction::DoubleSha256),
+			"sha256" => Ok(HashFunction::Sha256),
+			"blake2b" => Ok(HashFunction::Blake2b),
+			_ => Err(format!("unknown hash function {}", s)),
+		}
+	}
+}
+
+// A hash function is sent as one byte in the wire format of blocks
+impl Serializable for HashFunction {
+	fn serialize(&self, stream: &mut Stream) {
+		let code: u8 = match *self {
+			HashFunction::DoubleSha256 => 0,
+			HashFunction::Sha256 => 1,
+			HashFunction::Blake2b => 2,
+		};
+		stream.append(&code);
+	}
+}
+
+impl Deserializable for HashFunction {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		match reader.read::<u8>()? {
+			0 => Ok(HashFunction::DoubleSha256),
+			1 => Ok(HashFunction::Sha256),
+			2 => Ok(HashFunction::Blake2b),
+			_ => Err(Error::MalformedData),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use constants::BASE_SYMBOL_SIZE;
+	use block::Block;
+	use fixtures::{codes_dir, header, transactions};
+	use codes::{read_codes, shared_codes};
+	use decoder::{TreeDecoder, CodingErr};
+	use super::*;
+
+	#[test]
+	fn test_hash_functions() {
+		let data = [7u8; BASE_SYMBOL_SIZE];
+		assert_eq!(HashFunction::default().hash(&data), dhash256(&data));
+		assert_eq!(HashFunction::Sha256.hash(&data), sha256(&data));
+		assert_eq!(HashFunction::Blake2b.hash(&data), blake2b256(&data));
+		assert!(HashFunction::Blake2b.hash(&data) != HashFunction::DoubleSha256.hash(&data));
+		assert_eq!(HashFunction::Sha256.hash_symbol(&Symbol::Base(data)), Some(sha256(&data)));
+		assert_eq!(HashFunction::Sha256.hash_symbol(&Symbol::Empty), None);
+		assert_eq!("blake2b".parse::<HashFunction>(), Ok(HashFunction::Blake2b));
+		assert!("md5".parse::<HashFunction>().is_err());
+	}
+
+	// A tree hashed with BLAKE2b is decoded and its Merkle proofs pass under its header, but not once the header names double-SHA256
+	#[test]
+	fn test_tree_with_blake2b() {
+		let mut rng = StdRng::seed_from_u64(20);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let block = Block::new_with_hash_function(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+			&[], HashFunction::Blake2b, &mut rng);
+		let hashes = &block.block_header.coded_merkle_roots_hashes;
+		let reference = Block::new(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, vec![true; 2], 
+			&mut StdRng::seed_from_u64(20));
+		assert!(*hashes != reference.block_header.coded_merkle_roots_hashes);
+
+		let (symbol, proof, _) = block.base_sample(3);
+		assert!(block.block_header.verify_base(symbol, 3, &proof, &[]));
+		// the hash function is committed in the header, so relabelling it changes the header hash and fails the proofs
+		let mut relabelled = block.block_header.clone();
+		relabelled.cmt_params.hash_function = HashFunction::DoubleSha256;
+		assert!(relabelled.hash() != block.block_header.hash());
+		assert!(!relabelled.verify_base(symbol, 3, &proof, &[]));
+
+		let codes = shared_codes(&codes_for_decoding);
+		let received: Vec<Vec<u64>> = (0..2).map(|lvl| (0..block.layer_length(lvl) as u64).filter(|i| i % 4 != 1).collect()).collect();
+		let (symbols, indices) = block.collect_samples(&received);
+		let mut decoder = TreeDecoder::new(&codes, hashes).with_hash_function(HashFunction::Blake2b);
+		assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+		let mut decoder = TreeDecoder::new(&codes, hashes);
+		match decoder.run_tree_decoder(&symbols, &indices) {
+			Err(proof) => assert_eq!(proof.error_type, CodingErr::NotHash),
+			Ok(()) => panic!("symbols hashed with BLAKE2b do not match their double-SHA256 hashes"),
+		}
+	}
+}
@@ -1,88 +1,65 @@
-use crypto::dhash256;
+use std::io;
+use std::sync::Arc;
+use cmt_hash::{CmtHasher, DoubleSha256};
 use hash::H256;
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE};
-use decoder::{Symbol, Code, Decoder};
-use ser::{Serializable, Deserializable, deserialize, serialize};
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE};
+use arena::LayerArena;
+use layout::{CmtLayout, layer_lengths};
+use decoder::{Code, Decoder, InjectedError, ErrorPattern};
+use ser::{Serializable, Deserializable, Stream, Reader, Error};
 
 //define the data type for a symbol on the base layer
-//#[derive(Serializable)]
+//it is serialized as its bytes, without a length prefix
 pub type SymbolBase = [u8; BASE_SYMBOL_SIZE];
 
 //define the data type for a symbol on the upper layers
-//#[derive(Serializable)]
+//it is serialized as its AGGREGATE hashes, without a length prefix
 pub type SymbolUp = [H256; AGGREGATE];
 
 //define a new type for a vector of data symbols
-//This is the data structure used for each layer of coded Merkle tree
-#[derive(Clone)]
+//This is the data structure used for the symbols of incorrect-coding proofs, the layers of the tree are stored in a LayerArena
+#[derive(Debug, Clone, PartialEq)]
 pub enum Symbols{
 	Base(Vec<SymbolBase>),
 	Upper(Vec<SymbolUp>),
 } 
 
+//Symbols are sent as a tag byte, 0 for base symbols and 1 for upper symbols, followed by the list of symbols
+impl Serializable for Symbols {
+	fn serialize(&self, stream: &mut Stream) {
+		match *self {
+			Symbols::Base(ref syms) => stream.append(&0u8).append_list::<SymbolBase, _>(syms),
+			Symbols::Upper(ref syms) => stream.append(&1u8).append_list::<SymbolUp, _>(syms),
+		};
+	}
+}
 
-//Convert a data object with type Vec<Symbol> to an object with type Symbols
-//Vec<Symbol> is used for encoding/decoding, and Symbols is used for representing each layer of coded Merkle tree
-pub fn layer_to_layer_convert(symbols: Vec<Symbol>) -> Symbols {
-	match symbols[0] {
-		Symbol::Base(x) => {
-			let mut ss: Vec<SymbolBase> = vec![];
-			for i in 0..symbols.len() {
-				if let Symbol::Base(s) = symbols[i] {
-					ss.push(s);
-				}
-			} 
-			return Symbols::Base(ss);
-		},
-		Symbol::Upper(x) => {
-			let mut ss_up: Vec<SymbolUp> = vec![];
-			for j in 0..symbols.len() {
-				let mut up_hash = [H256::default(); AGGREGATE]; // A symbol value in the hash form
-				if let Symbol::Upper(s_up) = symbols[j] {
-					for t in 0..AGGREGATE {
-						let mut h = [0u8; 32];
-						h.copy_from_slice(&s_up[t*32..(t*32+32)]);
-						up_hash[t] = H256::from(h);
-					}
-				}
-				ss_up.push(up_hash);
-			}
-			return Symbols::Upper(ss_up);
-		},
-		_ => {unreachable!();}
+impl Deserializable for Symbols {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		match reader.read::<u8>()? {
+			0 => Ok(Symbols::Base(reader.read_list()?)),
+			1 => Ok(Symbols::Upper(reader.read_list()?)),
+			_ => Err(Error::MalformedData),
+		}
 	}
 }
 
+
 //Compute the hash of each symbol in a layer of CMT
-pub fn compute_hash(coded: &Symbols) -> Vec<H256> {
-	let mut roots = Vec::<H256>::new(); 
-	if let Symbols::Upper(layer) = coded {
-        for i in 0..layer.len(){
-        	let mut sym = [0u8; 32 * AGGREGATE];
-        	for j in 0..AGGREGATE {
-        		let temp: [u8; 32] = layer[i][j].clone().into();
-        		sym[j * 32 .. (j+1) * 32].copy_from_slice(&temp);
-        	}
-        	roots.push(dhash256(&sym));
-        }
-	} 
-	roots
+pub fn compute_hash(coded: &LayerArena, hasher: &dyn CmtHasher) -> Vec<H256> {
+	coded.iter().map(|sym| hasher.hash(sym)).collect()
 }
 
-//Add zero-valued symbols to the base layer of CMT, such that the last layer of CMT has exactly one symbol 
-fn pad(symbols: &[SymbolBase], rate: f32) -> Vec<SymbolBase> {
-	let mut data = symbols.to_vec();
-	let med = data.len() as f32;
+//Number of systematic symbols on the base layer of CMT after padding k symbols with zero-valued symbols,
+//such that the last layer of CMT has exactly one symbol 
+pub(crate) fn padded_length(k: usize, rate: f32, aggregate: usize) -> usize {
+	let med = k as f32;
 	let mut x = 1.0;
 	while x * rate < med {
-		x *= rate * (AGGREGATE as f32); 
+		x *= rate * (aggregate as f32); 
 	}
-	let difference = (x * rate - med) as u64;
-	for _i in 0..difference {
-		data.push([0x00;BASE_SYMBOL_SIZE]);        
-	}
-	data
+	let difference = (x * rate - med) as usize;
+	k + difference
 }
 
 // pub fn encoding(data: &Symbols, rate: f32) -> Symbols {
@@ -111,96 +88,106 @@ fn pad(symbols: &[SymbolBase], rate: f32) -> Vec<SymbolBase> {
 // }
 
 //Input: symbols on the lower layer of CMT
-//Output: new symbols constructed from the higher layer of CMT
+//Output: new systematic symbols constructed for the higher layer of CMT
 //This function computes the hashes of the symbols on the lower layer, interleave them, 
-//and aggregate them into new symbols on the upper layer  
-fn hash_aggregate(coded: &Symbols, rate: f32) -> Symbols{
-	let mut hashes = Vec::<H256>::new();
-	match coded {
-		Symbols::Base(message) => {
-			for i in 0..message.len() {
-				hashes.push(dhash256(&message[i]));
-			}
-		},
-		Symbols::Upper(_message) => {
-			// for i in 0..message.len() {
-			// 	hashes.push(dhash256(&message[i]));
-			// }
-			hashes = compute_hash(&coded);
-		},
-	}
+//and aggregate them into new symbols on the upper layer, written directly into the arena that the encoder extends
+//Each new symbol aggregates the hashes of aggregate symbols on the lower layer
+fn hash_aggregate(coded: &LayerArena, rate: f32, aggregate: usize, hasher: &dyn CmtHasher) -> LayerArena {
+	let hashes = compute_hash(coded, hasher);
     // n is numbe of coded symbols/hashes
 	let n = hashes.len();
 	// k is the number of new symbols after aggregation 
-	let k = (n/AGGREGATE) as u32;
+	let k = (n/aggregate) as u32;
 
-	let mut new_data = Vec::with_capacity(k as usize);
+	// with room for the parity symbols that the encoder appends
+	let mut new_data = LayerArena::with_symbol_size(false, 32 * aggregate, ((k as f32) / rate) as usize);
+	new_data.resize(k as usize);
     //hash interleaving
 	for i in 0..k {
-		let mut new_symbol: SymbolUp = [H256::default(); AGGREGATE];
-		for j in 0..(((AGGREGATE as f32) * rate) as usize){
-			let index  = ((((i * (AGGREGATE as u32)) as f32) * rate) as u32) + (j as u32);
-			new_symbol[j] = hashes[index as usize].clone();
+		let new_symbol = new_data.symbol_mut(i as usize);
+		for j in 0..(((aggregate as f32) * rate) as usize){
+			let index  = ((((i * (aggregate as u32)) as f32) * rate) as u32) + (j as u32);
+			new_symbol[j * 32 .. (j + 1) * 32].copy_from_slice(&hashes[index as usize][..]);
 		}
-		for k in 0..(((AGGREGATE as f32) * (1.0 - rate)) as usize){
-			let index = (((n as f32) * rate + (i as f32) * (AGGREGATE as f32) * (1.0 - rate)) 
+		for k in 0..(((aggregate as f32) * (1.0 - rate)) as usize){
+			let index = (((n as f32) * rate + (i as f32) * (aggregate as f32) * (1.0 - rate)) 
 				as u32) + (k as u32);
-			new_symbol[(((AGGREGATE as f32) * rate) as usize) + k] = hashes[index as usize].clone();
+			let j = (((aggregate as f32) * rate) as usize) + k;
+			new_symbol[j * 32 .. (j + 1) * 32].copy_from_slice(&hashes[index as usize][..]);
 		}
-		new_data.push(new_symbol);
 	}
-	Symbols::Upper(new_data)
+	new_data
 }
 
 //Calculates the roots of the coded Merkle tree
 //symbols are the original block, header_size indicates # of hashes in the header 
 //the variable correct indicates whether the coding is done correctly or incorrectly by malicious block producer
 //Output is the root hashes, and the entire CMT, where each element represents a layer of the tree
+//Symbols are hashed with double-SHA256
 pub fn coded_merkle_roots(symbols: &[SymbolBase], header_size: u32, rate: f32, codes: Vec<Code>, correct: Vec<bool>) 
--> (Vec<H256>, Vec<Symbols>) { 
-    let data = pad(symbols, rate);
-    let n = ((data.len() as f32) / rate) as u32;
-    let level = ((((n/header_size) as f32).log2()/(rate * (AGGREGATE as f32)).log2()) as u32) + 1;
+-> (Vec<H256>, Vec<LayerArena>) { 
+    let errors = errors_from_correct(&correct, &codes, rate);
+    coded_merkle_roots_with_errors(symbols, header_size, rate, codes, &errors, &DoubleSha256)
+}
 
-    //Coded merkle tree is a vector of symbols on each layer
-    let mut tree: Vec<Symbols> = Vec::with_capacity(level as usize); 
+//Errors injected on the layers of CMT that are not coded correctly: the bits of the 1st parity symbol are flipped
+pub fn errors_from_correct(correct: &Vec<bool>, codes: &Vec<Code>, rate: f32) -> Vec<InjectedError> {
+    let mut errors = vec![];
+    for i in 0..correct.len() {
+    	if !correct[i] {
+    		let k = ((codes[i].symbols.len() as f32) * rate) as u64;
+    		errors.push(InjectedError {layer: i as u32, index: k, pattern: ErrorPattern::FlipAll});
+    	}
+    }
+    errors
+}
+
+//Calculates the roots of a coded Merkle tree, whose coded symbols are corrupted as specified by errors after encoding
+//Every layer above a corrupted symbol commits to its corrupted value, symbols are hashed with hasher
+pub fn coded_merkle_roots_with_errors(symbols: &[SymbolBase], header_size: u32, rate: f32, codes: Vec<Code>, errors: &[InjectedError], 
+	hasher: &dyn CmtHasher) 
+-> (Vec<H256>, Vec<LayerArena>) { 
+    let mut base = LayerArena::with_capacity(true, ((padded_length(symbols.len(), rate, AGGREGATE) as f32) / rate) as usize);
+    for symbol in symbols.iter() {
+    	base.push(symbol);
+    }
+    coded_merkle_tree(base, header_size, rate, AGGREGATE, codes, errors, hasher)
+}
+
+//Calculates the roots of a coded Merkle tree from the systematic symbols of its base layer, stored in an arena
+//Each layer is encoded in place: the arena of its systematic symbols is extended with the parity symbols
+//The symbols of an upper layer aggregate the hashes of aggregate symbols, and the layers have the layout of CmtLayout::with_coding
+pub fn coded_merkle_tree(base: LayerArena, header_size: u32, rate: f32, aggregate: usize, codes: Vec<Code>, errors: &[InjectedError], 
+	hasher: &dyn CmtHasher) 
+-> (Vec<H256>, Vec<LayerArena>) { 
+    //Add zero-valued symbols to the base layer of CMT, such that the last layer of CMT has exactly one symbol 
+    let mut data = base;
+    let k = padded_length(data.len(), rate, aggregate);
+    data.resize(k);
+    let reduce_factor = ((aggregate as f32) * rate) as u64;
+    let layout = CmtLayout::from_coded_lengths(layer_lengths(k as u64, header_size as u64, rate, reduce_factor), rate, aggregate as u64);
+    let level = layout.height() as u32;
+
+    //Coded merkle tree is a vector of layers, each stored in a contiguous arena
+    let mut tree: Vec<LayerArena> = Vec::with_capacity(level as usize); 
 
     // Construct the base layer
     // Initialize encoder for base layer
     // Here encoder is implemented using peeling decoder
-    let mut base_layer: Decoder = Decoder::new(0 as u32, codes[0].parities.to_vec(), codes[0].symbols.to_vec());
-    //Perform encoding operation
-    let mut sys_symbols_base: Vec<Symbol> = vec![];
-    for j in 0..data.len() {
-    	sys_symbols_base.push(Symbol::Base(data[j]));
-    }
-    //Construct base layer
-    tree.push(layer_to_layer_convert(base_layer.encode(sys_symbols_base, correct[0])));
+    let mut base_layer: Decoder = Decoder::new_with_layout(0 as u32, Arc::new(codes[0].clone()), &layout);
+    //Perform encoding operation, which appends the parity symbols to the systematic symbols
+    tree.push(base_layer.encode_with_errors(data, errors));
 
     // Construct upper layers
     for i in 0..(level-1) {
     	//Construct the systematic data for level i by aggregating the hashes of the coded data on level i-1
-    	let new_data: Symbols = hash_aggregate(&tree[i as usize], rate); // data type is Symbols::Upper(Vec<SymbolUp>)
+    	let sys_symbols_upper: LayerArena = hash_aggregate(&tree[i as usize], rate, aggregate, hasher);
     	// Initialize encoder for layer i+1
-        let mut upper_layer: Decoder = Decoder::new((i+1) as u32, 
-        	codes[(i+1) as usize].parities.to_vec(), codes[(i+1) as usize].symbols.to_vec());
-        
-        let mut sys_symbols_upper: Vec<Symbol> = vec![];
-        //Convert new_data to Vec<Symbol> for encoder
-        if let Symbols::Upper(ss) = new_data {
-        	for t in 0..ss.len() {
-        		let mut upp_sym: [u8; 32 * AGGREGATE] = [0u8; 32 * AGGREGATE];
-        		for l in 0..AGGREGATE {
-        			let temp: [u8; 32] = ss[t][l].into();
-        			upp_sym[l*32..(l*32+32)].copy_from_slice(&temp);
-        		}
-        		sys_symbols_upper.push(Symbol::Upper(upp_sym));
-        	}
-        }
-        //Encode and convert back to Symbols::Upper(Vec<SymbolUp>)
-    	tree.push(layer_to_layer_convert(upper_layer.encode(sys_symbols_upper, correct[(i+1) as usize])));
+        let mut upper_layer: Decoder = Decoder::new_with_layout((i+1) as u32, Arc::new(codes[(i+1) as usize].clone()), &layout);
+        //Encode and store the coded symbols of layer i+1
+    	tree.push(upper_layer.encode_with_errors(sys_symbols_upper, errors));
     }
-    (compute_hash(&tree[tree.len()-1]), tree)
+    (compute_hash(&tree[tree.len()-1], hasher), tree)
 }
 
 // #[cfg(test)]
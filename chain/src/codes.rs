@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use rand::Rng;
+use rand::seq::SliceRandom;
+use constants::RATE;
+use decoder::Code;
+
+// obtain a code represented by symbols from the form represented by parities
+pub fn convert_parity_to_symbol(parities: Vec<Vec<u64>>, n: u64) -> Vec<Vec<u64>> {
+	let mut symbols: Vec<Vec<u64>> = vec![vec![];n as usize];
+	for i in 0..parities.len(){
+		let parity = &parities[i];
+		for s in parity.iter() {
+			symbols[*s as usize].push(i as u64);
+		}
+	}
+	symbols
+}
+
+// read the parity equations in a code file, each line represents a parity equation
+fn read_parities(filename: String) -> Vec<Vec<u64>> {
+	// Open the file in read-only mode (ignoring errors).
+	let file = File::open(filename).unwrap();
+	let reader = BufReader::new(file);
+
+	let mut parities: Vec<Vec<u64>> = vec![];
+	for line in reader.lines() {
+		let line = line.unwrap(); // Ignore errors.
+		let parity: Vec<u64> = line.split_whitespace().map(|s| s.parse().unwrap()).collect();
+		parities.push(parity);
+	}
+	parities
+}
+
+// Read the encoding and decoding codes with k systematic symbols from the folder dir
+// Generate one code for encoding, and one code for decoding
+pub fn read_code_from_file(dir: &str, k: u64) -> (Code, Code) {
+	//compute number of coded symbols
+	let n = ((k as f32) / RATE ) as u64;
+
+	//parity equations for encoding
+	let parities_encoding = read_parities(format!("{}/k={}_encode.txt", dir, k));
+	//parity equations for decoding
+	let parities_decoding = read_parities(format!("{}/k={}_decode.txt", dir, k));
+
+	(Code {parities: parities_encoding.clone(), symbols: convert_parity_to_symbol(parities_encoding, n)},
+		Code {parities: parities_decoding.clone(), symbols: convert_parity_to_symbol(parities_decoding, n)})
+}
+
+// The first code file missing from the folder dir for the codes with k systematic symbols in k_set, if any
+pub fn missing_code_file(dir: &str, k_set: &[u64]) -> Option<String> {
+	for k in k_set.iter() {
+		for kind in ["encode", "decode"].iter() {
+			let filename = format!("{}/k={}_{}.txt", dir, k, kind);
+			if !Path::new(&filename).is_file() {
+				return Some(filename);
+			}
+		}
+	}
+	None
+}
+
+//Read all codes for all coded Merkle tree layers
+pub fn read_codes(dir: &str, k_set: Vec<u64>) -> (Vec<Code>, Vec<Code>) {
+	let mut codes_for_encoding: Vec<Code> = vec![];
+	let mut codes_for_decoding: Vec<Code> = vec![];
+	for i in k_set.iter() {
+		let (code_e, code_d) = read_code_from_file(dir, *i);
+		codes_for_encoding.push(code_e);
+		codes_for_decoding.push(code_d);
+	}
+	(codes_for_encoding, codes_for_decoding)
+}
+
+// Read the codes of the layers with k systematic symbols in k_set from the folder dir, for a tree of another rate,
+// e.g. in a sweep over rates. Code files only name k, so the folder must hold codes of this rate
+// Fails if a code file is missing, or if a code does not have k / rate coded symbols
+pub fn read_codes_of_rate(dir: &str, k_set: &[u64], rate: f32) -> Result<(Vec<Code>, Vec<Code>), String> {
+	if let Some(filename) = missing_code_file(dir, k_set) {
+		return Err(format!("missing {}", filename));
+	}
+	let mut codes_for_encoding: Vec<Code> = vec![];
+	let mut codes_for_decoding: Vec<Code> = vec![];
+	for k in k_set.iter() {
+		let n = ((*k as f32) / rate) as u64;
+		for kind in ["encode", "decode"].iter() {
+			let filename = format!("{}/k={}_{}.txt", dir, k, kind);
+			let parities = read_parities(filename.clone());
+			// the last coded symbol is in some parity equation of a code of this length
+			if n == 0 || parities.iter().flat_map(|parity| parity.iter()).max() != Some(&(n - 1)) {
+				return Err(format!("{} is not a code of rate {}", filename, rate));
+			}
+			let code = Code {parities: parities.clone(), symbols: convert_parity_to_symbol(parities, n)};
+			if *kind == "encode" { codes_for_encoding.push(code) } else { codes_for_decoding.push(code) }
+		}
+	}
+	Ok((codes_for_encoding, codes_for_decoding))
+}
+
+// Put codes behind Arc, so that decoders share them instead of copying the graphs
+pub fn shared_codes(codes: &Vec<Code>) -> Vec<Arc<Code>> {
+	codes.iter().map(|code| Arc::new(code.clone())).collect()
+}
+
+// Generate a random LDPC code for decoding with k systematic symbols, as generate_random_LDPC.cpp does
+// Every coded symbol is in symbol_degree parity equations and every parity equation has parity_degree symbols,
+// the edges between them are a random permutation, and a symbol connected twice to a parity equation is removed from it
+// Returns None if the numbers of edges from the symbols and from the parity equations cannot be equal
+pub fn random_code<R: Rng>(k: u64, symbol_degree: u64, parity_degree: u64, rng: &mut R) -> Option<Code> {
+	let n = ((k as f32) / RATE ) as u64;
+	let edges = n * symbol_degree;
+	if parity_degree == 0 || edges % parity_degree != 0 {
+		return None;
+	}
+	let mut perm: Vec<u64> = (0..edges).collect();
+	perm.shuffle(rng);
+
+	let mut parities: Vec<Vec<u64>> = vec![vec![]; (edges / parity_degree) as usize];
+	for (i, e) in perm.iter().enumerate() {
+		parities[i / (parity_degree as usize)].push(e / symbol_degree);
+	}
+	for parity in parities.iter_mut() {
+		parity.sort();
+		let mut reduced: Vec<u64> = vec![];
+		for s in parity.iter() {
+			if reduced.last() == Some(s) {
+				reduced.pop();
+			} else {
+				reduced.push(*s);
+			}
+		}
+		*parity = reduced;
+	}
+	Some(Code {parities: parities.clone(), symbols: convert_parity_to_symbol(parities, n)})
+}
+
+// Write the parity equations of a code to a file, in the format of the code files: one parity equation per line
+pub fn write_code_to_file(code: &Code, filename: &str) -> io::Result<()> {
+	let mut writer = BufWriter::new(File::create(filename)?);
+	for parity in code.parities.iter() {
+		let line: Vec<String> = parity.iter().map(|s| s.to_string()).collect();
+		writeln!(writer, "{}", line.join(" "))?;
+	}
+	writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use super::*;
+
+	#[test]
+	fn test_random_code() {
+		let mut rng = StdRng::seed_from_u64(1);
+		assert!(random_code(64, 6, 7, &mut rng).is_none());
+
+		let code = random_code(64, 6, 8, &mut rng).unwrap();
+		assert_eq!(code.symbols.len(), 256);
+		assert_eq!(code.parities.len(), 192);
+		for parity in code.parities.iter() {
+			assert!(parity.len() <= 8 && parity.len() % 2 == 0);
+			assert!(parity.windows(2).all(|w| w[0] < w[1]));
+		}
+		assert!(code.symbols.iter().all(|parities| parities.len() <= 6));
+
+		// the code files are read back as the same code
+		let filename = env::temp_dir().join("cmt_random_code_decode.txt");
+		let filename = filename.to_str().unwrap();
+		write_code_to_file(&code, filename).unwrap();
+		assert_eq!(read_parities(filename.to_string()), code.parities);
+	}
+
+	#[test]
+	fn test_missing_code_file() {
+		let dir = format!("{}/src/LDPC_codes", env!("CARGO_MANIFEST_DIR"));
+		assert_eq!(missing_code_file(&dir, &[512, 256, 128, 64]), None);
+		assert_eq!(missing_code_file(&dir, &[512, 1024]), Some(format!("{}/k=1024_encode.txt", dir)));
+	}
+
+	#[test]
+	fn test_read_codes_of_rate() {
+		let dir = format!("{}/src/LDPC_codes", env!("CARGO_MANIFEST_DIR"));
+		assert_eq!(read_codes_of_rate(&dir, &[128, 64], RATE), Ok(read_codes(&dir, vec![128, 64])));
+		assert_eq!(read_codes_of_rate(&dir, &[128], 0.5), Err(format!("{}/k=128_encode.txt is not a code of rate 0.5", dir)));
+		assert_eq!(read_codes_of_rate(&dir, &[128], 0.125), Err(format!("{}/k=128_encode.txt is not a code of rate 0.125", dir)));
+		assert!(read_codes_of_rate(&dir, &[1024], RATE).unwrap_err().contains("missing"));
+	}
+}
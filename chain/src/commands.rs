@@ -0,0 +1,365 @@
+//Subcommands of the chain binary
+//Blocks, samples, incorrect-coding proofs and codes are read from and written to files in the wire format,
+//so that the steps of a run can be replayed and inspected separately: encode or inject-error, then sample, decode and verify-proof
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use clap::ArgMatches;
+use hex::FromHex;
+use rand::Rng;
+use ser::{deserialize, deserialize_borrowed, serialize, Serializable};
+
+use chain::block_header::BlockHeader;
+use chain::transaction::Transaction;
+use chain::block::{Block, TreeEncoding};
+use chain::constants::{BLOCK_SIZE, HEADER_SIZE, RATE, AGGREGATE, SAMPLE_COMPLEXITY};
+use chain::cmt_hash::HashFunction;
+use chain::layout::CmtLayout;
+use chain::decoder::{Code, TreeDecoder, CodingErr, ErrorPattern, InjectedError, IncorrectCodingProofRef};
+use chain::codes::{read_codes, shared_codes, random_code, write_code_to_file, missing_code_file};
+use chain::inactivation::DecodingMode;
+use chain::sampling::{SamplingParams, Samples};
+use chain::simulation::find_stopping_set;
+use chain::sweep::{SweepParams, run_sweep, write_csv, write_json};
+use chain::import::{ImportedBlock, BlkFileReader, ImportParams, ImportError, report_block};
+
+use super::{CODES_DIR, REFERENCE_TRANSACTION, reference_header, reference_transactions, decode_observer, simulate};
+
+pub fn run<R: Rng>(name: &str, args: &ArgMatches, rng: &mut R) {
+	match name {
+		"encode" => encode(args, false, rng),
+		"inject-error" => encode(args, true, rng),
+		"sample" => sample(args, rng),
+		"decode" => decode(args),
+		"verify-proof" => verify_proof(args),
+		"gen-code" => gen_code(args, rng),
+		"simulate" => simulate_command(args, rng),
+		"sweep" => sweep(args, rng),
+		"import" => import(args, rng),
+		_ => unreachable!(),
+	}
+}
+
+//Print an error and exit, for invalid inputs of a command
+fn fail(message: String) -> ! {
+	eprintln!("error: {}", message);
+	process::exit(1)
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+	fs::read(path).unwrap_or_else(|e| fail(format!("cannot read {}: {}", path, e)))
+}
+
+fn write_file(path: &str, data: &[u8]) {
+	fs::write(path, data).unwrap_or_else(|e| fail(format!("cannot write {}: {}", path, e)))
+}
+
+fn write_results<F: FnOnce(&mut BufWriter<File>) -> io::Result<()>>(path: &str, write: F) {
+	let file = File::create(path).unwrap_or_else(|e| fail(format!("cannot create {}: {}", path, e)));
+	write(&mut BufWriter::new(file)).unwrap_or_else(|e| fail(format!("cannot write {}: {}", path, e)));
+	println!("The results are written to {}.", path);
+}
+
+fn read_block(data: &[u8], source: &str) -> Block {
+	deserialize(data).unwrap_or_else(|e| fail(format!("{} is not a block: {:?}", source, e)))
+}
+
+fn value<T: ::std::str::FromStr>(args: &ArgMatches, name: &str) -> Option<T> {
+	args.value_of(name).map(|v| v.parse().unwrap_or_else(|_| fail(format!("invalid value {} for --{}", v, name))))
+}
+
+fn values<T: ::std::str::FromStr>(args: &ArgMatches, name: &str) -> Option<Vec<T>> {
+	args.values_of(name).map(|values| values.map(|v| v.parse().unwrap_or_else(|_| fail(format!("invalid value {} for --{}", v, name))))
+		.collect())
+}
+
+fn decoding_mode(args: &ArgMatches) -> DecodingMode {
+	match args.value_of("decoder") {
+		Some("inactivation") => DecodingMode::Inactivation,
+		_ => DecodingMode::Peeling,
+	}
+}
+
+//Codes for encoding and for decoding on every layer of a tree, read from the folder given by --codes
+fn codes_for_layout(args: &ArgMatches, layout: &CmtLayout) -> (Vec<Code>, Vec<Code>) {
+	let dir = args.value_of("codes").unwrap_or(CODES_DIR);
+	let k_set: Vec<u64> = (0..layout.height()).map(|lvl| layout.k(lvl as u32)).collect();
+	if let Some(filename) = missing_code_file(dir, &k_set) {
+		fail(format!("the codes of the tree are not in {}, missing {}", dir, filename));
+	}
+	read_codes(dir, k_set)
+}
+
+//Layout of the tree built from transactions padded to block_size bytes
+fn layout_for(transactions: &[Transaction], block_size: usize, header_size: u32) -> CmtLayout {
+	let size: usize = transactions.iter().map(|tx| tx.bytes().len()).sum();
+	CmtLayout::for_block(if size < block_size { block_size } else { size }, header_size as u64)
+}
+
+//Build the coded Merkle tree of the block given by --input or --hex, or of the reference block
+//With corrupt, the symbol given by --layer, --index and --pattern is corrupted after encoding
+fn encode<R: Rng>(args: &ArgMatches, corrupt: bool, rng: &mut R) {
+	let input = match (args.value_of("input"), args.value_of("hex")) {
+		(Some(path), _) => Some(read_block(&read_file(path), path)),
+		(None, Some(hex)) => {
+			let data: Vec<u8> = hex.from_hex().unwrap_or_else(|e| fail(format!("invalid hex block: {}", e)));
+			Some(read_block(&data, "the hex string"))
+		},
+		(None, None) => None,
+	};
+	let (header, transactions, block_size, hash_function): (BlockHeader, Vec<Transaction>, usize, HashFunction) = match input {
+		Some(block) => {
+			let hash_function = block.hash_function();
+			(block.block_header, block.transactions, block.block_size_in_bytes, hash_function)
+		},
+		None => (reference_header(), reference_transactions(), BLOCK_SIZE as usize, HashFunction::default()),
+	};
+	let block_size: usize = value(args, "block-size").unwrap_or(block_size);
+	let hash_function: HashFunction = value(args, "hash").unwrap_or(hash_function);
+	let header_size: u32 = value(args, "header-size").unwrap_or(HEADER_SIZE);
+	let layout = layout_for(&transactions, block_size, header_size);
+	let (codes_for_encoding, _) = codes_for_layout(args, &layout);
+
+	let mut errors = vec![];
+	if corrupt {
+		let layer: u32 = value(args, "layer").unwrap_or(0);
+		//by default the first parity symbol, as the incorrect-coding test of the reference design
+		let index: u64 = value(args, "index").unwrap_or_else(|| if (layer as usize) < layout.height() { layout.k(layer) } else { 0 });
+		let pattern: ErrorPattern = value(args, "pattern").unwrap_or(ErrorPattern::FlipAll);
+		let error = InjectedError { layer: layer, index: index, pattern: pattern };
+		error.check(&layout).unwrap_or_else(|e| fail(e));
+		errors.push(error);
+	}
+
+	let mut block = Block::new_with_hash_function(header, &transactions, block_size, header_size, &codes_for_encoding, &errors,
+		hash_function, rng);
+	if args.is_present("single-root") {
+		block = block.with_single_root();
+	}
+	let tree = match args.value_of("tree") {
+		Some("parity") => TreeEncoding::Parity,
+		Some("none") => TreeEncoding::None,
+		_ => TreeEncoding::Full,
+	};
+	let output = args.value_of("output").unwrap();
+	let data = block.to_bytes(tree);
+	write_file(output, &data);
+	println!("Encoded {} transactions into a coded Merkle tree of {} layers with {} base symbols, written with the block to {} ({} bytes).",
+		transactions.len(), layout.height(), layout.n(0), output, data.len());
+}
+
+//Sample the symbols of the block given by --block, and write them to --output
+fn sample<R: Rng>(args: &ArgMatches, rng: &mut R) {
+	let path = args.value_of("block").unwrap();
+	let block = read_block(&read_file(path), path);
+	if block.coded_tree.is_empty() {
+		fail(format!("{} is written without its coded Merkle tree", path));
+	}
+	let params = SamplingParams {
+		base_samples: value(args, "samples").unwrap(),
+		with_replacement: !args.is_present("without-replacement"),
+		layer_budgets: None,
+	};
+	let (symbols, indices, stats) = block.sample(&params, rng);
+	let output = args.value_of("output").unwrap();
+	write_file(output, &serialize(&Samples::from_views(&symbols, indices)));
+	println!("Sampled {:?} distinct symbols on the layers, downloading {} bytes, written to {}.",
+		stats.unique_symbols, stats.bytes_downloaded, output);
+}
+
+//Decode the block given by --block from the samples given by --samples, and write an incorrect-coding proof to --proof
+fn decode(args: &ArgMatches) {
+	let path = args.value_of("block").unwrap();
+	let block = read_block(&read_file(path), path);
+	let samples_path = args.value_of("samples").unwrap();
+	let samples: Samples = deserialize(&read_file(samples_path)[..])
+		.unwrap_or_else(|e| fail(format!("{} does not contain samples: {:?}", samples_path, e)));
+
+	let layout = block.block_header.layout().unwrap_or_else(|| fail(format!("the header of {} has no valid tree parameters", path)));
+	if samples.indices.len() != layout.height() {
+		fail(format!("the samples have {} layers and the tree has {}", samples.indices.len(), layout.height()));
+	}
+	let (_, codes_for_decoding) = codes_for_layout(args, &layout);
+	let codes_for_decoding = shared_codes(&codes_for_decoding);
+	//in single-root mode, the hashes of the top layer are served with the block
+	let served = if block.coded_tree.is_empty() { vec![] } else { block.top_hashes() };
+	let mut decoder = TreeDecoder::from_header(&codes_for_decoding, &block.block_header, &served)
+		.unwrap_or_else(|| fail(format!("the hashes of the top layer do not match the header of {}", path)))
+		.with_mode(decoding_mode(args))
+		.with_observer(decode_observer(args.value_of("trace")));
+
+	let received: usize = samples.indices.iter().map(|indices| indices.len()).sum();
+	match decoder.run_tree_decoder(&samples.views(), &samples.indices) {
+		Ok(()) => println!("The block is decoded from {} symbols.", received),
+		Err(proof) => {
+			match proof.error_type {
+				CodingErr::Stopped => println!("Decoding from {} symbols stops on layer {} with stopping ratio {}.",
+					received, proof.level, proof.stop_ratio),
+				_ => println!("Decoding from {} symbols finds an incorrect coding of type {:?} on layer {}.",
+					received, proof.error_type, proof.level),
+			}
+			if let Some(output) = args.value_of("proof") {
+				write_file(output, &serialize(&proof));
+				println!("The proof is written to {} ({} bytes).", output, proof.serialized_size());
+			}
+		},
+	}
+}
+
+//Verify the incorrect-coding proof given by --proof against the header of the block given by --block
+//Exits with an error if the proof is invalid
+fn verify_proof(args: &ArgMatches) {
+	let path = args.value_of("block").unwrap();
+	let block = read_block(&read_file(path), path);
+	let proof_path = args.value_of("proof").unwrap();
+	let data = read_file(proof_path);
+	let proof: IncorrectCodingProofRef = deserialize_borrowed(&data)
+		.unwrap_or_else(|e| fail(format!("{} is not an incorrect-coding proof: {:?}", proof_path, e)));
+
+	let layout = block.block_header.layout().unwrap_or_else(|| fail(format!("the header of {} has no valid tree parameters", path)));
+	let (_, codes_for_decoding) = codes_for_layout(args, &layout);
+	if block.block_header.verify_incorrect_coding_proof_ref(&codes_for_decoding, &proof) {
+		println!("The proof of type {:?} on layer {} is valid.", proof.error_type, proof.level);
+	} else {
+		println!("The proof of type {:?} on layer {} is invalid.", proof.error_type, proof.level);
+		process::exit(2);
+	}
+}
+
+//Generate a random LDPC code for decoding, and write it in the format given by --format
+fn gen_code<R: Rng>(args: &ArgMatches, rng: &mut R) {
+	let k: u64 = value(args, "k").unwrap();
+	let symbol_degree: u64 = value(args, "symbol-degree").unwrap();
+	let parity_degree: u64 = value(args, "parity-degree").unwrap();
+	let code = random_code(k, symbol_degree, parity_degree, rng).unwrap_or_else(||
+		fail(format!("the coded symbols of degree {} cannot be split into parity equations of degree {}", symbol_degree, parity_degree)));
+	let output = args.value_of("output").unwrap();
+	match args.value_of("format") {
+		Some("binary") => write_file(output, &serialize(&code)),
+		_ => write_code_to_file(&code, output).unwrap_or_else(|e| fail(format!("cannot write {}: {}", output, e))),
+	}
+	let stopping_set = find_stopping_set(&code, 0.5, rng);
+	println!("Generated a code with {} coded symbols and {} parity equations, written to {}.",
+		code.symbols.len(), code.parities.len(), output);
+	println!("Erasing half of the coded symbols leaves a stopping set of {} symbols.", stopping_set.len());
+}
+
+//Withhold a stopping set of the code on the layer given by --layer of the reference block
+fn simulate_command<R: Rng>(args: &ArgMatches, rng: &mut R) {
+	let hash_function: HashFunction = value(args, "hash").unwrap_or_default();
+	let transactions = reference_transactions();
+	let layout = layout_for(&transactions, BLOCK_SIZE as usize, HEADER_SIZE);
+	let (codes_for_encoding, codes_for_decoding) = codes_for_layout(args, &layout);
+	let codes_for_decoding: Vec<Arc<Code>> = shared_codes(&codes_for_decoding);
+	let block = Block::new_with_hash_function(reference_header(), &transactions, BLOCK_SIZE as usize, HEADER_SIZE,
+		&codes_for_encoding, &[], hash_function, rng);
+
+	let layer: usize = value(args, "layer").unwrap_or(0);
+	if layer >= layout.height() {
+		fail(format!("the tree has {} layers, there is no layer {}", layout.height(), layer));
+	}
+	let num_clients: u32 = value(args, "clients").unwrap_or(100);
+	let samples: u32 = value(args, "samples").unwrap_or(SAMPLE_COMPLEXITY);
+	let params = SamplingParams { base_samples: samples, with_replacement: !args.is_present("without-replacement"), layer_budgets: None };
+	simulate(&block, &codes_for_decoding, layer, &params, num_clients, rng);
+}
+
+//Run decoding trials at every combination of the parameters, and write the results to --csv and --json
+fn sweep<R: Rng>(args: &ArgMatches, rng: &mut R) {
+	let params = SweepParams {
+		block_sizes: values(args, "block-sizes").unwrap_or(vec![BLOCK_SIZE as usize]),
+		header_sizes: values(args, "header-sizes").unwrap_or(vec![HEADER_SIZE]),
+		rates: values(args, "rates").unwrap_or(vec![RATE]),
+		aggregates: values(args, "aggregates").unwrap_or(vec![AGGREGATE]),
+		code_dirs: values(args, "code-dirs").unwrap_or(vec![args.value_of("codes").unwrap_or(CODES_DIR).to_string()]),
+		sample_counts: values(args, "samples").unwrap_or(vec![1500, 1600, 1700, 1800, 1900, 2000]),
+		trials: value(args, "trials").unwrap(),
+		with_replacement: !args.is_present("without-replacement"),
+		mode: decoding_mode(args),
+		hash_function: value(args, "hash").unwrap_or_default(),
+		corrupt: args.is_present("corrupt"),
+		seed: rng.gen(),
+	};
+	let results = run_sweep(&params, &reference_header(), &REFERENCE_TRANSACTION.into()).unwrap_or_else(|e| fail(e));
+	for result in results.iter() {
+		let point = &result.point;
+		println!("Block of {} bytes, header size {}, rate {}, {} hashes per upper symbol, codes in {}: {} samples decode with probability {}, \
+			downloading {} bytes on average.", point.block_size, point.header_size, point.rate, point.aggregate, point.code_dir, point.samples, 
+			result.decode_probability(), result.mean_bytes_downloaded);
+	}
+
+	if let Some(path) = args.value_of("csv") {
+		write_results(path, |writer| write_csv(&results, writer));
+	}
+	if let Some(path) = args.value_of("json") {
+		write_results(path, |writer| write_json(&results, writer));
+	}
+}
+
+//Build the trees of the Bitcoin blocks given by --blk, --hex or --hex-file, and print their statistics
+//Blocks whose trees have no codes in the folder given by --codes are skipped
+fn import<R: Rng>(args: &ArgMatches, rng: &mut R) {
+	let blocks: Box<dyn Iterator<Item = Result<ImportedBlock, ImportError>>> = match (args.value_of("blk"), args.value_of("hex"),
+		args.value_of("hex-file")) {
+		(Some(path), _, _) => {
+			let file = File::open(path).unwrap_or_else(|e| fail(format!("cannot read {}: {}", path, e)));
+			let key_file = Path::new(path).with_file_name("xor.dat");
+			let key: Option<Vec<u8>> = match args.value_of("xor-key") {
+				Some(hex) => Some(hex.from_hex().unwrap_or_else(|e| fail(format!("invalid xor key: {}", e)))),
+				None if key_file.is_file() => Some(fs::read(&key_file).unwrap_or_else(|e| fail(format!("cannot read {}: {}", key_file.display(), e)))),
+				None => None,
+			};
+			let mut reader = BlkFileReader::new(io::BufReader::new(file));
+			if let Some(key) = key {
+				let mut xor_key = [0u8; 8];
+				if key.len() != xor_key.len() {
+					fail(format!("the xor key has {} bytes instead of 8", key.len()));
+				}
+				xor_key.copy_from_slice(&key);
+				reader = reader.with_xor_key(xor_key);
+			}
+			let limit: usize = value(args, "limit").unwrap_or(usize::MAX);
+			Box::new(reader.take(limit))
+		},
+		(None, Some(hex), _) => Box::new(Some(ImportedBlock::from_hex(hex)).into_iter()),
+		(None, None, Some(path)) => {
+			let hex = String::from_utf8(read_file(path)).unwrap_or_else(|_| fail(format!("{} is not a hex string", path)));
+			Box::new(Some(ImportedBlock::from_hex(&hex)).into_iter())
+		},
+		(None, None, None) => fail("a block is given by --blk, --hex or --hex-file".to_string()),
+	};
+	let params = ImportParams {
+		code_dir: args.value_of("codes").unwrap_or(CODES_DIR).to_string(),
+		header_size: value(args, "header-size").unwrap_or(HEADER_SIZE),
+		hash_function: value(args, "hash").unwrap_or_default(),
+		samples: value(args, "samples").unwrap(),
+		trials: value(args, "trials").unwrap(),
+	};
+
+	let (mut imported, mut skipped) = (0, 0);
+	for block in blocks {
+		let block = block.unwrap_or_else(|e| fail(format!("cannot import a block: {:?}", e)));
+		let hash = block.hash.to_reversed_str();
+		let report = match report_block(&block, &params, rng) {
+			Ok(report) => report,
+			Err(e) => {
+				println!("Skipping block {}: {}.", hash, e);
+				skipped += 1;
+				continue;
+			},
+		};
+		if !report.merkle_root_valid {
+			println!("The transactions of block {} do not match its Merkle root.", hash);
+		}
+		println!("Block {} has {} transactions, {} bytes or {} bytes without witnesses, padded with {} bytes.",
+			hash, report.transactions, report.size, report.coded_size, report.padding);
+		println!("Its coded Merkle tree has {} layers and {} base symbols. {} out of {} trials with {} samples decode it, downloading {} bytes on average. \
+			Incorrect-coding proofs on the layers have {:?} bytes.", report.layers, report.base_symbols, report.decoded, params.trials, params.samples,
+			report.bytes_downloaded / (params.trials.max(1) as u64), report.proof_sizes);
+		imported += 1;
+	}
+	println!("Imported {} blocks, skipped {} blocks without codes.", imported, skipped);
+}
@@ -0,0 +1,136 @@
+// Data-availability confidence of light clients
+//
+// To prevent full nodes from decoding some layer of CMT, a malicious block producer has to withhold
+// at least a stopping set of the code on that layer, i.e., a fraction of at least stopping_ratio of its coded symbols.
+// A light client sampling uniformly at random detects the withholding as soon as one of its samples is not returned.
+// The soundness of sampling is the probability that all samples miss the withheld symbols.
+
+// Probability that s samples taken with replacement all miss a withheld fraction stopping_ratio of symbols
+pub fn soundness(stopping_ratio: f64, s: u32) -> f64 {
+	if stopping_ratio <= 0.0 {
+		return 1.0;
+	}
+	if stopping_ratio >= 1.0 {
+		return if s == 0 { 1.0 } else { 0.0 };
+	}
+	// powf, since s may exceed the range of the i32 exponent of powi
+	(1.0 - stopping_ratio).powf(s as f64)
+}
+
+// Confidence that the block is available after s successful samples taken with replacement
+pub fn confidence(stopping_ratio: f64, s: u32) -> f64 {
+	1.0 - soundness(stopping_ratio, s)
+}
+
+// Number of samples taken with replacement such that the soundness is at most target_soundness
+// Returns None if no number of samples achieves the target, i.e., nothing needs to be withheld,
+// or if more than u32::MAX samples are needed
+pub fn samples_needed(stopping_ratio: f64, target_soundness: f64) -> Option<u32> {
+	if target_soundness >= 1.0 {
+		return Some(0);
+	}
+	if stopping_ratio <= 0.0 || target_soundness <= 0.0 {
+		return None;
+	}
+	if stopping_ratio >= 1.0 {
+		return Some(1);
+	}
+	let bound = (target_soundness.ln() / (1.0 - stopping_ratio).ln()).ceil();
+	if bound > u32::MAX as f64 {
+		return None;
+	}
+	// rounding errors may leave the bound just above the target
+	let mut s = bound as u32;
+	while soundness(stopping_ratio, s) > target_soundness {
+		s = s.checked_add(1)?;
+	}
+	Some(s)
+}
+
+// number of symbols out of n a malicious block producer has to withhold
+fn withheld_symbols(n: u64, stopping_ratio: f64) -> u64 {
+	let m = ((n as f64) * stopping_ratio).ceil() as u64;
+	if m > n { n } else { m }
+}
+
+// Probability that s distinct samples out of n symbols all miss a withheld fraction stopping_ratio of symbols
+pub fn soundness_without_replacement(n: u64, stopping_ratio: f64, s: u32) -> f64 {
+	let m = withheld_symbols(n, stopping_ratio);
+	let mut p = 1.0;
+	for i in 0..(s as u64) {
+		if i + m >= n {
+			return 0.0;
+		}
+		p *= ((n - m - i) as f64) / ((n - i) as f64);
+	}
+	p
+}
+
+// Confidence that the block is available after s successful distinct samples out of n symbols
+pub fn confidence_without_replacement(n: u64, stopping_ratio: f64, s: u32) -> f64 {
+	1.0 - soundness_without_replacement(n, stopping_ratio, s)
+}
+
+// Number of distinct samples out of n symbols such that the soundness is at most target_soundness
+pub fn samples_needed_without_replacement(n: u64, stopping_ratio: f64, target_soundness: f64) -> Option<u32> {
+	if target_soundness >= 1.0 {
+		return Some(0);
+	}
+	if withheld_symbols(n, stopping_ratio) == 0 || target_soundness <= 0.0 {
+		return None;
+	}
+	let m = withheld_symbols(n, stopping_ratio);
+	let mut p = 1.0;
+	for i in 0..(n - m + 1) {
+		if p <= target_soundness {
+			return Some(i as u32);
+		}
+		p *= ((n - m - i) as f64) / ((n - i) as f64);
+	}
+	Some((n - m + 1) as u32)
+}
+
+// The weakest layer determines the soundness of sampling
+pub fn min_stopping_ratio(stopping_ratios: &[f64]) -> f64 {
+	stopping_ratios.iter().cloned().fold(1.0, f64::min)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_samples_needed_with_replacement() {
+		// (1 - 0.5)^7 < 0.01 < (1 - 0.5)^6
+		assert_eq!(samples_needed(0.5, 0.01), Some(7));
+		assert!(soundness(0.5, 7) <= 0.01);
+		assert!(confidence(0.5, 6) < 0.99);
+		assert_eq!(samples_needed(0.0, 0.01), None);
+		assert_eq!(samples_needed(1.0, 0.01), Some(1));
+		assert_eq!(samples_needed(0.1, 1.0), Some(0));
+	}
+
+	#[test]
+	fn test_samples_needed_for_tiny_stopping_ratio() {
+		// about 2e13 samples are needed, more than a u32 can count
+		assert_eq!(samples_needed(1e-12, 1e-9), None);
+		// about 2.3e9 samples, beyond the exponents of powi
+		let s = samples_needed(9e-9, 1e-9).unwrap();
+		assert!(s > i32::MAX as u32);
+		assert!(soundness(9e-9, s) <= 1e-9);
+		assert!(soundness(1e-12, u32::MAX) <= 1.0);
+	}
+
+	#[test]
+	fn test_samples_needed_without_replacement() {
+		// 2 out of 4 symbols withheld: missing both takes (2/4) * (1/3) = 1/6 for 2 samples, and 0 for 3 samples
+		assert_eq!(soundness_without_replacement(4, 0.5, 2), 1.0 / 6.0);
+		assert_eq!(soundness_without_replacement(4, 0.5, 3), 0.0);
+		assert_eq!(samples_needed_without_replacement(4, 0.5, 0.2), Some(2));
+		assert_eq!(samples_needed_without_replacement(4, 0.5, 0.1), Some(3));
+		// sampling without replacement never needs more samples
+		let s = samples_needed_without_replacement(2048, 0.1, 1e-6).unwrap();
+		assert!(s <= samples_needed(0.1, 1e-6).unwrap());
+		assert_eq!(min_stopping_ratio(&[0.2, 0.05, 0.3]), 0.05);
+	}
+}
@@ -46,6 +46,7 @@ pub const HEADER_SIZE: u32 = 256;
 pub const NUMBER_ITERATION: u32 = 10;
 
 //number of coded symbols sampled by a light node to check data availability
+//a light client derives it from the stopping ratio of the codes and its target soundness, see confidence.rs
 pub const SAMPLE_COMPLEXITY: u32 = 30;
 
 
@@ -55,3 +56,17 @@ pub const SAMPLE_COMPLEXITY: u32 = 30;
 
 
 
+
+//Protocol maxima enforced when deserializing headers and proofs, so that forged length prefixes are rejected before allocation
+
+//maximum number of hashes in a block header
+pub const MAX_HEADER_HASHES: usize = 4096;
+
+//maximum number of layers of coded Merkle tree
+pub const MAX_CMT_LAYERS: usize = 32;
+
+//maximum number of symbols in a parity equation of an incorrect-coding proof
+pub const MAX_PARITY_DEGREE: usize = 4096;
+
+//maximum number of coded symbols on a layer of coded Merkle tree
+pub const MAX_LAYER_LENGTH: usize = 1 << 24;
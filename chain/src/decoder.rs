@@ -1,24 +1,63 @@
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, HEADER_SIZE};
-use std::cmp;
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, MAX_HEADER_HASHES, MAX_CMT_LAYERS, MAX_PARITY_DEGREE, MAX_LAYER_LENGTH};
+use std::{io, mem};
+use std::str::FromStr;
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::sync::Arc;
 use std::ops::BitXor;
 use {Symbols, SymbolBase, SymbolUp};
 use hash::H256;
-use crypto::dhash256;
-use rand::distributions::{Distribution, Bernoulli, Uniform};
+use cmt_hash::{CmtHasher, HashFunction};
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use inactivation::{DecodingMode, solve_residual};
+use observer::{DecodeObserver, NullObserver};
+use layout::CmtLayout;
+use block_header::BlockHeader;
+use top_root::{top_proof, top_proof_len};
+use symbol_ops::{xor_into, is_zero};
+use arena::LayerArena;
+use ser::{Serializable, Deserializable, Stream, Reader, Error, CompactInteger, Deserialize, SliceReader};
+use codes::convert_parity_to_symbol;
 
 // Symbols on the base layer can have different size as the upper layer
 // The value of symbol is empty before it is decoded
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Symbol {
 	Base([u8; BASE_SYMBOL_SIZE]),
 	Upper([u8; 32 * AGGREGATE]),
 	Empty,
 } 
 
+impl Symbol {
+	// A symbol of layer level from its bytes, which must have the size of the symbols of the layer
+	pub fn from_bytes(level: u32, bytes: &[u8]) -> Symbol {
+		if level == 0 {
+			let mut sym = [0u8; BASE_SYMBOL_SIZE];
+			sym.copy_from_slice(bytes);
+			Symbol::Base(sym)
+		} else {
+			let mut sym = [0u8; 32 * AGGREGATE];
+			sym.copy_from_slice(bytes);
+			Symbol::Upper(sym)
+		}
+	}
+
+	// the bytes of a symbol, none for an empty symbol
+	pub fn bytes(&self) -> &[u8] {
+		match *self {
+			Symbol::Base(ref sym) => sym,
+			Symbol::Upper(ref sym) => sym,
+			Symbol::Empty => &[],
+		}
+	}
+}
+
 // a new type indicating types of coding errors
 // NotZero: symbols in a parity equation does not sum up to zero
 // NotHash: decoded symbol does not match its hash
 // Stopped: peeling decoder cannot continue due to absence of degree-one parity node 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CodingErr{
 	NotZero,
 	NotHash,
@@ -26,6 +65,7 @@ pub enum CodingErr{
 } 
 
 // a full node sends an incorrect-coding proof if it detects errors during decoding
+#[derive(Debug, Clone, PartialEq)]
 pub struct IncorrectCodingProof {
 	pub error_type: CodingErr,
 	pub level: u32,
@@ -33,17 +73,401 @@ pub struct IncorrectCodingProof {
 	pub indices: Vec<u64>,
 	pub parity_index: u64,
 	pub proofs: Vec<Vec<Symbol>>,
+	pub top_proofs: Vec<Vec<H256>>, //inclusion proofs of the top-layer symbols of the Merkle proofs in single-root mode, empty otherwise
 	pub stop_set: Vec<u64>,
 	pub stop_ratio: f32
 }
 
+// A symbol borrowed from a Symbol, or from a received buffer without copying it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolRef<'a> {
+	Base(&'a [u8; BASE_SYMBOL_SIZE]),
+	Upper(&'a [u8; 32 * AGGREGATE]),
+	Empty,
+}
+
+// An incorrect-coding proof borrowing its symbols and inclusion proofs from a received buffer, with the fields of IncorrectCodingProof
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncorrectCodingProofRef<'a> {
+	pub error_type: CodingErr,
+	pub level: u32,
+	pub symbols: Vec<SymbolRef<'a>>,
+	pub indices: Cow<'a, [u64]>,
+	pub parity_index: u64,
+	pub proofs: Vec<Vec<SymbolRef<'a>>>,
+	pub top_proofs: Vec<&'a [H256]>,
+	pub stop_set: Cow<'a, [u64]>,
+	pub stop_ratio: f32
+}
+
 //A code is specified by its parity-check matrix, which is represented by parities or symbols vectors
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Code {
 	pub parities: Vec<Vec<u64>>,
 	pub symbols: Vec<Vec<u64>>,
 }
 
+// How a malicious block producer corrupts a coded symbol after encoding, used for tests of incorrect-coding proofs
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorPattern {
+	FlipAll, // flip all bits of the symbol
+	FlipBits(Vec<usize>), // flip the bits at these positions, bit i is bit i % 8 of byte i / 8
+	Random(u64), // replace the symbol by random bytes generated from this seed
+	Zero, // set every byte of the symbol to zero
+	SwapWith(u64), // exchange the symbol with another symbol on the same layer
+}
+
+// A corruption of the coded symbol with some index on some layer of CMT
+#[derive(Debug, Clone, PartialEq)]
+pub struct InjectedError {
+	pub layer: u32,
+	pub index: u64,
+	pub pattern: ErrorPattern,
+}
+
+// An error pattern is written as flip-all, zero, random:SEED, flip-bits:I,J,... or swap:INDEX
+impl FromStr for ErrorPattern {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parts = s.splitn(2, ':');
+		let name = parts.next().unwrap_or("");
+		let arg = parts.next();
+		let invalid = || format!("invalid error pattern {}", s);
+		match (name, arg) {
+			("flip-all", None) => Ok(ErrorPattern::FlipAll),
+			("zero", None) => Ok(ErrorPattern::Zero),
+			("random", Some(seed)) => seed.parse().map(ErrorPattern::Random).map_err(|_| invalid()),
+			("swap", Some(index)) => index.parse().map(ErrorPattern::SwapWith).map_err(|_| invalid()),
+			("flip-bits", Some(bits)) => bits.split(',').map(|bit| bit.parse().map_err(|_| invalid())).collect::<Result<Vec<usize>, _>>()
+				.map(ErrorPattern::FlipBits),
+			_ => Err(invalid()),
+		}
+	}
+}
+
+impl InjectedError {
+	// Check that the corrupted symbol, the swapped symbol and the flipped bits exist in a tree with this layout
+	pub fn check(&self, layout: &CmtLayout) -> Result<(), String> {
+		if (self.layer as usize) >= layout.height() {
+			return Err(format!("the tree has {} layers, there is no layer {}", layout.height(), self.layer));
+		}
+		let symbol_size = if self.layer == 0 { BASE_SYMBOL_SIZE } else { 32 * AGGREGATE };
+		check_error(layout.n(self.layer), symbol_size, self.index, &self.pattern)
+			.map_err(|e| format!("{} on layer {}", e, self.layer))
+	}
+}
+
+// Check an error on a layer of n symbols of symbol_size bytes
+fn check_error(n: u64, symbol_size: usize, index: u64, pattern: &ErrorPattern) -> Result<(), String> {
+	if index >= n {
+		return Err(format!("there is no symbol {} out of {} symbols", index, n));
+	}
+	match *pattern {
+		ErrorPattern::SwapWith(other) if other >= n => Err(format!("there is no symbol {} out of {} symbols", other, n)),
+		ErrorPattern::FlipBits(ref positions) => match positions.iter().find(|p| **p >= 8 * symbol_size) {
+			Some(p) => Err(format!("there is no bit {} in a symbol of {} bytes", p, symbol_size)),
+			None => Ok(()),
+		},
+		_ => Ok(()),
+	}
+}
+
+// Corrupt the symbol with some index of a layer according to pattern
+// Fails without changing the layer if the index, the swapped index or a flipped bit is out of range
+pub fn inject_error(layer: &mut LayerArena, index: u64, pattern: &ErrorPattern) -> Result<(), String> {
+	check_error(layer.len() as u64, layer.symbol_size(), index, pattern)?;
+	let index = index as usize;
+	if let ErrorPattern::SwapWith(other) = *pattern {
+		layer.swap(index, other as usize);
+		return Ok(());
+	}
+	let bytes = layer.symbol_mut(index);
+	match *pattern {
+		ErrorPattern::FlipAll => {
+			for l in 0..bytes.len() {
+				bytes[l] = bytes[l].bitxor(255u8);
+			}
+		},
+		ErrorPattern::FlipBits(ref positions) => {
+			for p in positions.iter() {
+				bytes[p / 8] = bytes[p / 8].bitxor(1u8 << (p % 8));
+			}
+		},
+		ErrorPattern::Random(seed) => StdRng::seed_from_u64(seed).fill_bytes(bytes),
+		ErrorPattern::Zero => {
+			for l in 0..bytes.len() {
+				bytes[l] = 0u8;
+			}
+		},
+		ErrorPattern::SwapWith(_) => unreachable!(),
+	}
+	Ok(())
+}
+
+// A symbol is sent as a tag byte followed by its bytes: 0 for a base symbol, 1 for an upper symbol, 2 for an empty symbol
+impl Serializable for Symbol {
+	fn serialize(&self, stream: &mut Stream) {
+		match *self {
+			Symbol::Base(ref sym) => stream.append(&0u8).append(sym),
+			Symbol::Upper(ref sym) => stream.append(&1u8).append(sym),
+			Symbol::Empty => stream.append(&2u8),
+		};
+	}
+}
+
+impl Deserializable for Symbol {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		match reader.read::<u8>()? {
+			0 => Ok(Symbol::Base(reader.read()?)),
+			1 => Ok(Symbol::Upper(reader.read()?)),
+			2 => Ok(Symbol::Empty),
+			_ => Err(Error::MalformedData),
+		}
+	}
+}
+
+impl<'a> From<&'a Symbol> for SymbolRef<'a> {
+	fn from(symbol: &'a Symbol) -> Self {
+		match *symbol {
+			Symbol::Base(ref sym) => SymbolRef::Base(sym),
+			Symbol::Upper(ref sym) => SymbolRef::Upper(sym),
+			Symbol::Empty => SymbolRef::Empty,
+		}
+	}
+}
+
+impl<'a> SymbolRef<'a> {
+	pub fn to_symbol(&self) -> Symbol {
+		match *self {
+			SymbolRef::Base(sym) => Symbol::Base(*sym),
+			SymbolRef::Upper(sym) => Symbol::Upper(*sym),
+			SymbolRef::Empty => Symbol::Empty,
+		}
+	}
+
+	// The hashes of a symbol on an upper layer, viewed in place like symbol_to_up
+	pub fn up(&self) -> Option<&'a SymbolUp> {
+		match *self {
+			SymbolRef::Upper(bytes) => Some(H256::slice_from_bytes(bytes).try_into().expect("an upper symbol has AGGREGATE hashes")),
+			_ => None,
+		}
+	}
+}
+
+impl<'a> Deserialize<'a> for SymbolRef<'a> {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		match reader.read::<u8>()? {
+			0 => Ok(SymbolRef::Base(reader.read()?)),
+			1 => Ok(SymbolRef::Upper(reader.read()?)),
+			2 => Ok(SymbolRef::Empty),
+			_ => Err(Error::MalformedData),
+		}
+	}
+}
+
+impl<'a> Deserialize<'a> for CodingErr {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		reader.read_owned()
+	}
+}
+
+impl Serializable for CodingErr {
+	fn serialize(&self, stream: &mut Stream) {
+		let code: u8 = match *self {
+			CodingErr::NotZero => 0,
+			CodingErr::NotHash => 1,
+			CodingErr::Stopped => 2,
+		};
+		stream.append(&code);
+	}
+}
+
+impl Deserializable for CodingErr {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		match reader.read::<u8>()? {
+			0 => Ok(CodingErr::NotZero),
+			1 => Ok(CodingErr::NotHash),
+			2 => Ok(CodingErr::Stopped),
+			_ => Err(Error::MalformedData),
+		}
+	}
+}
+
+// A code is sent as its number of coded symbols and its parity equations, the symbols form is rebuilt from the parities
+impl Serializable for Code {
+	fn serialize(&self, stream: &mut Stream) {
+		stream
+			.append(&(self.symbols.len() as u64))
+			.append(&CompactInteger::from(self.parities.len()));
+		for parity in self.parities.iter() {
+			stream.append_list(parity);
+		}
+	}
+}
+
+impl Deserializable for Code {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		let n: u64 = reader.read()?;
+		let num_parities = reader.read_len()?;
+		let mut parities: Vec<Vec<u64>> = vec![];
+		let mut degrees = 0u64;
+		for _ in 0..num_parities {
+			let parity: Vec<u64> = reader.read_list()?;
+			if parity.iter().any(|s| *s >= n) {
+				return Err(Error::MalformedData);
+			}
+			degrees += parity.len() as u64;
+			parities.push(parity);
+		}
+		// a symbol in no parity equation can never be decoded, so every symbol appears in some parity equation
+		if n > degrees {
+			return Err(Error::MalformedData);
+		}
+		Ok(Code {
+			symbols: convert_parity_to_symbol(parities.clone(), n),
+			parities: parities,
+		})
+	}
+}
+
+impl Serializable for IncorrectCodingProof {
+	fn serialize(&self, stream: &mut Stream) {
+		stream
+			.append(&self.error_type)
+			.append(&self.level)
+			.append_list(&self.symbols)
+			.append_list(&self.indices)
+			.append(&self.parity_index)
+			.append(&CompactInteger::from(self.proofs.len()));
+		for proof in self.proofs.iter() {
+			stream.append_list(proof);
+		}
+		stream.append(&CompactInteger::from(self.top_proofs.len()));
+		for proof in self.top_proofs.iter() {
+			stream.append_list(proof);
+		}
+		stream
+			.append_list(&self.stop_set)
+			.append(&self.stop_ratio);
+	}
+}
+
+impl Deserializable for IncorrectCodingProof {
+	// A proof has a Merkle proof for each symbol of a parity equation, and each Merkle proof has at most one symbol per layer
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		let error_type = reader.read()?;
+		let level = reader.read()?;
+		let symbols = reader.read_list_max(MAX_PARITY_DEGREE)?;
+		let indices = reader.read_list_max(MAX_PARITY_DEGREE)?;
+		let parity_index = reader.read()?;
+		let num_proofs = reader.read_len()?;
+		if num_proofs > MAX_PARITY_DEGREE {
+			return Err(Error::MalformedData);
+		}
+		let mut proofs = vec![];
+		for _ in 0..num_proofs {
+			proofs.push(reader.read_list_max(MAX_CMT_LAYERS)?);
+		}
+		let num_top_proofs = reader.read_len()?;
+		if num_top_proofs > MAX_PARITY_DEGREE {
+			return Err(Error::MalformedData);
+		}
+		let mut top_proofs = vec![];
+		for _ in 0..num_top_proofs {
+			top_proofs.push(reader.read_list_max(top_proof_len(MAX_HEADER_HASHES as u64))?);
+		}
+		Ok(IncorrectCodingProof {
+			error_type: error_type,
+			level: level,
+			symbols: symbols,
+			indices: indices,
+			parity_index: parity_index,
+			proofs: proofs,
+			top_proofs: top_proofs,
+			stop_set: reader.read_list_max(MAX_LAYER_LENGTH)?,
+			stop_ratio: reader.read()?,
+		})
+	}
+}
+
+// The wire format of IncorrectCodingProof, with the same protocol maxima
+impl<'a> Deserialize<'a> for IncorrectCodingProofRef<'a> {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let error_type = reader.read()?;
+		let level = reader.read()?;
+		let symbols = reader.read_list_max(MAX_PARITY_DEGREE)?;
+		let indices: Vec<u64> = reader.read_list_max(MAX_PARITY_DEGREE)?;
+		let parity_index = reader.read()?;
+		let num_proofs = reader.read_len()?;
+		if num_proofs > MAX_PARITY_DEGREE {
+			return Err(Error::MalformedData);
+		}
+		let mut proofs = vec![];
+		for _ in 0..num_proofs {
+			proofs.push(reader.read_list_max(MAX_CMT_LAYERS)?);
+		}
+		let num_top_proofs = reader.read_len()?;
+		if num_top_proofs > MAX_PARITY_DEGREE {
+			return Err(Error::MalformedData);
+		}
+		let mut top_proofs = vec![];
+		for _ in 0..num_top_proofs {
+			let top_proof: &[H256] = reader.read()?;
+			if top_proof.len() > top_proof_len(MAX_HEADER_HASHES as u64) {
+				return Err(Error::MalformedData);
+			}
+			top_proofs.push(top_proof);
+		}
+		let stop_set: Vec<u64> = reader.read_list_max(MAX_LAYER_LENGTH)?;
+		Ok(IncorrectCodingProofRef {
+			error_type: error_type,
+			level: level,
+			symbols: symbols,
+			indices: Cow::Owned(indices),
+			parity_index: parity_index,
+			proofs: proofs,
+			top_proofs: top_proofs,
+			stop_set: Cow::Owned(stop_set),
+			stop_ratio: reader.read()?,
+		})
+	}
+}
+
+impl IncorrectCodingProof {
+	// The proof borrowed, without copying its symbols
+	pub fn as_borrowed(&self) -> IncorrectCodingProofRef {
+		IncorrectCodingProofRef {
+			error_type: self.error_type,
+			level: self.level,
+			symbols: self.symbols.iter().map(SymbolRef::from).collect(),
+			indices: Cow::Borrowed(&self.indices),
+			parity_index: self.parity_index,
+			proofs: self.proofs.iter().map(|proof| proof.iter().map(SymbolRef::from).collect()).collect(),
+			top_proofs: self.top_proofs.iter().map(|proof| &proof[..]).collect(),
+			stop_set: Cow::Borrowed(&self.stop_set),
+			stop_ratio: self.stop_ratio,
+		}
+	}
+}
+
+impl<'a> IncorrectCodingProofRef<'a> {
+	// Copy of the proof that no longer borrows from the received buffer
+	pub fn to_proof(&self) -> IncorrectCodingProof {
+		IncorrectCodingProof {
+			error_type: self.error_type,
+			level: self.level,
+			symbols: self.symbols.iter().map(SymbolRef::to_symbol).collect(),
+			indices: self.indices.to_vec(),
+			parity_index: self.parity_index,
+			proofs: self.proofs.iter().map(|proof| proof.iter().map(SymbolRef::to_symbol).collect()).collect(),
+			top_proofs: self.top_proofs.iter().map(|proof| proof.to_vec()).collect(),
+			stop_set: self.stop_set.to_vec(),
+			stop_ratio: self.stop_ratio,
+		}
+	}
+}
+
 // Decoder for CMT
 // contains a decoder for each layer of CMT
 // hashes are hashes of the coded symbols on the last (top) layer
@@ -51,7 +475,12 @@ pub struct TreeDecoder {
 	pub n: u64, //block length of code on the base layer of the tree
 	pub height: u32,
 	pub decoders: Vec<Decoder>,
-	pub hashes: Vec<Vec<H256>> //hashes of all layers
+	pub hashes: Vec<Vec<H256>>, //hashes of all layers
+	pub layout: CmtLayout, //geometry of the tree, from the lengths of the codes
+	pub top_root: Option<H256>, //single root over the hashes of the top layer, if the header is in single-root mode
+	pub mode: DecodingMode, //whether to fall back to Gaussian elimination when peeling stalls
+	pub inactivation_recovered: Vec<u64>, //number of symbols recovered by Gaussian elimination on each layer
+	pub observer: Box<dyn DecodeObserver>, //receives every decoding step, ignores them by default
 }
 
 #[derive(Clone)]
@@ -61,13 +490,13 @@ pub struct Decoder {
 	pub k: u64, // # of systematic symbols
 	pub p: u64, // # of parity check equations
 
-	pub code: Code, //code shall not change during decoding
+	pub code: Arc<Code>, //code shall not change during decoding, and is shared by all decoders using it
+	pub hash_function: HashFunction, //hash function of the tree, decoded symbols are checked against their hashes with it
 
-	pub parities: Vec<Vec<u64>>, // vector of length p, each element is a vector indicating the variable nodes connected to a parity node
-	pub symbols: Vec<Vec<u64>>, // vector of length n, each element is a vector indicating the parity nodes connected to a variable node
-
-	pub symbol_values: Vec<Symbol>, // values of variable nodes
-    pub parity_values: Vec<Symbol>, //values of parity nodes
+	//mutable peeling state, the graph itself is never modified: the degree of a parity node counts its undecoded symbols
+	pub symbol_values: LayerArena, // values of variable nodes, valid for the decoded ones
+	pub decoded: Vec<bool>, // whether each variable node is received or decoded
+    pub parity_values: LayerArena, //values of parity nodes
     pub parity_degree: Vec<u32>, 
     pub degree_1_parities: Vec<u64>, // set of parity nodes whose degree is 1 during decoding
 
@@ -75,85 +504,62 @@ pub struct Decoder {
     pub num_decoded_symbols: u64,
 }
 
-//Convert decoded symbols of the current layer to the hashes of the previous layer
-fn symbol_to_hash(symbols: &Vec<Symbol>) -> Vec<H256> {
-    let reduce_factor = ((AGGREGATE as f32) * RATE) as u64;
-
-	let number_of_hashes = symbols.len() * AGGREGATE; 
-	let mut previous_hashes = vec![H256::default();number_of_hashes];
+//Convert decoded systematic symbols of layer lvl + 1 to the hashes of layer lvl
+pub(crate) fn symbol_to_hash(layout: &CmtLayout, lvl: u32, upper: &LayerArena) -> Vec<H256> {
+	//read the hash directly from the bytes of the parent symbol
+	(0..layout.n(lvl)).map(|index| *upper.hash(layout.parent(lvl, index) as usize, layout.hash_slot(lvl, index))).collect()
+}
 
-    //convert each symbol to a vector of hashes
-	let mut symbols_in_hashes: Vec<SymbolUp> = vec![];
-	for i in 0..symbols.len() {
-		//convert symbols[i] to a vector of hashes
-		let mut symbol_in_hash = [H256::default(); AGGREGATE];
-		if let Symbol::Upper(symbol_in_bytes) = symbols[i] {
+// Convert a symbol on an upper layer to the hashes it aggregates, None for a base or empty symbol
+pub fn symbol_to_up(symbol: &Symbol) -> Option<SymbolUp> {
+	match *symbol {
+		Symbol::Upper(bytes) => {
+			let mut sym = [H256::default(); AGGREGATE];
 			for j in 0..AGGREGATE {
 				let mut h = [0u8; 32];
-				h.copy_from_slice(&symbol_in_bytes[j*32..(j*32+32)]);
-			    symbol_in_hash[j] = H256::from(h);
-		    }
-		}
-		symbols_in_hashes.push(symbol_in_hash);
-	}
-    
-    //number of systematic symbols on the previous level
-	let k = ((previous_hashes.len() as f32) * RATE) as u64;
-
-	for index in 0..previous_hashes.len() {
-		let mut hash_index = 0;
-
-        // current symbol is a systematic symbol
-		if (index as u64) <= k - 1 {
-			hash_index = (index as u64) % reduce_factor;
-		}
-		// current symbol is a parity symbol
-		else {
-			hash_index = ((index as u64) - k) % ((AGGREGATE as u64) - reduce_factor) + reduce_factor;
-		}
-
-		previous_hashes[index] = symbols_in_hashes[next_index(index as u64, k, reduce_factor) as usize][hash_index as usize];
+				h.copy_from_slice(&bytes[j*32..(j*32+32)]);
+				sym[j] = H256::from(h);
+			}
+			Some(sym)
+		},
+		_ => None,
 	}
-
-	previous_hashes
 }
 
-//return if a symbol is equal to zero or not (every byte equals to 0u8)
-fn symbol_equal_to_zero(symbol: Symbol) -> bool {
-	let mut flag = true;
-	match symbol {
-		Symbol::Base(decoded) => {
-			for j in 0..BASE_SYMBOL_SIZE {
-				if decoded[j] != 0u8 {
-					flag = false;
-					break;
-				}
-			}	
-		},
-		Symbol::Upper(decoded) => {
-			for j in 0..32 * AGGREGATE {
-				if decoded[j] != 0u8 {
-					flag = false;
-					break;
-				}
-			}	
-		},
-		_ => {}
+// Convert the hashes of a symbol on an upper layer to its bytes, the inverse of symbol_to_up
+pub fn up_to_bytes(symbol: &SymbolUp) -> [u8; 32 * AGGREGATE] {
+	let mut bytes = [0u8; 32 * AGGREGATE];
+	for j in 0..AGGREGATE {
+		bytes[j * 32 .. (j + 1) * 32].copy_from_slice(&symbol[j][..]);
 	}
-	flag
+	bytes
 }
 
-//index of the parent symbol on the coded Merkle tree
-fn next_index(index: u64, k: u64, reduce_factor: u64) -> u64 {
-	if index <= k - 1 {
-		index / reduce_factor
-	}
-	else {
-		(index - k) / ((AGGREGATE as u64) - reduce_factor)
+// Convert symbols of the decoder back to a layer of CMT, None if they are not all base or all upper symbols
+pub fn to_symbols(lvl: u32, symbols: &[Symbol]) -> Option<Symbols> {
+	if lvl == 0 {
+		let mut base: Vec<SymbolBase> = vec![];
+		for symbol in symbols.iter() {
+			match *symbol {
+				Symbol::Base(sym) => base.push(sym),
+				_ => return None,
+			}
+		}
+		Some(Symbols::Base(base))
+	} else {
+		let mut upper: Vec<SymbolUp> = vec![];
+		for symbol in symbols.iter() {
+			match symbol_to_up(symbol) {
+				Some(sym) => upper.push(sym),
+				None => return None,
+			}
+		}
+		Some(Symbols::Upper(upper))
 	}
 }
 
 
+
 fn remove_one_item(vector: &Vec<u64>, item: &u64) -> Vec<u64> {
 	let mut new_vec = vec![]; 
 	for i in 0..vector.len() {
@@ -166,7 +572,8 @@ fn remove_one_item(vector: &Vec<u64>, item: &u64) -> Vec<u64> {
 
 impl TreeDecoder {
 	//Decode coded Merkle tree after receiving enough symbols on each level
-	pub fn run_tree_decoder(&mut self, symbols_all_levels: Vec<Vec<Symbol>>, indices_all_levels: Vec<Vec<u64>>) 
+	//The received symbols are views of their bytes, e.g. into the layers of a block or into a received buffer
+	pub fn run_tree_decoder(&mut self, symbols_all_levels: &[Vec<&[u8]>], indices_all_levels: &[Vec<u64>]) 
 	//-> Result<Vec<Decoder>, IncorrectCodingProof> {	
 	-> Result<(), IncorrectCodingProof> {
 		//hashes of the symbols being decoded. For top layer, they are stored in the header
@@ -174,42 +581,30 @@ impl TreeDecoder {
 
 		//Iterate decoding starting from the top level of coded Merkle tree
 		for i in (0..self.height).rev() {
-			let received_symbols = symbols_all_levels[i as usize].clone();
-			let received_indices = indices_all_levels[i as usize].clone();
 			//Data reception on level i
 			//Here the variable decoded is used for indicating layer i gets decoded
-			let (mut new_symbols, mut new_symbol_indices, mut decoded) = self.decoders[i as usize].symbol_update_from_reception(
-				received_symbols, received_indices);
+			let (new_symbol_indices, mut decoded) = self.decoders[i as usize].symbol_update_from_reception(
+				&symbols_all_levels[i as usize], &indices_all_levels[i as usize]);
+			for index in new_symbol_indices.iter() {
+				self.observer.received(i, *index);
+			}
 			//Update the parities using the received symbols
-			let mut progress = self.decoders[i as usize].parity_update(new_symbols, new_symbol_indices);
+			let mut progress = self.decoders[i as usize].parity_update_observed(&new_symbol_indices, &mut *self.observer);
 			//parity nodes are updated, now check if there is any incorrect coding
-			for j in 0..self.decoders[i as usize].p {
-				if self.decoders[i as usize].parity_degree[j as usize] == 0 { //all symbols associated to parity node j are known
-					if !symbol_equal_to_zero(self.decoders[i as usize].parity_values[j as usize]) {
-						//construct NotZero incorrect coding proof
-						let error_indices = self.decoders[i as usize].code.parities[j as usize].clone();
-						let mut error_symbols: Vec<Symbol> = vec![];
-						
-						for t in error_indices.iter() {
-							error_symbols.push(self.decoders[i as usize].symbol_values[*t as usize]);
-						}
-						println!("NotZero incorrect coding detected on layer {} for parity equation #{}.",i,j);
-						return Err(self.generate_incorrect_coding_proof(CodingErr::NotZero, i, 
-							j as u64, error_symbols, error_indices, vec![], 1.0));
-					} 
-				}
+			if let Some(proof) = self.not_zero_proof(i) {
+				return Err(proof);
 			}
 
             //Already received all coded symbols and all parity equations are satisfied
 			if decoded {
+				self.observer.layer_decoded(i);
 				if i > 0 {
 					//decoding done for layer i, use the systematic symbols as the hash proof for previous layer, and continue to previous layer
-				    self.hashes[(i-1) as usize] = symbol_to_hash(&self.decoders[i as usize].symbol_values[0..(self.decoders[i as usize].k as usize)].to_vec());
+				    self.hashes[(i-1) as usize] = symbol_to_hash(&self.layout, i - 1, &self.decoders[i as usize].symbol_values);
 				    hash_proof = self.hashes[(i-1) as usize].clone();
 				    continue;	
 				} else {
 					//return Ok(self.decoders.clone());
-					println!("Coded Merkle tree successfully decoded.");
 					return Ok(()); //Entire coded Merkle tree is decoded
 				}							
 			}
@@ -218,39 +613,27 @@ impl TreeDecoder {
 			loop {
 				//check for degree-1 parity nodes, if no such nodes are found, decoding is stalled
 				if progress {
-					let mut decoding_result = self.decoders[i as usize].symbol_update_from_degree_1_parities(&hash_proof);
+					let decoding_result = self.decoders[i as usize].symbol_update_from_degree_1_parities_observed(&hash_proof, 
+						&mut *self.observer);
 					match decoding_result {
-						Ok((dec_syms, dec_sym_indices, finished)) => { //all decoded symbols match their hash values
+						Ok((dec_sym_indices, finished)) => { //all decoded symbols match their hash values
 							//Update the parity values
-							progress = self.decoders[i as usize].parity_update(dec_syms, dec_sym_indices);
+							progress = self.decoders[i as usize].parity_update_observed(&dec_sym_indices, &mut *self.observer);
 							//first check if any "NotZero" errors occur after decoding
 							//If found any, construct NotZero incorrect-coding proof
-							for j in 0..self.decoders[i as usize].p {
-							    if self.decoders[i as usize].parity_degree[j as usize] == 0 { //all symbols associated to this parity are known
-					                if !symbol_equal_to_zero(self.decoders[i as usize].parity_values[j as usize]) {
-					                //construct incorrect coding proof
-						            let error_indices = self.decoders[i as usize].code.parities[j as usize].clone();
-						            let mut error_symbols: Vec<Symbol> = vec![];
-						
-						            for t in error_indices.iter() {
-						            	error_symbols.push(self.decoders[i as usize].symbol_values[*t as usize]);
-						            }
-						            println!("NotZero incorrect coding detected on layer {} for parity equation #{}.",i,j);
-						            return Err(self.generate_incorrect_coding_proof(CodingErr::NotZero, i, 
-						            	j as u64, error_symbols, error_indices, vec![], 1.0));
-					                } 
-				                }
-			                }
+							if let Some(proof) = self.not_zero_proof(i) {
+								return Err(proof);
+							}
 			                if finished { //decoding is correctly done for layer i 
+			                	self.observer.layer_decoded(i);
 			                	if i > 0 { //not the base layer yet
 					            //decoding done for layer i, use the systematic symbols as the hash proof for previous layer
-				                    self.hashes[(i-1) as usize] = symbol_to_hash(&self.decoders[i as usize].symbol_values[0..(self.decoders[i as usize].k as usize)].to_vec());
+				                    self.hashes[(i-1) as usize] = symbol_to_hash(&self.layout, i - 1, &self.decoders[i as usize].symbol_values);
 				                    hash_proof = self.hashes[(i-1) as usize].clone();
 				                    decoded = finished;
 				                    break;
 				                } else { //base layer decoded
 				                	//return Ok(self.decoders.clone());
-				                	println!("Coded Merkle tree successfully decoded.");
 				                	return Ok(());
 				                } 				                
 				            } else { //decoding for layer i needs to continue 
@@ -263,17 +646,22 @@ impl TreeDecoder {
 						}
 					} 
 				} else {
+					//peeling is stalled, try to recover symbols of the stopping set by Gaussian elimination
+					if self.mode == DecodingMode::Inactivation && self.inactivate(i as usize, &hash_proof) {
+						progress = true;
+						continue;
+					}
 					//no more progress can be made, a stopping set is found
 					//construct a Stopped incorrect-coding proof using the indices of the encountered stopping set
 					let mut stopping_set = vec![];
 					for sym_idx in 0..self.decoders[i as usize].n {
-						if let Symbol::Empty = self.decoders[i as usize].symbol_values[sym_idx as usize] {
+						if !self.decoders[i as usize].decoded[sym_idx as usize] {
 							stopping_set.push(sym_idx.clone());
 						}
 					}
 					let stopping_ratio = (stopping_set.len() as f32) / (self.decoders[i as usize].n as f32);
 
-					println!("Hitting a stopping set at layer {}. Decoding failed with a stopping ratio of {}.", i, stopping_ratio);
+					self.observer.stalled(i, stopping_set.len(), stopping_ratio);
 					//panic!("Hitting a stopping set at layer {}. Decoding failured.", i);
 					return Err(self.generate_incorrect_coding_proof(CodingErr::Stopped, i as u32, 
 						    0u64, vec![], vec![], stopping_set, stopping_ratio));
@@ -284,7 +672,6 @@ impl TreeDecoder {
 					continue;
 				} else {
 					//return Ok(self.decoders.clone());
-					println!("Coded Merkle tree successfully decoded.");
 					return Ok(());
 				}
 			} 
@@ -293,15 +680,27 @@ impl TreeDecoder {
 		//Ok(self.decoders.clone())
 	}
 
-    //Initialize the tree decoder
-	pub fn new(codes: Vec<Code>, header_hash: &Vec<H256>) -> Self {
+    //Initialize the tree decoder, using the peeling decoder only
+	pub fn new(codes: &[Arc<Code>], header_hash: &Vec<H256>) -> Self {
+		TreeDecoder::new_with_mode(codes, header_hash, DecodingMode::Peeling)
+	}
+
+	//The codes are shared with the decoder, not copied
+	pub fn new_with_mode(codes: &[Arc<Code>], header_hash: &Vec<H256>, mode: DecodingMode) -> Self {
+		let layout = CmtLayout::from_lengths(codes.iter().map(|code| code.symbols.len() as u64).collect());
+		TreeDecoder::new_with_layout(codes, header_hash, layout, mode)
+	}
+
+	//Initialize the tree decoder for a tree with the rate and aggregation factor of layout, e.g. the layout of a block
+	//built with Block::new_with_coding
+	pub fn new_with_layout(codes: &[Arc<Code>], header_hash: &Vec<H256>, layout: CmtLayout, mode: DecodingMode) -> Self {
 		let num_layers = codes.len();
 		let base_length: u64 = codes[0].symbols.len() as u64;
 		let mut decs: Vec<Decoder> = vec![];
 		let mut hash_list: Vec<Vec<H256>> = vec![];
 		for i in 0..num_layers {
 			let code = &codes[i];
-			let dec: Decoder = Decoder::new(i as u32, code.parities.to_vec(), code.symbols.to_vec());
+			let dec: Decoder = Decoder::new_with_layout(i as u32, code.clone(), &layout);
 			decs.push(dec);
 			hash_list.push(vec![H256::default();code.symbols.len()]);
 		}
@@ -312,23 +711,124 @@ impl TreeDecoder {
 			height: num_layers as u32,
 			decoders: decs,
 			hashes: hash_list,
+			mode: mode,
+			inactivation_recovered: vec![0; num_layers],
+			layout: layout,
+			top_root: None,
+			observer: Box::new(NullObserver),
+		}
+	}
+
+	//Initialize the tree decoder from a block header in either mode, checking the decoded symbols with the hash function of the header
+	//In single-root mode, served are the hashes of the top layer served with the block, and incorrect-coding proofs carry
+	//inclusion proofs under the root. Returns None if the served hashes do not match the root
+	pub fn from_header(codes: &[Arc<Code>], header: &BlockHeader, served: &[H256]) -> Option<Self> {
+		match header.top_hashes(served) {
+			Some(hashes) => {
+				let mut decoder = TreeDecoder::new(codes, &hashes).with_hash_function(header.cmt_params.hash_function);
+				decoder.top_root = header.single_root();
+				Some(decoder)
+			},
+			None => None,
+		}
+	}
+
+	//Decode in another mode, e.g. a decoder created from a block header with inactivation decoding
+	pub fn with_mode(mut self, mode: DecodingMode) -> Self {
+		self.mode = mode;
+		self
+	}
+
+	//Report the decoding steps to an observer, e.g. a LogObserver or a JsonTraceWriter
+	pub fn with_observer(mut self, observer: Box<dyn DecodeObserver>) -> Self {
+		self.observer = observer;
+		self
+	}
+
+	//Check the decoded symbols with the hash function of the tree, double-SHA256 by default
+	//A decoder created from a block header takes the hash function committed by the header instead
+	pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+		for decoder in self.decoders.iter_mut() {
+			decoder.hash_function = hash_function;
+		}
+		self
+	}
+
+	//Prepare the decoder for another block, in the mode and with the hash function of its header as from_header does
+	//In single-root mode, served are the hashes of the top layer served with the block
+	//Returns false, leaving the decoder unchanged, if the served hashes do not match the root
+	pub fn reset(&mut self, header: &BlockHeader, served: &[H256]) -> bool {
+		let hashes = match header.top_hashes(served) {
+			Some(hashes) => hashes,
+			None => return false,
+		};
+		self.reset_top(&hashes, header.single_root());
+		for decoder in self.decoders.iter_mut() {
+			decoder.hash_function = header.cmt_params.hash_function;
+		}
+		true
+	}
+
+	//Prepare the decoder for another block with the hashes of the top layer, and their single root in single-root mode
+	pub(crate) fn reset_top(&mut self, header_hash: &[H256], top_root: Option<H256>) {
+		self.top_root = top_root;
+		for decoder in self.decoders.iter_mut() {
+			decoder.reset();
+		}
+		let top = self.hashes.len() - 1;
+		for hashes in self.hashes[0..top].iter_mut() {
+			for h in hashes.iter_mut() {
+				*h = H256::default();
+			}
+		}
+		self.hashes[top].clear();
+		self.hashes[top].extend_from_slice(header_hash);
+		for count in self.inactivation_recovered.iter_mut() {
+			*count = 0;
 		}
 	}
 
 	//Generate merkle proof for a symbol  
 	pub fn generate_merkle_proof(&self, lvl: usize, index: u64) -> Vec<Symbol> {
-		let header_size = self.hashes.len();
+		self.layout.proof_indices(lvl as u32, index).iter().enumerate()
+			.map(|(i, moving_index)| self.decoders[lvl + i + 1].to_symbol(*moving_index)).collect()
+	}
 
-		let mut proof = Vec::<Symbol>::new();
-		let mut moving_index = index;
-		let mut moving_k = self.decoders[lvl].k;
-		let reduce_factor = ((AGGREGATE as f32) * RATE) as u64;
-		for i in lvl..((self.height - 1) as usize) {
-			moving_index = next_index(moving_index, moving_k, reduce_factor);
-            proof.push(self.decoders[i+1].symbol_values[moving_index as usize].clone());
-            moving_k = moving_k / reduce_factor;
+	//Check if the symbols of a parity equation on layer lvl are all known but do not sum up to zero
+	//If found any, construct NotZero incorrect-coding proof
+	pub fn not_zero_proof(&self, lvl: u32) -> Option<IncorrectCodingProof> {
+		let decoder = &self.decoders[lvl as usize];
+		for j in 0..decoder.p {
+			if decoder.parity_degree[j as usize] == 0 && !is_zero(decoder.parity_values.symbol(j as usize)) {
+				let error_indices = decoder.code.parities[j as usize].clone();
+				let error_symbols: Vec<Symbol> = error_indices.iter().map(|t| decoder.to_symbol(*t)).collect();
+				return Some(self.generate_incorrect_coding_proof(CodingErr::NotZero, lvl, 
+					j as u64, error_symbols, error_indices, vec![], 1.0));
+			}
 		}
-		proof
+		None
+	}
+
+	//Recover the symbols of layer lvl determined by the residual parity equations, and feed them back to the peeling decoder
+	//A recovered symbol that does not match its hash is left undecoded: a NotHash proof needs a single parity equation,
+	//so the mismatch is only reported if peeling later decodes that symbol from a degree-1 parity
+	//Returns false if no symbol can be recovered
+	pub(crate) fn inactivate(&mut self, lvl: usize, hashes: &Vec<H256>) -> bool {
+		let hash_function = self.decoders[lvl].hash_function;
+		let recovered: Vec<(u64, Vec<u8>)> = solve_residual(&self.decoders[lvl]).into_iter()
+			.filter(|&(index, ref bytes)| hashes.get(index as usize) == Some(&hash_function.hash(bytes))).collect();
+		if recovered.is_empty() {
+			return false;
+		}
+		self.inactivation_recovered[lvl] += recovered.len() as u64;
+		let symbols: Vec<&[u8]> = recovered.iter().map(|&(_, ref bytes)| &bytes[..]).collect();
+		let indices: Vec<u64> = recovered.iter().map(|&(index, _)| index).collect();
+		let (indices, _) = self.decoders[lvl].symbol_update_from_reception(&symbols, &indices);
+		for index in indices.iter() {
+			self.observer.inactivated(lvl as u32, *index);
+		}
+		self.decoders[lvl].parity_update_observed(&indices, &mut *self.observer);
+		true
 	}
 
 	pub fn generate_incorrect_coding_proof(&self, err_type: CodingErr, lvl: u32, parity: u64, 
@@ -337,6 +837,14 @@ impl TreeDecoder {
 		for i in 0..indices.len() {
 			merkle_proofs.push(self.generate_merkle_proof(lvl as usize, indices[i]));
 		}
+		let mut top_proofs: Vec<Vec<H256>> = vec![];
+		if self.top_root.is_some() {
+			let top = self.hashes.len() - 1;
+			for i in 0..indices.len() {
+				let top_index = self.layout.proof_indices(lvl, indices[i]).last().cloned().unwrap_or(indices[i]);
+				top_proofs.push(top_proof(&self.decoders[top].hash_function, &self.hashes[top], top_index));
+			}
+		}
 		IncorrectCodingProof {
 			error_type: err_type,
 	        level: lvl,
@@ -344,6 +852,7 @@ impl TreeDecoder {
 	        indices: indices,
 	        parity_index: parity,
 	        proofs: merkle_proofs,
+	        top_proofs: top_proofs,
 	        stop_set: stopping_set,
 	        stop_ratio: stopping_ratio
 	    }
@@ -353,108 +862,125 @@ impl TreeDecoder {
 
 impl Decoder {
 	// Initialize the decoder for a layer of CMT 
-	pub fn new(level: u32, parities: Vec<Vec<u64>>, symbols: Vec<Vec<u64>>) -> Self {
-		let n: u64 = symbols.len() as u64; //number of coded symbols
-		let p: u64 = parities.len() as u64; //number of parity nodes
+	pub fn new(level: u32, code: Arc<Code>) -> Self {
+		let n: u64 = code.symbols.len() as u64; //number of coded symbols
 		let k: u64 = ((n as f32) * RATE) as u64; //number of systematic symbols
+		let symbol_size = if level == 0 { BASE_SYMBOL_SIZE } else { 32 * AGGREGATE };
+		Decoder::new_with_sizes(level, code, k, symbol_size)
+	}
 
-		let mut parity_deg = vec![0u32; p as usize]; //number of variable nodes a parity node is connected to, this changes during peeling decoding
-		for i in 0..(p as usize) {
-			parity_deg[i] = parities[i].len() as u32; 
-		}
+	// Initialize the decoder for layer level of a tree with the rate and the symbol sizes of layout
+	pub fn new_with_layout(level: u32, code: Arc<Code>, layout: &CmtLayout) -> Self {
+		Decoder::new_with_sizes(level, code, layout.k(level), layout.symbol_size(level))
+	}
 
-		let mut parity_val = Vec::<Symbol>::new(); //values of parity nodes
-		let mut symbol_val = Vec::<Symbol>::new(); //values of variable nodes
+	fn new_with_sizes(level: u32, code: Arc<Code>, k: u64, symbol_size: usize) -> Self {
+		let n: u64 = code.symbols.len() as u64; //number of coded symbols
+		let p: u64 = code.parities.len() as u64; //number of parity nodes
+		let mut symbol_values = LayerArena::with_symbol_size(level == 0, symbol_size, n as usize);
+		symbol_values.resize(n as usize);
+		let mut parity_values = LayerArena::with_symbol_size(level == 0, symbol_size, p as usize);
+		parity_values.resize(p as usize);
 
-		match level {
-			0 => {
-				for _ in 0..p {
-					parity_val.push(Symbol::Base([0u8; BASE_SYMBOL_SIZE]));
-				}
-			},
-			_ => {
-				for _ in 0..p {
-					parity_val.push(Symbol::Upper([0u8; 32 * AGGREGATE]));
-				}
-			},
+		let mut decoder = Decoder {
+			level: level, n: n, k: k, p: p,
+			code: code,
+			hash_function: HashFunction::default(),
+			symbol_values: symbol_values,
+			decoded: vec![false; n as usize],
+			parity_values: parity_values,
+			parity_degree: vec![],
+			degree_1_parities: vec![],
+			num_decoded_sys_symbols: 0, num_decoded_symbols: 0,
+		};
+		decoder.reset();
+		decoder
+	}
+
+	// Clear the peeling state to decode another block with the same code, reusing the allocated arenas and vectors
+	pub fn reset(&mut self) {
+		for decoded in self.decoded.iter_mut() {
+			*decoded = false;
 		}
+		self.parity_values.zero();
+		//number of variable nodes a parity node is connected to, this changes during peeling decoding
+		self.parity_degree.clear();
+		let code = &self.code;
+		self.parity_degree.extend(code.parities.iter().map(|parity| parity.len() as u32));
+		self.degree_1_parities.clear();
+		self.num_decoded_sys_symbols = 0;
+		self.num_decoded_symbols = 0;
+	}
 
-		for _ in 0..n {
-			symbol_val.push(Symbol::Empty);
+	// value of a symbol, None if it is not decoded yet
+	pub fn symbol(&self, index: u64) -> Option<&[u8]> {
+		if self.decoded[index as usize] {
+			Some(self.symbol_values.symbol(index as usize))
+		} else {
+			None
 		}
+	}
 
-		Decoder {
-			level: level, n: n, k: k, p: p,
-			code: Code {parities: parities.clone(), symbols: symbols.clone()},
-			parities: parities, symbols: symbols,
-			symbol_values: symbol_val,
-			parity_values: parity_val,
-			parity_degree: parity_deg,
-			degree_1_parities: vec![],
-			num_decoded_sys_symbols: 0, num_decoded_symbols: 0,
+	// copy of a symbol as it is sent in proofs, Empty if it is not decoded yet
+	pub fn to_symbol(&self, index: u64) -> Symbol {
+		match self.symbol(index) {
+			Some(bytes) => Symbol::from_bytes(self.level, bytes),
+			None => Symbol::Empty,
 		}
 	}
 
-    //decode new symbols simply from receiving them
-	pub fn symbol_update_from_reception(&mut self, symbols: Vec<Symbol>, symbol_indices: Vec<u64>) -> (Vec<Symbol>,
-		Vec<u64>, bool) {
-		let mut out_symbols = Vec::<Symbol>::new();
-        let mut out_indices = Vec::<u64>::new();
+    //the undecoded symbol connected to a degree-1 parity node, None if it has been decoded by another parity node
+	fn undecoded_symbol(&self, parity: u64) -> Option<u64> {
+		self.code.parities[parity as usize].iter().cloned().find(|s| !self.decoded[*s as usize])
+	}
 
-        let length = cmp::min(symbols.len(), symbol_indices.len());
-        for i in 0..length {
-        	if let Symbol::Empty = self.symbol_values[(symbol_indices[i] as usize)] {
-        		self.symbol_values[(symbol_indices[i] as usize)] = symbols[i].clone();
-        		self.num_decoded_symbols += 1;
-        		if symbol_indices[i] < self.k {
-        			self.num_decoded_sys_symbols += 1;
-        		}
+	//count a symbol whose value is in symbol_values as decoded
+	fn mark_decoded(&mut self, index: u64) {
+		self.decoded[index as usize] = true;
+		self.num_decoded_symbols += 1;
+		if index < self.k {
+			self.num_decoded_sys_symbols += 1;
+		}
+	}
+
+    //decode new symbols simply from receiving them, as views of their bytes
+    //Returned are the indices of the new symbols, symbols already known, out of range or of another size are ignored
+	pub fn symbol_update_from_reception(&mut self, symbols: &[&[u8]], symbol_indices: &[u64]) -> (Vec<u64>, bool) {
+        let mut out_indices = Vec::<u64>::new();
+        let symbol_size = self.symbol_values.symbol_size();
+        for (symbol, index) in symbols.iter().zip(symbol_indices.iter()) {
+        	if *index < self.n && !self.decoded[*index as usize] && symbol.len() == symbol_size {
+        		self.symbol_values.symbol_mut(*index as usize).copy_from_slice(symbol);
+        		self.mark_decoded(*index);
         		//output the updated symbols for future peeling decoding
         		//the output is a subset of input
-        		out_symbols.push(symbols[i].clone());
-        	    out_indices.push(symbol_indices[i].clone());
+        	    out_indices.push(*index);
         	}        
         }
 
-        (out_symbols, out_indices, self.num_decoded_symbols == self.n)
+        (out_indices, self.num_decoded_symbols == self.n)
 	}
 
     //Update the values of parity nodes using decoded/received symbols
-	pub fn parity_update(&mut self, symbols: Vec<Symbol>, symbol_indices: Vec<u64>) -> bool {
-		if  symbols.len() == 0 {
-			return self.degree_1_parities.len() != 0;
-		}
-		let length = cmp::min(symbols.len(), symbol_indices.len());
-		for i in 0..length {
-			let (s, idx) = (symbols[i].clone(), symbol_indices[i].clone());
-			let parity_list = self.symbols[idx as usize].clone(); // subset of parity nodes that will be affected by symbol s
-			for parity in parity_list.iter() {
-				//Update the value of each parity node symbol s connects to
-				match (self.parity_values[*parity as usize], s) {
-					(Symbol::Base(x), Symbol::Base(y)) => {
-						let mut sum: [u8; BASE_SYMBOL_SIZE] = x.clone();
-						//XOR the symbols with the parity node
-						for j in 0..BASE_SYMBOL_SIZE {
-							sum[j] = sum[j].bitxor(y[j]);
-						} 
-						self.parity_values[*parity as usize] = Symbol::Base(sum);
-					},
-					(Symbol::Upper(x), Symbol::Upper(y)) => {
-						let mut sum: [u8; 32 * AGGREGATE] = x.clone();
-						//XOR the symbols with the parity node
-						for j in 0..(32 * AGGREGATE) {
-							sum[j] = sum[j].bitxor(y[j]);
-						} 
-						self.parity_values[*parity as usize] = Symbol::Upper(sum);
-					},
-					(_, _) => {},
-				}
+	pub fn parity_update(&mut self, symbol_indices: &[u64]) -> bool {
+		self.parity_update_observed(symbol_indices, &mut NullObserver)
+	}
+
+	//Update the values of parity nodes, reporting the check of every parity equation whose symbols are all known
+	pub fn parity_update_observed(&mut self, symbol_indices: &[u64], observer: &mut dyn DecodeObserver) -> bool {
+		let code = self.code.clone();
+		for idx in symbol_indices.iter() {
+			let s = self.symbol_values.symbol(*idx as usize);
+			// parity nodes that will be affected by symbol s, every symbol is added to its parity nodes only once
+			for parity in code.symbols[*idx as usize].iter() {
+					//Update the value of each parity node symbol s connects to, by XORing the symbol with the parity node
+				xor_into(self.parity_values.symbol_mut(*parity as usize), s);
 				self.parity_degree[*parity as usize] -= 1;
 				if self.parity_degree[*parity as usize] == 1 {
                     self.degree_1_parities.push(parity.clone());
+				} else if self.parity_degree[*parity as usize] == 0 {
+					observer.parity_checked(self.level, *parity, is_zero(self.parity_values.symbol(*parity as usize)));
 				}
-				self.parities[*parity as usize] = remove_one_item(&self.parities[*parity as usize], &idx);
-				self.symbols[idx as usize] = remove_one_item(&self.symbols[idx as usize], &parity);
 			}
 		}
 		self.degree_1_parities.len() != 0
@@ -488,52 +1014,47 @@ impl Decoder {
 
     //Decode symbols using values of degree 1 parities. Decoding error may occur if the decoded symbol does not match its hash.
 	pub fn symbol_update_from_degree_1_parities(&mut self, hashes: &Vec<H256>) 
-	-> Result<(Vec<Symbol>, Vec<u64>, bool), (u32, u64, Vec<u64>, Vec<Symbol>)> {
-		let mut symbols = Vec::<Symbol>::new();
+	-> Result<(Vec<u64>, bool), (u32, u64, Vec<u64>, Vec<Symbol>)> {
+		self.symbol_update_from_degree_1_parities_observed(hashes, &mut NullObserver)
+	}
+
+	//Decode symbols using values of degree 1 parities, reporting every decoded symbol and hash mismatch
+	//Returned are the indices of the decoded symbols, whose values are in symbol_values
+	pub fn symbol_update_from_degree_1_parities_observed(&mut self, hashes: &Vec<H256>, observer: &mut dyn DecodeObserver) 
+	-> Result<(Vec<u64>, bool), (u32, u64, Vec<u64>, Vec<Symbol>)> {
         let mut symbol_indices = Vec::<u64>::new();
 
         for i in 0..self.degree_1_parities.len() {
         	let parity = self.degree_1_parities[i].clone();
-        	if self.parities[parity as usize].len() > 0 {
-        		let symbol_idx = self.parities[parity as usize][0];
-        		// The only symbol connected to this parity node has not been decoded yet
-        		if let Symbol::Empty = self.symbol_values[symbol_idx as usize] {
-        			self.symbol_values[symbol_idx as usize] = self.parity_values[parity as usize]; //Symbol decoded
+        	// The only symbol connected to this parity node that has not been decoded yet
+        	if let Some(symbol_idx) = self.undecoded_symbol(parity) {
+        			self.symbol_values.symbol_mut(symbol_idx as usize).copy_from_slice(self.parity_values.symbol(parity as usize)); //Symbol decoded
 
         			//now check if the decoded symbol matches its hash
-        			let mut computed_hash = H256::default();
-        			match self.symbol_values[symbol_idx as usize] {
-        				Symbol::Base(decoded_sym) => {computed_hash = dhash256(&decoded_sym);},
-        				Symbol::Upper(decoded_sym) => {computed_hash = dhash256(&decoded_sym);},
-        				_ => {}
-        			}
+        			let computed_hash = self.hash_function.hash(self.symbol_values.symbol(symbol_idx as usize));
         			if computed_hash == hashes[symbol_idx as usize] {
-        				self.num_decoded_symbols += 1; 
-        			    if symbol_idx < self.k {
-                            self.num_decoded_sys_symbols += 1;
-                        }
-        			    symbols.push(self.parity_values[parity as usize].clone());
+        				self.mark_decoded(symbol_idx);
                         symbol_indices.push(symbol_idx.clone());
+                        observer.peeled(self.level, symbol_idx, parity);
                     } else {//coding is done incorrectly, return an incorrect-coding message
-                    	println!("NotHash incorrect coding detected on layer {} for parity equation #{}.",self.level,parity);
+                    	observer.hash_mismatch(self.level, symbol_idx, parity);
                     	// Preparing info for constructing incorrect-coding proof
                     	let index_set: Vec<u64> = self.code.parities[parity as usize].clone();
                     	let mut correct_index_set: Vec<u64> = remove_one_item(&index_set, &symbol_idx);
                     	let mut symbols_in_proof: Vec<Symbol> = vec![];
                     	for j in 0..correct_index_set.len() {
-                    		symbols_in_proof.push(self.symbol_values[j]);
+                    		symbols_in_proof.push(self.to_symbol(correct_index_set[j]));
                     	}
                     	correct_index_set.push(symbol_idx);
                     	return Err((self.level, parity, correct_index_set, symbols_in_proof));
                     }
-                } 
             }
         }
         // value of the coded symbol connected to a degree 1 parity node is updated
         // remove this connection and set the degree_1_parities set to empty
         self.degree_1_parities = vec![];
 
-        Ok((symbols, symbol_indices, self.num_decoded_symbols == self.n))
+        Ok((symbol_indices, self.num_decoded_symbols == self.n))
     }
 
 
@@ -551,37 +1072,29 @@ impl Decoder {
     
     //this function encodes systematic symbols to obtain parity symbols, using the same decoding process
     //obtain new parity symbols through decoding from systematic symbols
-	pub fn symbol_update_from_degree_1_parities_encode(&mut self) -> (Vec<Symbol>, Vec<u64>, bool) {
-		let mut symbols = Vec::<Symbol>::new();
+	pub fn symbol_update_from_degree_1_parities_encode(&mut self) -> (Vec<u64>, bool) {
         let mut symbol_indices = Vec::<u64>::new();
 
         for i in 0..self.degree_1_parities.len() {
         	let parity = self.degree_1_parities[i].clone();
-        	if self.parities[parity as usize].len() > 0 {
-        		let symbol_idx = self.parities[parity as usize][0];
-        		if let Symbol::Empty = self.symbol_values[symbol_idx as usize] {
-        			self.symbol_values[symbol_idx as usize] = self.parity_values[parity as usize]; //Symbol decoded
-        			self.num_decoded_symbols += 1; 
-        			if symbol_idx < self.k {
-                        self.num_decoded_sys_symbols += 1;
-                    }
-        			symbols.push(self.parity_values[parity as usize].clone());
+        	if let Some(symbol_idx) = self.undecoded_symbol(parity) {
+        			self.symbol_values.symbol_mut(symbol_idx as usize).copy_from_slice(self.parity_values.symbol(parity as usize)); //Symbol decoded
+        			self.mark_decoded(symbol_idx);
                     symbol_indices.push(symbol_idx.clone());                    
-                } 
             }
         }
         self.degree_1_parities = vec![];
 
-        (symbols, symbol_indices, self.num_decoded_symbols == self.n)
+        (symbol_indices, self.num_decoded_symbols == self.n)
     }
 
 	pub fn peeling_encode(&mut self) -> bool {
 		// iterative encoding/decoding until all parity symbols are found
 		loop {
-			let (symbols, symbol_indices, encoded) = self.symbol_update_from_degree_1_parities_encode();
+			let (symbol_indices, encoded) = self.symbol_update_from_degree_1_parities_encode();
 			if encoded { return encoded; }
-			if symbols.len() > 0 { // new symbols get decoded
-				let keep_peeling = self.parity_update(symbols, symbol_indices); 
+			if symbol_indices.len() > 0 { // new symbols get decoded
+				let keep_peeling = self.parity_update(&symbol_indices); 
 				//println!("The current parities vector is {:?}", self.parities);
 				//println!("The set of degree-1 parity nodes are {:?}.", self.degree_1_parities);
 				//println!("It is {} that more parity values are available.", keep_peeling);
@@ -595,76 +1108,298 @@ impl Decoder {
 
 	//Encoding by decoding all parity symbols from systematic symbols
 	//The variable "correct" indicates if the encoding will be done correctly
-	pub fn encode(&mut self, sys_symbols: Vec<Symbol>, correct: bool) -> Vec<Symbol> {
-		let mut indices = vec![];//indices of sysmtematic symbols
-		for i in 0..self.k {
-			indices.push(i as u64);
+	//If not, the bits of the 1st parity symbol (kth symbol overall) are flipped
+	pub fn encode(&mut self, sys_symbols: LayerArena, correct: bool) -> LayerArena {
+		let mut errors = vec![];
+		if !correct {
+			errors.push(InjectedError {layer: self.level, index: self.k, pattern: ErrorPattern::FlipAll});
 		}
-		// to start, feed systematic symbols into the decoder
-		let (symbols, symbol_indices, encoded) = self.symbol_update_from_reception(sys_symbols, indices);
-		loop {
-			if encoded {
-			    //println!("All {} coded symbols are received.", self.n);	
-				break;
-			}
-			// more degree 1 parity nodes are available, we can continue encoding
-			if self.parity_update(symbols, symbol_indices) {
-			    //println!("The current parities vector is {:?}.", self.parities);
-			    //println!("Now we have these degree-1 parity nodes: {:?}.", self.degree_1_parities);
-
-			    // n parity symbols are created, since decoding/encoding will always be successful from the systematic symbols
-				if self.peeling_encode() {break;}
-				else {unreachable!();} //encoding will succeed if all systematic symbols are given
-			}
-			else {
-				unreachable!(); //encoding will succeed if all systematic symbols are given
+		self.encode_with_errors(sys_symbols, &errors)
+	}
+
+	//Encoding by decoding all parity symbols from the k systematic symbols of a layer, then corrupting the coded symbols
+	//as specified by the errors on this layer. Errors on other layers are ignored
+	//The parity symbols are written in place after the systematic symbols, and the arena is returned as the coded layer.
+	//The decoder is left reset
+	//Panics if an error on this layer is out of range, errors from users are checked first with InjectedError::check
+	pub fn encode_with_errors(&mut self, sys_symbols: LayerArena, errors: &[InjectedError]) -> LayerArena {
+		assert_eq!(sys_symbols.len() as u64, self.k, "the encoder takes the systematic symbols of the layer");
+		assert_eq!(sys_symbols.symbol_size(), self.symbol_values.symbol_size(), "systematic symbols of another layer");
+		self.reset();
+		// to start, the systematic symbols are the first values of the decoder
+		let mut layer = sys_symbols;
+		layer.resize(self.n as usize);
+		mem::swap(&mut self.symbol_values, &mut layer);
+		let indices: Vec<u64> = (0..self.k).collect();
+		for i in indices.iter() {
+			self.mark_decoded(*i);
+		}
+		let encoded = self.num_decoded_symbols == self.n;
+		// n parity symbols are created, since decoding/encoding will always be successful from the systematic symbols
+		if !encoded && !(self.parity_update(&indices) && self.peeling_encode()) {
+			unreachable!(); //encoding will succeed if all systematic symbols are given
+		}
+		mem::swap(&mut self.symbol_values, &mut layer);
+		self.reset();
+		for error in errors.iter().filter(|e| e.layer == self.level) {
+			//The coded symbol is maliciously modified
+			if let Err(e) = inject_error(&mut layer, error.index, &error.pattern) {
+				panic!("invalid error {:?}: {}", error, e);
 			}
 		}
-		let mut output_symbols = self.symbol_values.clone();
-		if !correct { // flip the 1st parity symbol (kth symbol overall)
-			if self.level == 0 { //This is base layer
-				// let mut systematic = [0u8; BASE_SYMBOL_SIZE];
-				// for j in 0..BASE_SYMBOL_SIZE {
-				// 	let die = Uniform::from(0u8..=255u8);
-				// 	systematic[j] = die.sample(&mut rand::thread_rng());
-			 //    }
-			 //    let mut parity = [0u8; BASE_SYMBOL_SIZE];
-				// for l in 0..BASE_SYMBOL_SIZE {
-				// 	let die = Uniform::from(0u8..=255u8);
-				// 	parity[l] = die.sample(&mut rand::thread_rng());
-			 //    }
-			    let mut parity = [0u8; BASE_SYMBOL_SIZE];
-			    if let Symbol::Base(sym) = self.symbol_values[self.k as usize] {
-			    	for l in 0..BASE_SYMBOL_SIZE {
-					    parity[l] = sym[l].bitxor(255u8);
-					}
-			    }
-			    //The first parity symbol is maliciously modified
-			    //output_symbols[0] = Symbol::Base(systematic);
-			    output_symbols[self.k as usize] = Symbol::Base(parity);
-			} else { //This is higher layer
-				// let mut systematic = [0u8; 32 * AGGREGATE];
-				// for j in 0..(32 * AGGREGATE) {
-				// 	let die = Uniform::from(0u8..=255u8);
-				// 	systematic[j] = die.sample(&mut rand::thread_rng());
-			 //    }
-			 //    let mut parity = [0u8; 32 * AGGREGATE];
-				// for l in 0..(32 * AGGREGATE) {
-				// 	let die = Uniform::from(0u8..=255u8);
-				// 	parity[l] = die.sample(&mut rand::thread_rng());
-			 //    }
-			    let mut parity_up = [0u8; 32 * AGGREGATE];
-			    if let Symbol::Upper(sym_up) = self.symbol_values[self.k as usize] {
-			    	for l in 0..(32 * AGGREGATE) {
-					    parity_up[l] = sym_up[l].bitxor(255u8);
-					}
-			    }
-			    //The first parity symbol is maliciously modified
-			    //output_symbols[0] = Symbol::Upper(systematic);
-			    output_symbols[self.k as usize] = Symbol::Upper(parity_up);
+		layer
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use block::Block;
+	use fixtures::{codes_dir, header, transactions, test_block};
+	use test_helpers::{samples_without, decode_without};
+	use codes::{read_codes, shared_codes};
+	use ser::{serialize, deserialize, deserialize_borrowed};
+	use super::*;
+
+	#[test]
+	fn test_symbol_serialization() {
+		let mut upper = [0u8; 32 * AGGREGATE];
+		upper[40] = 9;
+		for symbol in vec![Symbol::Base([3u8; BASE_SYMBOL_SIZE]), Symbol::Upper(upper), Symbol::Empty].into_iter() {
+			let bytes = serialize(&symbol);
+			assert_eq!(deserialize::<_, Symbol>(&bytes as &[u8]), Ok(symbol));
+		}
+		assert_eq!(serialize(&Symbol::Base([3u8; BASE_SYMBOL_SIZE])).len(), 1 + BASE_SYMBOL_SIZE);
+		assert_eq!(deserialize::<_, Symbol>(&[3u8] as &[u8]), Err(Error::MalformedData));
+
+		let symbols = Symbols::Upper(vec![symbol_to_up(&Symbol::Upper(upper)).unwrap(); 2]);
+		let bytes = serialize(&symbols);
+		assert_eq!(bytes.len(), 1 + 1 + 2 * 32 * AGGREGATE);
+		assert_eq!(deserialize::<_, Symbols>(&bytes as &[u8]), Ok(symbols));
+		let symbols = Symbols::Base(vec![[1u8; BASE_SYMBOL_SIZE]]);
+		assert_eq!(deserialize::<_, Symbols>(&serialize(&symbols) as &[u8]), Ok(symbols));
+	}
+
+	// Codes and incorrect-coding proofs sent over the network are restored and still verify
+	#[test]
+	fn test_code_and_proof_serialization() {
+		let mut rng = StdRng::seed_from_u64(14);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		for code in codes_for_decoding.iter() {
+			assert_eq!(deserialize::<_, Code>(&serialize(code) as &[u8]).as_ref(), Ok(code));
+		}
+		let forged = Code { parities: vec![vec![0, 1], vec![1, 4]], symbols: vec![vec![0], vec![0, 1], vec![], vec![]] };
+		assert_eq!(deserialize::<_, Code>(&serialize(&forged) as &[u8]), Err(Error::MalformedData));
+
+		let errors = vec![InjectedError {layer: 1, index: 100, pattern: ErrorPattern::FlipAll}];
+		let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+			&errors, &mut rng);
+		for missing in vec![None, Some((1, 100))].into_iter() {
+			let proof = decode_without(&block, &codes_for_decoding, missing).unwrap_err();
+			let received: IncorrectCodingProof = deserialize(&serialize(&proof) as &[u8]).unwrap();
+			assert_eq!(received, proof);
+			assert!(block.block_header.verify_incorrect_coding_proof(&codes_for_decoding, &received));
+		}
+
+		// a stopping set, with inclusion proofs of a single-root header
+		let block = test_block(&mut rng).with_single_root();
+		let decoder = TreeDecoder::from_header(&shared_codes(&codes_for_decoding), &block.block_header, &block.top_hashes()).unwrap();
+		let proof = decoder.generate_incorrect_coding_proof(CodingErr::Stopped, 0, 0, vec![], vec![3], vec![3, 7], 0.5);
+		assert_eq!(proof.top_proofs.len(), 1);
+		assert_eq!(deserialize::<_, IncorrectCodingProof>(&serialize(&proof) as &[u8]), Ok(proof.clone()));
+
+		// a Merkle proof longer than any tree, and a list of symbols beyond the limits of the reader
+		let mut forged = proof;
+		forged.proofs = vec![vec![Symbol::Empty; MAX_CMT_LAYERS + 1]];
+		assert_eq!(deserialize::<_, IncorrectCodingProof>(&serialize(&forged) as &[u8]), Err(Error::MalformedData));
+		let bytes = [2u8, 0, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 0xff];
+		assert_eq!(deserialize::<_, IncorrectCodingProof>(&bytes as &[u8]), Err(Error::LimitExceeded));
+	}
+
+	// Proofs are verified in the received buffer, their symbols and inclusion proofs are not copied
+	#[test]
+	fn test_borrowed_proof() {
+		let mut rng = StdRng::seed_from_u64(15);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let errors = vec![InjectedError {layer: 0, index: 300, pattern: ErrorPattern::FlipAll}];
+		let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+			&errors, &mut rng).with_single_root();
+		let mut decoder = TreeDecoder::from_header(&shared_codes(&codes_for_decoding), &block.block_header, &block.top_hashes()).unwrap();
+		let (symbols_all_levels, indices_all_levels) = samples_without(&block, Some((0, 300)));
+		let proof = decoder.run_tree_decoder(&symbols_all_levels, &indices_all_levels).unwrap_err();
+		assert!(!proof.top_proofs.is_empty());
+
+		let buffer = serialize(&proof);
+		let received: IncorrectCodingProofRef = deserialize_borrowed(&buffer).unwrap();
+		assert_eq!(received, proof.as_borrowed());
+		assert_eq!(received.to_proof(), proof);
+		let range = buffer.as_ptr() as usize..buffer.as_ptr() as usize + buffer.len();
+		match received.symbols[0] {
+			SymbolRef::Base(sym) => assert!(range.contains(&(sym.as_ptr() as usize))),
+			_ => panic!("a base symbol is expected"),
+		}
+		assert!(range.contains(&(received.top_proofs[0].as_ptr() as usize)));
+		assert!(block.block_header.verify_incorrect_coding_proof_ref(&codes_for_decoding, &received));
+
+		let mut forged = received.clone();
+		forged.symbols.swap(0, 1);
+		assert!(!block.block_header.verify_incorrect_coding_proof_ref(&codes_for_decoding, &forged));
+		let mut forged = proof;
+		forged.proofs = vec![vec![Symbol::Empty; MAX_CMT_LAYERS + 1]];
+		let buffer = serialize(&forged);
+		assert_eq!(deserialize_borrowed::<IncorrectCodingProofRef>(&buffer), Err(Error::MalformedData));
+	}
+
+	#[test]
+	fn test_inject_error_patterns() {
+		let original = LayerArena::from_symbols(&[Symbol::Base([1u8; BASE_SYMBOL_SIZE]), Symbol::Base([2u8; BASE_SYMBOL_SIZE])]).unwrap();
+		let base = |symbols: &LayerArena, i: usize| symbols.base_symbol(i);
+
+		let mut symbols = original.clone();
+		inject_error(&mut symbols, 0, &ErrorPattern::FlipAll).unwrap();
+		assert!(base(&symbols, 0).iter().all(|b| *b == 254u8));
+
+		let mut symbols = original.clone();
+		inject_error(&mut symbols, 1, &ErrorPattern::FlipBits(vec![0, 9])).unwrap();
+		assert_eq!(base(&symbols, 1)[0], 3u8);
+		assert_eq!(base(&symbols, 1)[1], 0u8);
+		assert!(base(&symbols, 1)[2..].iter().all(|b| *b == 2u8));
+
+		let mut symbols = original.clone();
+		inject_error(&mut symbols, 0, &ErrorPattern::Zero).unwrap();
+		assert!(is_zero(symbols.symbol(0)));
+
+		let mut symbols = original.clone();
+		inject_error(&mut symbols, 0, &ErrorPattern::SwapWith(1)).unwrap();
+		assert_eq!(base(&symbols, 0)[0], 2u8);
+		assert_eq!(base(&symbols, 1)[0], 1u8);
+
+		let mut first = original.clone();
+		let mut second = original.clone();
+		inject_error(&mut first, 0, &ErrorPattern::Random(9)).unwrap();
+		inject_error(&mut second, 0, &ErrorPattern::Random(9)).unwrap();
+		assert_eq!(base(&first, 0).to_vec(), base(&second, 0).to_vec());
+		assert!(base(&first, 0).to_vec() != base(&original, 0).to_vec());
+
+		// errors out of range are rejected and leave the symbols unchanged
+		let mut symbols = original.clone();
+		assert!(inject_error(&mut symbols, 2, &ErrorPattern::FlipAll).is_err());
+		assert!(inject_error(&mut symbols, 0, &ErrorPattern::SwapWith(2)).is_err());
+		assert!(inject_error(&mut symbols, 1, &ErrorPattern::FlipBits(vec![0, 8 * BASE_SYMBOL_SIZE])).is_err());
+		assert_eq!(symbols, original);
+		inject_error(&mut symbols, 1, &ErrorPattern::FlipBits(vec![8 * BASE_SYMBOL_SIZE - 1])).unwrap();
+		assert_eq!(base(&symbols, 1)[BASE_SYMBOL_SIZE - 1], 130u8);
+
+		// and checked against the layout of a tree before encoding
+		let layout = CmtLayout::new(256, 16);
+		let error = |layer: u32, index: u64, pattern: ErrorPattern| InjectedError { layer: layer, index: index, pattern: pattern };
+		assert_eq!(error(1, layout.n(1) - 1, ErrorPattern::FlipBits(vec![8 * 32 * AGGREGATE - 1])).check(&layout), Ok(()));
+		assert!(error(layout.height() as u32, 0, ErrorPattern::FlipAll).check(&layout).is_err());
+		assert!(error(1, layout.n(1), ErrorPattern::FlipAll).check(&layout).is_err());
+		assert!(error(0, 0, ErrorPattern::SwapWith(layout.n(0))).check(&layout).is_err());
+		assert!(error(1, 0, ErrorPattern::FlipBits(vec![8 * 32 * AGGREGATE])).check(&layout).is_err());
+	}
+
+	#[test]
+	fn test_parse_error_patterns() {
+		assert_eq!("flip-all".parse(), Ok(ErrorPattern::FlipAll));
+		assert_eq!("zero".parse(), Ok(ErrorPattern::Zero));
+		assert_eq!("random:9".parse(), Ok(ErrorPattern::Random(9)));
+		assert_eq!("swap:3".parse(), Ok(ErrorPattern::SwapWith(3)));
+		assert_eq!("flip-bits:0,9".parse(), Ok(ErrorPattern::FlipBits(vec![0, 9])));
+		assert!("flip-all:1".parse::<ErrorPattern>().is_err());
+		assert!("flip-bits:0,x".parse::<ErrorPattern>().is_err());
+		assert!("swap".parse::<ErrorPattern>().is_err());
+	}
+
+	// A corrupted symbol is detected as NotZero when all symbols are received,
+	// and as NotHash when it is decoded from the other symbols
+	#[test]
+	fn test_injected_errors_are_detected_on_every_layer() {
+		let mut rng = StdRng::seed_from_u64(6);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let cases = vec![
+			(0, 0, ErrorPattern::FlipBits(vec![5])),
+			(0, 200, ErrorPattern::Random(1)),
+			(1, 3, ErrorPattern::Zero),
+			(1, 100, ErrorPattern::FlipAll),
+		];
+		for (layer, index, pattern) in cases.into_iter() {
+			let errors = vec![InjectedError {layer: layer, index: index, pattern: pattern}];
+			let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+				&errors, &mut rng);
+			let detected = |result: Result<(), IncorrectCodingProof>| match result {
+				Err(proof) => (proof.error_type, proof.level),
+				Ok(()) => panic!("incorrect coding on layer {} is not detected", layer),
+			};
+			assert_eq!(detected(decode_without(&block, &codes_for_decoding, None)), (CodingErr::NotZero, layer));
+			assert_eq!(detected(decode_without(&block, &codes_for_decoding, Some((layer as usize, index)))), (CodingErr::NotHash, layer));
+		}
+	}
+
+	// A decoder reset for another block decodes it as a new decoder does, and all decoders share the codes
+	#[test]
+	fn test_reset_decoder_for_another_block() {
+		let mut rng = StdRng::seed_from_u64(16);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes = shared_codes(&codes);
+		let first = test_block(&mut rng);
+		let second = test_block(&mut rng);
+		fn samples(block: &Block) -> (Vec<Vec<&[u8]>>, Vec<Vec<u64>>) {
+			let indices: Vec<Vec<u64>> = (0..block.coded_tree.len())
+				.map(|lvl| (1..block.layer_length(lvl) as u64).collect())
+				.collect();
+			block.collect_samples(&indices)
+		}
+
+		let mut decoder = TreeDecoder::new(&codes, &first.block_header.coded_merkle_roots_hashes);
+		let (symbols, indices) = samples(&first);
+		assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+		assert!(decoder.reset(&second.block_header, &[]));
+		let mut fresh = TreeDecoder::new(&codes, &second.block_header.coded_merkle_roots_hashes);
+		assert_eq!(Arc::strong_count(&codes[0]), 3);
+		assert!(Arc::ptr_eq(&decoder.decoders[1].code, &fresh.decoders[1].code));
+
+		let (symbols, indices) = samples(&second);
+		assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+		assert!(fresh.run_tree_decoder(&symbols, &indices).is_ok());
+		assert_eq!(decoder.hashes, fresh.hashes);
+		for lvl in 0..2 {
+			for i in 0..second.layer_length(lvl) {
+				assert_eq!(decoder.decoders[lvl].symbol(i as u64), Some(second.coded_tree[lvl].symbol(i)));
+				assert_eq!(fresh.decoders[lvl].symbol(i as u64), Some(second.coded_tree[lvl].symbol(i)));
 			}
 		}
-		output_symbols
+	}
+
+	// A decoder reset for a block with a single-root header stays in single-root mode, and proofs keep their inclusion proofs
+	#[test]
+	fn test_reset_decoder_in_single_root_mode() {
+		let mut rng = StdRng::seed_from_u64(17);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes = shared_codes(&codes_for_decoding);
+		let first = test_block(&mut rng).with_single_root();
+		let errors = vec![InjectedError {layer: 0, index: 300, pattern: ErrorPattern::FlipAll}];
+		let second = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding, 
+			&errors, &mut rng).with_single_root();
+
+		let mut decoder = TreeDecoder::from_header(&codes, &first.block_header, &first.top_hashes()).unwrap();
+		assert!(!decoder.reset(&second.block_header, &first.top_hashes()));
+		assert_eq!(decoder.top_root, first.block_header.single_root());
+		assert!(decoder.reset(&second.block_header, &second.top_hashes()));
+		assert_eq!(decoder.top_root, second.block_header.single_root());
+
+		let (symbols, indices) = samples_without(&second, Some((0, 300)));
+		let proof = decoder.run_tree_decoder(&symbols, &indices).unwrap_err();
+		assert_eq!(proof.top_proofs.len(), proof.indices.len());
+		assert!(second.block_header.verify_incorrect_coding_proof(&codes_for_decoding, &proof));
+
+		// a header with all hashes of the top layer leaves single-root mode
+		let mut header = first.block_header.clone();
+		header.coded_merkle_roots_hashes = first.top_hashes();
+		assert!(decoder.reset(&header, &[]));
+		assert_eq!(decoder.top_root, None);
 	}
 }
 
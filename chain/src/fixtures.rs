@@ -0,0 +1,37 @@
+//! A small block and the codes of its coded Merkle tree, shared by the tests of the crate and its benchmarks
+
+use rand::Rng;
+use hash::H256;
+use constants::BASE_SYMBOL_SIZE;
+use block::Block;
+use block_header::BlockHeader;
+use transaction::Transaction;
+use layout::CmtParams;
+use codes::read_codes;
+
+pub fn codes_dir() -> String {
+	format!("{}/src/LDPC_codes", env!("CARGO_MANIFEST_DIR"))
+}
+
+pub fn header() -> BlockHeader {
+	BlockHeader {
+		version: 1,
+		previous_header_hash: H256::default(),
+		merkle_root_hash: H256::default(),
+		time: 4,
+		bits: 5.into(),
+		nonce: 6,
+		coded_merkle_roots_hashes: vec![],
+		cmt_params: CmtParams::default(),
+	}
+}
+
+pub fn transactions() -> Vec<Transaction> {
+	vec!["0100000001a6b97044d03da79c005b20ea9c0e1a6d9dc12d9f7b91a5911c9030a439eed8f5000000004948304502206e21798a42fae0e854281abd38bacd1aeed3ee3738d9e1446618c4571d1090db022100e2ac980643b0b82c0e88ffdfec6b64e3e6ba35e7ba5fdd7d5d6cc8d25c6b241501ffffffff0100f2052a010000001976a914404371705fa9bd789a2fcd52d2c580b65d35549d88ac00000000".into()]
+}
+
+// a correctly coded block with two layers, 512 coded symbols on the base layer and 256 hashes in the header
+pub fn test_block<R: Rng>(rng: &mut R) -> Block {
+	let (codes, _) = read_codes(&codes_dir(), vec![128, 64]);
+	Block::new(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes, vec![true; 2], rng)
+}
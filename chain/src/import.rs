@@ -0,0 +1,410 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+use hex::FromHex;
+use rand::Rng;
+use crypto::dhash256;
+use ser::{Reader, Error, Serializable};
+use hash::H256;
+use block::Block;
+use block_header::BlockHeader;
+use transaction::Transaction;
+use constants::{BASE_SYMBOL_SIZE, RATE, MAX_LAYER_LENGTH};
+use layout::{CmtLayout, CmtParams};
+use cmt_hash::HashFunction;
+use codes::{read_codes, shared_codes, missing_code_file};
+use coded_merkle_roots::errors_from_correct;
+use decoder::{Code, TreeDecoder};
+use sampling::SamplingParams;
+use merkle_root::merkle_root;
+
+// Size of a Bitcoin block header in bytes
+pub const BITCOIN_HEADER_SIZE: usize = 80;
+
+// Largest serialized size of a Bitcoin block, witnesses included, since segwit
+pub const MAX_BITCOIN_BLOCK_SIZE: usize = 4_000_000;
+
+// Magic bytes starting every block in a blk*.dat file, for mainnet, testnet3, regtest and signet
+pub const BLK_MAGICS: [[u8; 4]; 4] = [
+	[0xf9, 0xbe, 0xb4, 0xd9],
+	[0x0b, 0x11, 0x09, 0x07],
+	[0xfa, 0xbf, 0xb5, 0xda],
+	[0x0a, 0x03, 0xcf, 0x40],
+];
+
+#[derive(Debug)]
+pub enum ImportError {
+	Io(io::Error),
+	Hex, // the block is not a hex string
+	Magic([u8; 4]), // a block in a blk*.dat file does not start with the magic bytes of a network
+	Size(usize), // the size of a block in a blk*.dat file exceeds MAX_BITCOIN_BLOCK_SIZE
+	Block(Error), // the block cannot be deserialized
+}
+
+impl From<io::Error> for ImportError {
+	fn from(err: io::Error) -> Self {
+		ImportError::Io(err)
+	}
+}
+
+// A Bitcoin block, with its header read into a header without coded Merkle tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedBlock {
+	pub hash: H256, // hash of the Bitcoin header, in internal byte order
+	pub header: BlockHeader,
+	pub transactions: Vec<Transaction>,
+	pub size: usize, // size of the serialized block, with witnesses
+}
+
+impl ImportedBlock {
+	// Read a block serialized as in the Bitcoin protocol: an 80-byte header and the transactions
+	pub fn from_bytes(data: &[u8]) -> Result<Self, ImportError> {
+		if data.len() < BITCOIN_HEADER_SIZE {
+			return Err(ImportError::Block(Error::UnexpectedEnd));
+		}
+		let mut reader = Reader::new(data);
+		let header = BlockHeader {
+			version: reader.read().map_err(ImportError::Block)?,
+			previous_header_hash: reader.read().map_err(ImportError::Block)?,
+			merkle_root_hash: reader.read().map_err(ImportError::Block)?,
+			time: reader.read().map_err(ImportError::Block)?,
+			bits: reader.read().map_err(ImportError::Block)?,
+			nonce: reader.read().map_err(ImportError::Block)?,
+			coded_merkle_roots_hashes: vec![],
+			cmt_params: CmtParams::default(),
+		};
+		let transactions: Vec<Transaction> = reader.read_list().map_err(ImportError::Block)?;
+		if !reader.is_finished() {
+			return Err(ImportError::Block(Error::UnreadData));
+		}
+		Ok(ImportedBlock {
+			hash: dhash256(&data[..BITCOIN_HEADER_SIZE]),
+			header: header,
+			transactions: transactions,
+			size: data.len(),
+		})
+	}
+
+	pub fn from_hex(hex: &str) -> Result<Self, ImportError> {
+		let data: Vec<u8> = hex.trim().from_hex().map_err(|_| ImportError::Hex)?;
+		ImportedBlock::from_bytes(&data)
+	}
+
+	// Whether the transactions match the Merkle root of the header
+	pub fn merkle_root_is_valid(&self) -> bool {
+		let hashes: Vec<H256> = self.transactions.iter().map(Transaction::hash).collect();
+		!hashes.is_empty() && merkle_root(&hashes) == self.header.merkle_root_hash
+	}
+
+	// size in bytes of the transactions encoded in the coded Merkle tree, without witnesses
+	pub fn coded_size(&self) -> usize {
+		self.transactions.iter().map(|tx| tx.bytes().len()).sum()
+	}
+}
+
+// Reader of the blocks stored in a blk*.dat file of Bitcoin Core
+// Every block is preceded by the magic bytes of its network and its size as a 4-byte little-endian integer.
+// Bitcoin Core obfuscates the files with the 8-byte key in blocks/xor.dat since version 28, see with_xor_key
+pub struct BlkFileReader<R: Read> {
+	reader: R,
+	xor_key: Option<[u8; 8]>,
+	offset: u64, // position in the file, from which the byte of the key is taken
+	finished: bool,
+}
+
+impl<R: Read> BlkFileReader<R> {
+	pub fn new(reader: R) -> Self {
+		BlkFileReader {
+			reader: reader,
+			xor_key: None,
+			offset: 0,
+			finished: false,
+		}
+	}
+
+	pub fn with_xor_key(mut self, key: [u8; 8]) -> Self {
+		// a key of zeros leaves the file as it is
+		self.xor_key = if key == [0u8; 8] { None } else { Some(key) };
+		self
+	}
+
+	// Fill buf, returns false at the end of the file
+	fn read_exact_or_end(&mut self, buf: &mut [u8]) -> Result<bool, ImportError> {
+		let mut read = 0;
+		while read < buf.len() {
+			match self.reader.read(&mut buf[read..]) {
+				Ok(0) if read == 0 => return Ok(false),
+				Ok(0) => return Err(ImportError::Io(io::ErrorKind::UnexpectedEof.into())),
+				Ok(n) => read += n,
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+				Err(err) => return Err(ImportError::Io(err)),
+			}
+		}
+		if let Some(key) = self.xor_key {
+			for (i, byte) in buf.iter_mut().enumerate() {
+				*byte ^= key[((self.offset + i as u64) % 8) as usize];
+			}
+		}
+		self.offset += buf.len() as u64;
+		Ok(true)
+	}
+
+	fn next_block(&mut self) -> Result<Option<ImportedBlock>, ImportError> {
+		let mut magic = [0u8; 4];
+		// Bitcoin Core preallocates the files, the unused end of a file is zeros
+		if !self.read_exact_or_end(&mut magic)? || magic == [0u8; 4] {
+			return Ok(None);
+		}
+		if !BLK_MAGICS.contains(&magic) {
+			return Err(ImportError::Magic(magic));
+		}
+		let mut size = [0u8; 4];
+		if !self.read_exact_or_end(&mut size)? {
+			return Err(ImportError::Io(io::ErrorKind::UnexpectedEof.into()));
+		}
+		// the size is checked before allocating, as the file is not trusted
+		let size = u32::from_le_bytes(size) as usize;
+		if size > MAX_BITCOIN_BLOCK_SIZE {
+			return Err(ImportError::Size(size));
+		}
+		let mut data = vec![0u8; size];
+		if !self.read_exact_or_end(&mut data)? && !data.is_empty() {
+			return Err(ImportError::Io(io::ErrorKind::UnexpectedEof.into()));
+		}
+		ImportedBlock::from_bytes(&data).map(Some)
+	}
+}
+
+// Blocks in the order of the file, the reader stops after an error
+impl<R: Read> Iterator for BlkFileReader<R> {
+	type Item = Result<ImportedBlock, ImportError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.finished {
+			return None;
+		}
+		let result = self.next_block();
+		match result {
+			Ok(Some(block)) => Some(Ok(block)),
+			Ok(None) => {
+				self.finished = true;
+				None
+			},
+			Err(err) => {
+				self.finished = true;
+				Some(Err(err))
+			},
+		}
+	}
+}
+
+// Parameters of the coded Merkle trees built for imported blocks
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportParams {
+	pub code_dir: String,
+	pub header_size: u32,
+	pub hash_function: HashFunction,
+	pub samples: u32, // number of symbols sampled from the base layer in a decoding trial
+	pub trials: u32,
+}
+
+// Coding and proof statistics of the coded Merkle tree of an imported block
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportReport {
+	pub hash: H256,
+	pub transactions: usize,
+	pub size: usize, // size of the serialized block, with witnesses
+	pub coded_size: usize, // size of the transactions in the tree, without witnesses
+	pub padding: usize, // random bytes added to the transactions to fill the systematic symbols of the base layer
+	pub merkle_root_valid: bool,
+	pub layers: usize,
+	pub base_symbols: u64,
+	pub decoded: u32, // number of trials decoding the whole tree from params.samples base samples
+	pub bytes_downloaded: u64, // by all trials
+	// size in bytes of the incorrect-coding proof of a corrupted parity symbol on each layer
+	pub proof_sizes: Vec<usize>,
+}
+
+// Layout of the smallest tree holding size bytes whose codes are all in code_dir
+// The code files only exist for some numbers of systematic symbols, so a block may be padded to a larger tree
+// Returns an error naming the missing code file of the tree without padding if there is no such tree
+fn smallest_layout(code_dir: &str, size: usize, header_size: u32) -> Result<CmtLayout, String> {
+	let needed = CmtLayout::for_block(size, header_size as u64);
+	let mut layout = needed.clone();
+	loop {
+		let k_set: Vec<u64> = (0..layout.height()).map(|lvl| layout.k(lvl as u32)).collect();
+		if missing_code_file(code_dir, &k_set).is_none() {
+			return Ok(layout);
+		}
+		if layout.n(0) >= MAX_LAYER_LENGTH as u64 {
+			let k_set: Vec<u64> = (0..needed.height()).map(|lvl| needed.k(lvl as u32)).collect();
+			return Err(format!("no codes for a block of {} bytes in {}, missing {}", size, code_dir,
+				missing_code_file(code_dir, &k_set).unwrap_or_default()));
+		}
+		layout = CmtLayout::new(layout.k(0) * 2, header_size as u64);
+	}
+}
+
+// Build the coded Merkle tree of an imported block, and measure its decoding from samples and its incorrect-coding proofs
+// The transactions are padded with random bytes up to the smallest tree whose codes are in params.code_dir
+pub fn report_block<R: Rng>(block: &ImportedBlock, params: &ImportParams, rng: &mut R) -> Result<ImportReport, String> {
+	let coded_size = block.coded_size();
+	let layout = smallest_layout(&params.code_dir, coded_size, params.header_size)?;
+	let block_size = (layout.k(0) as usize) * BASE_SYMBOL_SIZE;
+	let k_set: Vec<u64> = (0..layout.height()).map(|lvl| layout.k(lvl as u32)).collect();
+	let (codes_for_encoding, codes_for_decoding) = read_codes(&params.code_dir, k_set);
+	let codes_for_decoding: Vec<Arc<Code>> = shared_codes(&codes_for_decoding);
+	let encode = |correct: Vec<bool>, rng: &mut R| {
+		let errors = errors_from_correct(&correct, &codes_for_encoding, RATE);
+		Block::new_with_hash_function(block.header.clone(), &block.transactions, block_size, params.header_size, &codes_for_encoding,
+			&errors, params.hash_function, rng)
+	};
+
+	let coded = encode(vec![true; layout.height()], rng);
+	let sampling = SamplingParams::with_replacement(params.samples);
+	let mut decoded = 0;
+	let mut bytes_downloaded = 0;
+	for _ in 0..params.trials {
+		let (symbols, indices, stats) = coded.sample(&sampling, rng);
+		let mut decoder = TreeDecoder::new(&codes_for_decoding, &coded.block_header.coded_merkle_roots_hashes)
+			.with_hash_function(coded.hash_function());
+		if decoder.run_tree_decoder(&symbols, &indices).is_ok() {
+			decoded += 1;
+		}
+		bytes_downloaded += stats.bytes_downloaded;
+	}
+
+	// a full node receiving every symbol of a tree with a corrupted parity symbol generates a proof
+	let mut proof_sizes = vec![];
+	for lvl in 0..layout.height() {
+		let mut correct = vec![true; layout.height()];
+		correct[lvl] = false;
+		let corrupted = encode(correct, rng);
+		let indices: Vec<Vec<u64>> = (0..layout.height()).map(|l| (0..layout.n(l as u32)).collect()).collect();
+		let symbols: Vec<Vec<&[u8]>> = indices.iter().enumerate()
+			.map(|(l, layer)| layer.iter().map(|i| corrupted.coded_tree[l].symbol(*i as usize)).collect())
+			.collect();
+		let mut decoder = TreeDecoder::new(&codes_for_decoding, &corrupted.block_header.coded_merkle_roots_hashes)
+			.with_hash_function(corrupted.hash_function());
+		match decoder.run_tree_decoder(&symbols, &indices) {
+			Err(proof) => proof_sizes.push(proof.serialized_size()),
+			Ok(()) => return Err(format!("the corrupted symbol on layer {} is not detected", lvl)),
+		}
+	}
+
+	Ok(ImportReport {
+		hash: block.hash,
+		transactions: block.transactions.len(),
+		size: block.size,
+		coded_size: coded_size,
+		padding: block_size - coded_size,
+		merkle_root_valid: block.merkle_root_is_valid(),
+		layers: layout.height(),
+		base_symbols: layout.n(0),
+		decoded: decoded,
+		bytes_downloaded: bytes_downloaded,
+		proof_sizes: proof_sizes,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use fixtures::codes_dir;
+	use super::*;
+
+	// the genesis block of mainnet
+	const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c0101000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+	fn blk_file(blocks: &[&[u8]]) -> Vec<u8> {
+		let mut file = vec![];
+		for block in blocks.iter() {
+			file.extend_from_slice(&BLK_MAGICS[0]);
+			file.extend_from_slice(&(block.len() as u32).to_le_bytes());
+			file.extend_from_slice(block);
+		}
+		file
+	}
+
+	#[test]
+	fn test_import_genesis_block() {
+		let block = ImportedBlock::from_hex(GENESIS).unwrap();
+		assert_eq!(block.hash.to_reversed_str(), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+		assert_eq!((block.transactions.len(), block.size, block.coded_size()), (1, 285, 204));
+		assert_eq!(block.header.time, 1231006505);
+		assert!(block.merkle_root_is_valid());
+
+		let data: Vec<u8> = GENESIS.from_hex().unwrap();
+		let mut changed = data.clone();
+		changed[200] ^= 1;
+		assert!(!ImportedBlock::from_bytes(&changed).unwrap().merkle_root_is_valid());
+		assert!(match ImportedBlock::from_bytes(&data[..284]) { Err(ImportError::Block(Error::UnexpectedEnd)) => true, _ => false });
+		assert!(match ImportedBlock::from_hex("0x") { Err(ImportError::Hex) => true, _ => false });
+	}
+
+	#[test]
+	fn test_blk_file_reader() {
+		let data: Vec<u8> = GENESIS.from_hex().unwrap();
+		let mut file = blk_file(&[&data, &data]);
+		// the preallocated end of the file
+		file.extend_from_slice(&[0u8; 16]);
+		let blocks: Vec<ImportedBlock> = BlkFileReader::new(&file[..]).map(Result::unwrap).collect();
+		assert_eq!(blocks.len(), 2);
+		assert_eq!(blocks[1], ImportedBlock::from_bytes(&data).unwrap());
+
+		// an obfuscated file
+		let key = [1u8, 2, 3, 4, 5, 6, 7, 8];
+		let obfuscated: Vec<u8> = file.iter().enumerate().map(|(i, b)| b ^ key[i % 8]).collect();
+		assert_eq!(BlkFileReader::new(&obfuscated[..]).with_xor_key(key).map(Result::unwrap).collect::<Vec<_>>(), blocks);
+
+		// an unknown network, and a truncated block
+		let mut file = blk_file(&[&data]);
+		file[0] = 0;
+		let mut reader = BlkFileReader::new(&file[..]);
+		assert!(match reader.next() { Some(Err(ImportError::Magic(_))) => true, _ => false });
+		assert!(reader.next().is_none());
+		let file = blk_file(&[&data]);
+		assert!(match BlkFileReader::new(&file[..file.len() - 1]).next() { Some(Err(ImportError::Io(_))) => true, _ => false });
+
+		// a forged size is rejected before the block is read
+		let mut file = blk_file(&[&data]);
+		file[4..8].copy_from_slice(&u32::max_value().to_le_bytes());
+		let mut reader = BlkFileReader::new(&file[..]);
+		assert!(match reader.next() { Some(Err(ImportError::Size(size))) => size == u32::max_value() as usize, _ => false });
+		assert!(reader.next().is_none());
+		file[4..8].copy_from_slice(&(MAX_BITCOIN_BLOCK_SIZE as u32 + 1).to_le_bytes());
+		assert!(match BlkFileReader::new(&file[..]).next() { Some(Err(ImportError::Size(_))) => true, _ => false });
+	}
+
+	#[test]
+	fn test_report_block() {
+		let mut rng = StdRng::seed_from_u64(3);
+		let block = ImportedBlock::from_hex(GENESIS).unwrap();
+		let params = ImportParams {
+			code_dir: codes_dir(),
+			header_size: 256,
+			hash_function: HashFunction::default(),
+			samples: 20,
+			trials: 4,
+		};
+		// the genesis block is padded to the smallest tree with codes, of 4 systematic symbols
+		let report = report_block(&block, &params, &mut rng).unwrap();
+		assert_eq!((report.layers, report.base_symbols, report.padding), (1, 16, 4 * BASE_SYMBOL_SIZE - 204));
+		assert_eq!(report.decoded, 4);
+		assert_eq!(report.proof_sizes.len(), 1);
+		assert!(report.merkle_root_valid);
+
+		// a block of 32 systematic symbols is padded to 64, as there is no code with k=32
+		let mut large = block.clone();
+		large.transactions = vec![block.transactions[0].clone(); 40];
+		let report = report_block(&large, &params, &mut rng).unwrap();
+		assert_eq!((report.base_symbols, report.padding), (256, 64 * BASE_SYMBOL_SIZE - 40 * 204));
+		large.transactions = vec![block.transactions[0].clone(); 200];
+		let report = report_block(&large, &params, &mut rng).unwrap();
+		assert_eq!((report.layers, report.base_symbols, report.proof_sizes.len()), (3, 1024, 3));
+		assert!(report.proof_sizes[0] > report.proof_sizes[2]);
+
+		let params = ImportParams { code_dir: "missing".to_string(), ..params };
+		assert!(report_block(&block, &params, &mut rng).unwrap_err().contains("missing/k=1_encode.txt"));
+	}
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::ops::BitXor;
+use decoder::Decoder;
+use symbol_ops::xor_into;
+
+// Maximum-likelihood erasure decoding of a layer of CMT over GF(2)
+// When the peeling decoder stalls, the parity equations restricted to the undecoded symbols still form a linear system,
+// whose value is the XOR of the known symbols of each equation. Gaussian elimination on this residual system
+// recovers every undecoded symbol that the received symbols determine, including symbols in a stopping set.
+
+// Decoding algorithm used by the tree decoder on each layer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecodingMode {
+	Peeling, // stop as soon as no degree-1 parity node is left
+	Inactivation, // fall back to Gaussian elimination on the residual equations when peeling stalls
+}
+
+// a residual parity equation: the undecoded symbols it contains as a bit vector, and the value they sum up to
+struct Equation {
+	unknowns: Vec<u64>,
+	value: Vec<u8>,
+}
+
+impl Equation {
+	fn contains(&self, column: usize) -> bool {
+		(self.unknowns[column / 64] >> (column % 64)) & 1 == 1
+	}
+
+	fn add(&mut self, other: &Equation) {
+		for (w, o) in self.unknowns.iter_mut().zip(other.unknowns.iter()) {
+			*w = w.bitxor(*o);
+		}
+		xor_into(&mut self.value, &other.value);
+	}
+
+	fn num_unknowns(&self) -> u32 {
+		self.unknowns.iter().map(|w| w.count_ones()).sum()
+	}
+}
+
+// Solve the residual system of a stalled peeling decoder
+// Returned are the undecoded symbols that are uniquely determined by the system, with the bytes of their values
+pub fn solve_residual(decoder: &Decoder) -> Vec<(u64, Vec<u8>)> {
+	let unknowns: Vec<u64> = (0..decoder.n).filter(|i| !decoder.decoded[*i as usize]).collect();
+	if unknowns.is_empty() {
+		return vec![];
+	}
+	let column: HashMap<u64, usize> = unknowns.iter().enumerate().map(|(c, i)| (*i, c)).collect();
+	let words = (unknowns.len() + 63) / 64;
+
+	// each parity node still connected to undecoded symbols gives an equation
+	let mut equations: Vec<Equation> = vec![];
+	for p in 0..(decoder.p as usize) {
+		if decoder.parity_degree[p] == 0 {
+			continue;
+		}
+		let mut equation = Equation { unknowns: vec![0u64; words], value: decoder.parity_values.symbol(p).to_vec() };
+		for s in decoder.code.parities[p].iter() {
+			if let Some(c) = column.get(s) {
+				equation.unknowns[c / 64] ^= 1u64 << (c % 64);
+			}
+		}
+		equations.push(equation);
+	}
+
+	// Gauss-Jordan elimination, pivots are pairs of a column and the equation it is the pivot of
+	let mut pivots: Vec<(usize, usize)> = vec![];
+	let mut rank = 0;
+	for c in 0..unknowns.len() {
+		if rank == equations.len() {
+			break;
+		}
+		let pivot = match (rank..equations.len()).find(|r| equations[*r].contains(c)) {
+			Some(r) => r,
+			None => continue, // symbol c is not determined by the pivots so far
+		};
+		equations.swap(rank, pivot);
+		let (before, rest) = equations.split_at_mut(rank);
+		let (row, after) = rest.split_first_mut().unwrap();
+		for other in before.iter_mut().chain(after.iter_mut()) {
+			if other.contains(c) {
+				other.add(row);
+			}
+		}
+		pivots.push((c, rank));
+		rank += 1;
+	}
+
+	// a symbol is determined if its equation in reduced form does not involve any free symbol
+	pivots.iter()
+		.filter(|&&(_, r)| equations[r].num_unknowns() == 1)
+		.map(|&(c, r)| (unknowns[c], equations[r].value.clone()))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use fixtures::{codes_dir, test_block};
+	use std::sync::Arc;
+	use codes::{read_codes, shared_codes};
+	use decoder::{Code, TreeDecoder, CodingErr};
+	use sampling::draw_indices;
+	use constants::AGGREGATE;
+	use super::*;
+
+	fn receive(decoder: &mut Decoder, index: u64, value: &[u8]) {
+		decoder.symbol_update_from_reception(&[value], &[index]);
+		decoder.parity_update(&[index]);
+	}
+
+	#[test]
+	fn test_solve_residual_recovers_stopping_set() {
+		// parity equations s0 + s1 + s3 = 0, s1 + s2 = 0, s0 + s1 + s2 = 0
+		let parities = vec![vec![0, 1, 3], vec![1, 2], vec![0, 1, 2]];
+		let symbols = vec![vec![0, 2], vec![0, 1, 2], vec![1, 2], vec![0]];
+		let mut decoder = Decoder::new(1, Arc::new(Code { parities: parities, symbols: symbols }));
+		let value = [7u8; 32 * AGGREGATE];
+		receive(&mut decoder, 3, &value);
+		// every parity node is still connected to two undecoded symbols, so peeling cannot start
+		assert!(decoder.degree_1_parities.is_empty());
+
+		let mut recovered = solve_residual(&decoder);
+		recovered.sort_by_key(|r| r.0);
+		assert_eq!(recovered.iter().map(|r| r.0).collect::<Vec<u64>>(), vec![0, 1, 2]);
+		assert_eq!(recovered[0].1, vec![0u8; 32 * AGGREGATE]);
+		assert_eq!(recovered[1].1, value.to_vec());
+		assert_eq!(recovered[2].1, value.to_vec());
+	}
+
+	#[test]
+	fn test_solve_residual_leaves_undetermined_symbols() {
+		// parity equations s0 + s1 = 0, s1 + s2 = 0, s0 + s2 = 0 only determine the symbols up to a common value
+		let parities = vec![vec![0, 1], vec![1, 2], vec![0, 2]];
+		let symbols = vec![vec![0, 2], vec![0, 1], vec![1, 2]];
+		let decoder = Decoder::new(1, Arc::new(Code { parities: parities, symbols: symbols }));
+		assert!(solve_residual(&decoder).is_empty());
+	}
+
+	// Half of the base layer is erased, which stops the peeling decoder but still determines the layer
+	#[test]
+	fn test_inactivation_decodes_beyond_stopping_sets() {
+		let mut rng = StdRng::seed_from_u64(9);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes = shared_codes(&codes);
+		let n = block.layer_length(0) as u64;
+		let erased = draw_indices(n, (n / 2) as u32, false, &mut rng);
+		let received = vec![(0..n).filter(|i| !erased.contains(i)).collect(), (0..block.layer_length(1) as u64).collect()];
+		let (symbols_all_levels, indices_all_levels) = block.collect_samples(&received);
+
+		let mut peeling = TreeDecoder::new(&codes, &block.block_header.coded_merkle_roots_hashes);
+		match peeling.run_tree_decoder(&symbols_all_levels, &indices_all_levels) {
+			Err(proof) => assert_eq!((proof.error_type, proof.level), (CodingErr::Stopped, 0)),
+			Ok(()) => panic!("peeling should stop on half of the base layer"),
+		}
+
+		let mut inactivation = TreeDecoder::new_with_mode(&codes, &block.block_header.coded_merkle_roots_hashes, 
+			DecodingMode::Inactivation);
+		assert!(inactivation.run_tree_decoder(&symbols_all_levels, &indices_all_levels).is_ok());
+		assert!(inactivation.inactivation_recovered[0] > 0);
+		for i in 0..n {
+			assert_eq!(inactivation.decoders[0].symbol(i), Some(block.coded_tree[0].symbol(i as usize)));
+		}
+	}
+}
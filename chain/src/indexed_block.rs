@@ -9,6 +9,7 @@ use indexed_header::IndexedBlockHeader;
 use indexed_transaction::IndexedTransaction;
 use constants::{BLOCK_SIZE, HEADER_SIZE};
 use bytes::Bytes;
+use rand::Rng;
 
 #[derive(Debug, Clone, Deserializable)]
 pub struct IndexedBlock {
@@ -40,7 +41,7 @@ impl IndexedBlock {
 	///
 	/// Hashes block header + transactions.
 	pub fn from_raw(block: Block) -> Self {
-		let Block { block_header, transactions, coded_tree, block_size_in_bytes} = block;
+		let Block { block_header, transactions, .. } = block;
 		Self::new(
 			IndexedBlockHeader::from_raw(block_header),
 			transactions.into_iter().map(IndexedTransaction::from_raw).collect(),
@@ -51,9 +52,9 @@ impl IndexedBlock {
 		&self.header.hash
 	}
 
-	pub fn to_raw_block(self) -> Block {
+	pub fn to_raw_block<R: Rng>(self, rng: &mut R) -> Block {
 		Block::new(self.header.raw, &self.transactions.into_iter().map(|tx| tx.raw).collect(), 
-			BLOCK_SIZE as usize, HEADER_SIZE, &vec![], vec![])
+			BLOCK_SIZE as usize, HEADER_SIZE, &vec![], vec![], rng)
 	}
 
 	pub fn size(&self) -> usize {
@@ -103,11 +104,11 @@ mod tests {
 
 	#[test]
 	fn size_with_witness_not_equal_to_size() {
-		let block_without_witness: IndexedBlock = "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000000000100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000".into();
+		let block_without_witness: IndexedBlock = "000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000001000000000100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000".into();
 		assert_eq!(block_without_witness.size(), block_without_witness.size_with_witness());
 
 		// bip143 block
-		let block_with_witness: IndexedBlock = "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000010100000000000000000000000000000000000000000000000000000000000000000000000000000000000001010000000000".into();
+		let block_with_witness: IndexedBlock = "0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000010100000000000000000000000000000000000000000000000000000000000000000000000000000000000001010000000000".into();
 		assert!(block_with_witness.size() != block_with_witness.size_with_witness());
 	}
 }
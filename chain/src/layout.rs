@@ -0,0 +1,337 @@
+use std::io;
+use std::ops::Range;
+use ser::{Deserializable, Reader, Error};
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, MAX_CMT_LAYERS};
+use cmt_hash::HashFunction;
+use coded_merkle_roots::padded_length;
+
+// Parameters of a coded Merkle tree committed in the block header, from which verifiers derive its geometry
+// and the hash function of its Merkle proofs. The height of the tree is the number of layer lengths
+#[derive(Debug, Default, Clone, PartialEq, Serializable)]
+pub struct CmtParams {
+	pub base_k: u32, // number of systematic symbols on the base layer
+	pub layer_lengths: Vec<u32>, // number of coded symbols on each layer, starting from the base layer
+	pub symbol_size: u32, // size of a base symbol in bytes
+	pub aggregate: u32, // number of hashes aggregated in a symbol of an upper layer
+	pub hash_function: HashFunction, // hash function of the coded symbols and of the single root
+}
+
+// A tree has at most MAX_CMT_LAYERS layers
+impl Deserializable for CmtParams {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		Ok(CmtParams {
+			base_k: reader.read()?,
+			layer_lengths: reader.read_list_max(MAX_CMT_LAYERS)?,
+			symbol_size: reader.read()?,
+			aggregate: reader.read()?,
+			hash_function: reader.read()?,
+		})
+	}
+}
+
+// Geometry of a coded Merkle tree, and navigation between the symbols of its layers
+// Layer 0 is the base layer. On every layer the first k of the n coded symbols are systematic.
+// The hashes of the n coded symbols of a layer are aggregated into the k systematic symbols of the layer above:
+// the parent of a systematic symbol holds reduce_factor systematic hashes, and aggregate - reduce_factor parity hashes.
+// The hashes of the top layer are the hashes in the block header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CmtLayout {
+	n: Vec<u64>, // number of coded symbols on each layer
+	k: Vec<u64>, // number of systematic symbols on each layer
+	aggregate: u64, // number of hashes in a symbol of an upper layer
+	reduce_factor: u64, // number of systematic hashes aggregated in a symbol of the layer above
+}
+
+impl CmtLayout {
+	// Layout of the tree built from base_k systematic base symbols, up to a top layer of at most header_size symbols
+	pub fn new(base_k: u64, header_size: u64) -> Self {
+		CmtLayout::from_lengths(layer_lengths(base_k, header_size, RATE, ((AGGREGATE as f32) * RATE) as u64))
+	}
+
+	// Layout of the tree built from base_k systematic base symbols with another rate and aggregation factor,
+	// e.g. for a sweep over reference designs. None if they do not describe a valid tree, or one whose layers do not shrink
+	pub fn with_coding(base_k: u64, header_size: u64, rate: f32, aggregate: u64) -> Option<Self> {
+		let reduce_factor = ((aggregate as f32) * rate) as u64;
+		if reduce_factor < 2 {
+			return None;
+		}
+		let lengths = layer_lengths(base_k, header_size, rate, reduce_factor);
+		if lengths.iter().any(|n| *n > u32::MAX as u64) {
+			return None;
+		}
+		CmtLayout::from_params(&CmtParams {
+			base_k: base_k as u32,
+			layer_lengths: lengths.iter().map(|n| *n as u32).collect(),
+			symbol_size: BASE_SYMBOL_SIZE as u32,
+			aggregate: aggregate as u32,
+			hash_function: HashFunction::default(),
+		})
+	}
+
+	// Layout of the tree of a block with size bytes of transactions, as built by Block::coded_merkle_roots:
+	// the base layer is padded to a power of two systematic symbols
+	pub fn for_block(size: usize, header_size: u64) -> Self {
+		let base_k = size.div_ceil(BASE_SYMBOL_SIZE) as u64;
+		CmtLayout::new(base_k.next_power_of_two(), header_size)
+	}
+
+	// for_block with another rate and aggregation factor, as built by Block::new_with_coding:
+	// the base layer is padded so that each layer above can have a whole number of symbols
+	pub fn for_block_with_coding(size: usize, header_size: u64, rate: f32, aggregate: u64) -> Option<Self> {
+		// the padding grows by the reduce factor, which must be at least 2 as in with_coding
+		if (((aggregate as f32) * rate) as u64) < 2 {
+			return None;
+		}
+		let base_k = padded_length(size.div_ceil(BASE_SYMBOL_SIZE), rate, aggregate as usize) as u64;
+		CmtLayout::with_coding(base_k, header_size, rate, aggregate)
+	}
+
+	// Layout of a tree with the given numbers of coded symbols on each layer, e.g. the lengths of the codes
+	pub fn from_lengths(n: Vec<u64>) -> Self {
+		CmtLayout::from_coded_lengths(n, RATE, AGGREGATE as u64)
+	}
+
+	// from_lengths for a tree of another rate and aggregation factor
+	pub fn from_coded_lengths(n: Vec<u64>, rate: f32, aggregate: u64) -> Self {
+		let k = n.iter().map(|n| ((*n as f32) * rate) as u64).collect();
+		CmtLayout {
+			n: n,
+			k: k,
+			aggregate: aggregate,
+			reduce_factor: ((aggregate as f32) * rate) as u64,
+		}
+	}
+
+	// Layout committed by the parameters of a tree, None if they do not describe a valid tree:
+	// all layers have the rate of the base layer, and the coded symbols of a layer are hashed into the systematic symbols of the next
+	pub fn from_params(params: &CmtParams) -> Option<Self> {
+		let n: Vec<u64> = params.layer_lengths.iter().map(|n| *n as u64).collect();
+		let (base_k, aggregate) = (params.base_k as u64, params.aggregate as u64);
+		if n.is_empty() || n[0] == 0 || base_k == 0 || base_k >= n[0] || params.symbol_size == 0 {
+			return None;
+		}
+		// systematic hashes in an upper symbol, the rate of the code times aggregate
+		if (aggregate * base_k) % n[0] != 0 {
+			return None;
+		}
+		let reduce_factor = aggregate * base_k / n[0];
+		if reduce_factor == 0 || reduce_factor >= aggregate {
+			return None;
+		}
+		let mut k = vec![];
+		for lvl in 0..n.len() {
+			if (n[lvl] * base_k) % n[0] != 0 {
+				return None;
+			}
+			k.push(n[lvl] * base_k / n[0]);
+			if lvl > 0 && k[lvl] * aggregate != n[lvl - 1] {
+				return None;
+			}
+		}
+		Some(CmtLayout {
+			n: n,
+			k: k,
+			aggregate: aggregate,
+			reduce_factor: reduce_factor,
+		})
+	}
+
+	// Parameters committing to this layout and hash function, for base symbols of BASE_SYMBOL_SIZE bytes
+	pub fn params(&self, hash_function: HashFunction) -> CmtParams {
+		CmtParams {
+			base_k: self.k[0] as u32,
+			layer_lengths: self.n.iter().map(|n| *n as u32).collect(),
+			symbol_size: BASE_SYMBOL_SIZE as u32,
+			aggregate: self.aggregate as u32,
+			hash_function: hash_function,
+		}
+	}
+
+	// number of layers
+	pub fn height(&self) -> usize {
+		self.n.len()
+	}
+
+	pub fn n(&self, lvl: u32) -> u64 {
+		self.n[lvl as usize]
+	}
+
+	pub fn k(&self, lvl: u32) -> u64 {
+		self.k[lvl as usize]
+	}
+
+	// number of hashes in the header
+	pub fn header_size(&self) -> u64 {
+		self.n[self.n.len() - 1]
+	}
+
+	pub fn aggregate(&self) -> u64 {
+		self.aggregate
+	}
+
+	// fraction of systematic symbols on every layer
+	pub fn rate(&self) -> f32 {
+		(self.reduce_factor as f32) / (self.aggregate as f32)
+	}
+
+	// size in bytes of a symbol on layer lvl: a base symbol, or the aggregated hashes of an upper symbol
+	pub fn symbol_size(&self, lvl: u32) -> usize {
+		if lvl == 0 { BASE_SYMBOL_SIZE } else { 32 * (self.aggregate as usize) }
+	}
+
+	pub fn reduce_factor(&self) -> u64 {
+		self.reduce_factor
+	}
+
+	pub fn is_top(&self, lvl: u32) -> bool {
+		(lvl as usize) + 1 == self.n.len()
+	}
+
+	pub fn is_systematic(&self, lvl: u32, index: u64) -> bool {
+		index < self.k(lvl)
+	}
+
+	// index of the parent symbol on layer lvl + 1
+	pub fn parent(&self, lvl: u32, index: u64) -> u64 {
+		let k = self.k(lvl);
+		if index < k {
+			index / self.reduce_factor
+		} else {
+			(index - k) / (self.aggregate - self.reduce_factor)
+		}
+	}
+
+	// position of the hash of a symbol within its parent symbol
+	pub fn hash_slot(&self, lvl: u32, index: u64) -> usize {
+		let k = self.k(lvl);
+		if index < k {
+			(index % self.reduce_factor) as usize
+		} else {
+			((index - k) % (self.aggregate - self.reduce_factor) + self.reduce_factor) as usize
+		}
+	}
+
+	// indices of the symbols on layer lvl - 1 whose hashes are aggregated in symbol index, in the order of their hash slots
+	pub fn children(&self, lvl: u32, index: u64) -> Vec<u64> {
+		assert!(lvl > 0, "symbols on the base layer have no children");
+		let k = self.k(lvl - 1);
+		let parity = self.aggregate - self.reduce_factor;
+		(index * self.reduce_factor .. (index + 1) * self.reduce_factor)
+			.chain(k + index * parity .. k + (index + 1) * parity).collect()
+	}
+
+	// parity symbols of layer lvl sharing the parent of symbol index, all parity symbols on the top layer
+	pub fn parity_siblings(&self, lvl: u32, index: u64) -> Range<u64> {
+		let (k, n) = (self.k(lvl), self.n(lvl));
+		if self.is_top(lvl) {
+			return k..n;
+		}
+		let parity = self.aggregate - self.reduce_factor;
+		let start = k + self.parent(lvl, index) * parity;
+		start.min(n)..(start + parity).min(n)
+	}
+
+	// indices of the symbols in the Merkle proof of symbol index on layer lvl, one on each layer above
+	pub fn proof_indices(&self, lvl: u32, index: u64) -> Vec<u64> {
+		let mut indices = vec![];
+		let mut current = index;
+		for l in lvl..((self.height() - 1) as u32) {
+			current = self.parent(l, current);
+			indices.push(current);
+		}
+		indices
+	}
+}
+
+// numbers of coded symbols on the layers of a tree built from base_k systematic base symbols,
+// up to a top layer of at most header_size symbols
+pub(crate) fn layer_lengths(base_k: u64, header_size: u64, rate: f32, reduce_factor: u64) -> Vec<u64> {
+	let mut lengths = vec![];
+	let mut k = base_k;
+	loop {
+		let n = ((k as f32) / rate) as u64;
+		lengths.push(n);
+		if n <= header_size || k / reduce_factor == 0 {
+			break;
+		}
+		k /= reduce_factor;
+	}
+	lengths
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_layout_of_reference_tree() {
+		let layout = CmtLayout::new(512, 256);
+		assert_eq!(layout, CmtLayout::from_lengths(vec![2048, 1024, 512, 256]));
+		assert_eq!((layout.height(), layout.k(2), layout.header_size()), (4, 128, 256));
+		assert_eq!(CmtLayout::new(128, 256).height(), 2);
+		assert_eq!(CmtLayout::new(128, 0).height(), 8);
+		assert_eq!(CmtLayout::for_block(131072, 256), layout);
+		assert_eq!(CmtLayout::for_block(131073, 256).n(0), 4096);
+
+		// every symbol is a child of its parent, at its hash slot
+		for lvl in 0..3 {
+			for index in 0..layout.n(lvl) {
+				let children = layout.children(lvl + 1, layout.parent(lvl, index));
+				assert_eq!(children[layout.hash_slot(lvl, index)], index);
+			}
+		}
+		assert_eq!(layout.children(1, 0), vec![0, 1, 512, 513, 514, 515, 516, 517]);
+
+		// parity siblings have the same parent
+		assert_eq!(layout.parity_siblings(0, 3), 518..524);
+		assert!(layout.parity_siblings(1, 700).all(|s| layout.parent(1, s) == layout.parent(1, 700)));
+		assert_eq!(layout.parity_siblings(3, 5), 64..256);
+		assert_eq!(layout.proof_indices(0, 1000), vec![81, 40, 20]);
+		assert_eq!(layout.proof_indices(3, 7), vec![]);
+	}
+
+	#[test]
+	fn test_layout_from_params() {
+		let layout = CmtLayout::new(128, 256);
+		let params = layout.params(HashFunction::Blake2b);
+		assert_eq!(params, CmtParams { base_k: 128, layer_lengths: vec![512, 256], symbol_size: BASE_SYMBOL_SIZE as u32, 
+			aggregate: AGGREGATE as u32, hash_function: HashFunction::Blake2b });
+		assert_eq!(CmtLayout::from_params(&params), Some(layout));
+
+		// a rate of 1/2 and an aggregation of 4 hashes
+		let half = CmtParams { base_k: 64, layer_lengths: vec![128, 64, 32], symbol_size: 100, aggregate: 4, ..CmtParams::default() };
+		let layout = CmtLayout::from_params(&half).unwrap();
+		assert_eq!((layout.k(1), layout.reduce_factor()), (32, 2));
+		assert_eq!(layout.children(1, 1), vec![2, 3, 66, 67]);
+
+		let invalid = vec![
+			CmtParams::default(),
+			CmtParams { layer_lengths: vec![128, 60], ..half.clone() },
+			CmtParams { base_k: 128, ..half.clone() },
+			CmtParams { aggregate: 1, ..half.clone() },
+			CmtParams { symbol_size: 0, ..half.clone() },
+		];
+		for params in invalid.iter() {
+			assert_eq!(CmtLayout::from_params(params), None, "{:?}", params);
+		}
+	}
+
+	#[test]
+	fn test_layout_with_coding() {
+		assert_eq!(CmtLayout::with_coding(512, 256, RATE, AGGREGATE as u64), Some(CmtLayout::new(512, 256)));
+		assert_eq!(CmtLayout::for_block_with_coding(131072, 256, RATE, AGGREGATE as u64), Some(CmtLayout::for_block(131072, 256)));
+
+		// a rate of 1/2 and an aggregation of 16 hashes
+		let layout = CmtLayout::with_coding(512, 64, 0.5, 16).unwrap();
+		assert_eq!(layout, CmtLayout::from_coded_lengths(vec![1024, 128, 16], 0.5, 16));
+		assert_eq!((layout.k(1), layout.reduce_factor(), layout.rate()), (64, 8, 0.5));
+		assert_eq!((layout.symbol_size(0), layout.symbol_size(1)), (BASE_SYMBOL_SIZE, 512));
+
+		// the layers of the tree must shrink
+		assert_eq!(CmtLayout::with_coding(512, 64, 0.25, 4), None);
+		assert_eq!(CmtLayout::for_block_with_coding(131072, 256, 0.25, 4), None);
+		assert_eq!(CmtLayout::with_coding(512, 64, 0.25, 2), None);
+		// the systematic hashes of an upper symbol must be a whole number
+		assert_eq!(CmtLayout::with_coding(512, 64, 0.3, 10), None);
+	}
+}
@@ -15,9 +15,29 @@ pub mod block;
 pub mod block_header;
 pub mod merkle_root;
 pub mod coded_merkle_roots;
+pub mod arena;
+pub mod layout;
+pub mod top_root;
+pub mod cmt_hash;
 pub mod transaction;
 pub mod decoder;
-//mod light_client;
+pub mod inactivation;
+pub mod online;
+pub mod recovery;
+pub mod observer;
+pub mod symbol_ops;
+pub mod codes;
+pub mod sampling;
+pub mod confidence;
+pub mod light_client;
+pub mod simulation;
+pub mod sweep;
+pub mod import;
+
+#[doc(hidden)]
+pub mod fixtures;
+#[cfg(test)]
+pub(crate) mod test_helpers;
 
 /// `IndexedBlock` extension
 pub mod read_and_hash;
@@ -28,11 +48,23 @@ pub mod indexed_transaction;
 pub use primitives::{hash, bytes, bigint, compact};
 pub use rand::distributions::{Distribution, Bernoulli, Uniform};
 
-pub use decoder::{Symbol, Decoder, Code, CodingErr};
-pub use block::Block;
+pub use decoder::{Symbol, SymbolRef, Decoder, Code, CodingErr, ErrorPattern, InjectedError, IncorrectCodingProofRef};
+pub use block::{Block, BaseSampleRef};
+pub use inactivation::DecodingMode;
+pub use online::{OnlineTreeDecoder, DecodeEvent};
+pub use recovery::{TargetedRecovery, RecoveryReport, RecoveryPlan};
+pub use observer::{DecodeObserver, NullObserver, LogObserver, JsonTraceWriter};
+pub use sampling::{SamplingParams, SamplingStats, Samples};
+pub use light_client::{LightClient, SamplingOutcome};
+pub use sweep::{SweepParams, SweepPoint, SweepResult};
+pub use import::{ImportedBlock, BlkFileReader, ImportParams, ImportReport, ImportError};
 pub use block_header::BlockHeader;
 pub use merkle_root::{merkle_root, merkle_node_hash};
 pub use coded_merkle_roots::{Symbols, SymbolBase, SymbolUp};
+pub use arena::LayerArena;
+pub use layout::{CmtLayout, CmtParams};
+pub use top_root::{top_root, top_proof, verify_top_proof};
+pub use cmt_hash::{CmtHasher, HashFunction};
 pub use transaction::{Transaction, TransactionInput, TransactionOutput, OutPoint};
 
 pub use read_and_hash::{ReadAndHash, HashedData};
@@ -1,94 +1,179 @@
-use ser::{deserialize, serialize};
-use {BlockHeader, Transaction};
-use constants::{BASE_SYMBOL_SIZE, AGGREGATE, RATE, SAMPLE_COMPLEXITY};
-use {Symbols, SymbolBase, SymbolUp};
-use bytes::Bytes;
-use coded_merkle_roots::coded_merkle_roots;
+use rand::Rng;
+use BlockHeader;
+use {SymbolBase, SymbolUp};
 use hash::H256;
-use merkle_root::merkle_root;
-use rand::distributions::{Distribution, Uniform};
+use bytes::Bytes;
+use ser::deserialize_borrowed;
+use block::BaseSampleRef;
+use confidence::{confidence, samples_needed};
+use sampling::draw_indices;
+
+// Outcome of the sampling of a block by a light client
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplingOutcome {
+	Available(f64), // all samples are received with valid Merkle proofs, with the confidence achieved
+	Unavailable(u64), // index of a sampled symbol that is not returned
+	InvalidProof(u64), // index of a sampled symbol whose Merkle proof does not pass
+	InvalidHeader, // the header does not commit to a valid layout of CMT
+	UnreachableSoundness, // no number of samples reaches the soundness for the stopping ratio, which must both be positive
+}
 
-#[derive(Debug, PartialEq, Clone, Serializable, Deserializable)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LightClient {
 	pub block_header: BlockHeader,
+	pub stopping_ratio: f64, // minimum stopping ratio of the codes of CMT, or an assumed fraction of withheld symbols
+	pub soundness: f64, // target probability of accepting a block whose data is not available
 }
 
 impl LightClient {
-	// We have n coded symbols in the block, and each light node randomly samples s of them
-	pub fn sampling_base(&self, n: u64, s: u32) -> (Vec<u64>, Vec<SymbolBase>, Vec<Vec<SymbolUp>>) {
-		let k = ((n as f32) * RATE) as u32; //number of systematic symbols on the base layer
-
-		let mut index = vec![];//indices of sampled symbols
-		let mut symbols: Vec<SymbolBase> = vec![];//sampled symbols
-		let mut proofs: Vec<Vec<SymbolUp>> = vec![];//Merkle proofs of sampled symbols
-
-		let mut rng = rand::thread_rng();
-        let die = Uniform::from(0..n);
-
-        //sample the first symbol
-		let throw = die.sample(&mut rng); //select the first index of the coded symbol to sample
-		let (symbol, proof): (Option<SymbolBase>, Option<Vec<SymbolUp>>) = 
-		match (symbol, proof) {
-			(None, _) => panic!("Symbol No.{} is missing!", throw),
-			(_, None) => panic!("Merkle proof for symbol No.{} is missing!", throw),
-			(Some(s), Some(p)) => {
-				if self.block_header.verify_base(s, throw, k, &p) {
-					index.push(throw);
-					symbols.push(s);
-					proofs.push(p);
-				} else {
-					panic!("Merkle proof for symbol No.{} does not pass!", throw);
-				}
-			},
+	pub fn new(block_header: BlockHeader, stopping_ratio: f64, soundness: f64) -> Self {
+		LightClient {
+			block_header: block_header,
+			stopping_ratio: stopping_ratio,
+			soundness: soundness,
 		}
+	}
 
-		for _ in 1..s {
-			let throw = die.sample(&mut rng);
-			//if !index.contains(&throw) {
-			let (symbol, proof): (Option<SymbolBase>, Option<Vec<SymbolUp>>) = 
-			match (symbol, proof) {
-				(None, _) => panic!("Symbol No.{} is missing!", throw),
-				(_, None) => panic!("Merkle proof for symbol No.{} is missing!", throw),
-				(Some(s), Some(p)) => {
-					if self.block_header.verify_base(s, throw, k, &p) {
-					    index.push(throw);
-					    symbols.push(s);
-					    proofs.push(p);
-					} else {
-					    panic!("Merkle proof for symbol No.{} does not pass!", throw);
-					}
-				},
-			}
-			//}
-		}
+	// Number of samples the light client takes before accepting a block
+	// None if the stopping ratio or the soundness is not positive, as the client can never accept a block
+	pub fn samples_needed(&self) -> Option<u32> {
+		samples_needed(self.stopping_ratio, self.soundness)
+	}
 
-		(index, symbols, proofs)
+	// Confidence that the block is available after some successful samples
+	pub fn confidence(&self, successful_samples: u32) -> f64 {
+		confidence(self.stopping_ratio, successful_samples)
 	}
 
-    //Take s samples from each upper layer of coded Merkle tree
-	pub fn sampling_upper(&self, index: Vec<u64>, proofs: Vec<Vec<SymbolUp>>, s: u32) -> (Vec<Vec<u64>>, 
-		Vec<Vec<SymbolUp>>, Vec<Vec<Option<Vec<SymbolUp>>>>) {
-		let height = proofs[0].len() + 1; //height of coded Merkle tree
-		for j in 0..s { //iterate over s samples
-			for i in 1..height {  //iterate over height - 1 upper layers
-				let mut symbol: SymbolUp = proofs[j][i - 1];
-				if i < height - 1 {
-				    let mut proof: Option<Vec<SymbolUp>> = Some(proofs[j][i..(height - 1)].to_vec());
-				} else {
-					let mut proof: Option<Vec<SymbolUp>> = None;
-				}
-	
-
-			} 
+	// A block is accepted once enough samples are successful to reach the target soundness
+	pub fn accept(&self, successful_samples: u32) -> bool {
+		self.samples_needed().map_or(false, |s| successful_samples >= s)
+	}
 
-		}
+	// The light node randomly samples the coded symbols on the base layer until it can accept the block
+	// The number of coded symbols and the hash function of the Merkle proofs are read from the parameters committed by the header
+	// fetch requests a symbol on the base layer and its Merkle proof from the network, None if it is not returned
+	// If the header is in single-root mode, the proof comes with the inclusion proof of its top-layer symbol, empty otherwise
+	pub fn sampling_base<R, F>(&self, rng: &mut R, mut fetch: F) -> SamplingOutcome
+	where R: Rng, F: FnMut(u64) -> Option<(SymbolBase, Vec<SymbolUp>, Vec<H256>)> {
+		self.sampling_base_with(rng, |index| fetch(index).map(|(symbol, proof, top_proof)| 
+			self.block_header.verify_base(symbol, index as u32, &proof, &top_proof)))
+	}
 
+	// sampling_base with samples received as buffers in the format of Block::base_sample_bytes
+	// The samples are verified in the received buffers without copying them, and a malformed sample is an invalid proof
+	pub fn sampling_base_from_buffers<R, F>(&self, rng: &mut R, mut fetch: F) -> SamplingOutcome
+	where R: Rng, F: FnMut(u64) -> Option<Bytes> {
+		self.sampling_base_with(rng, |index| fetch(index).map(|buffer| match deserialize_borrowed::<BaseSampleRef>(&buffer) {
+			Ok(sample) => self.block_header.verify_base_ref(sample.symbol, index as u32, sample.proof, 
+				sample.top_proof),
+			Err(_) => false,
+		}))
+	}
 
+	// check fetches a sampled symbol and verifies it, None if it is not returned
+	fn sampling_base_with<R, F>(&self, rng: &mut R, mut check: F) -> SamplingOutcome
+	where R: Rng, F: FnMut(u64) -> Option<bool> {
+		let n = match self.block_header.layout() {
+			Some(layout) => layout.n(0),
+			None => return SamplingOutcome::InvalidHeader,
+		};
+		let s = match self.samples_needed() {
+			Some(s) => s,
+			None => return SamplingOutcome::UnreachableSoundness,
+		};
+		for index in draw_indices(n, s, true, rng) {
+			match check(index) {
+				None => return SamplingOutcome::Unavailable(index),
+				Some(false) => return SamplingOutcome::InvalidProof(index),
+				Some(true) => {},
+			}
+		}
+		SamplingOutcome::Available(self.confidence(s))
 	}
+}
 
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use super::*;
+	use fixtures::test_block;
+
+	#[test]
+	fn test_light_client_accepts_available_block() {
+		let mut rng = StdRng::seed_from_u64(2);
+		let block = test_block(&mut rng);
+		let client = LightClient::new(block.block_header.clone(), 0.1, 1e-3);
+		assert_eq!(client.samples_needed(), Some(66));
+		assert!(!client.accept(65));
+		assert!(client.accept(66));
+
+		let outcome = client.sampling_base(&mut rng, |i| Some(block.base_sample(i as usize)));
+		assert_eq!(outcome, SamplingOutcome::Available(client.confidence(66)));
+	}
 
+	#[test]
+	fn test_light_client_detects_withheld_and_invalid_symbols() {
+		let mut rng = StdRng::seed_from_u64(3);
+		let block = test_block(&mut rng);
+		let client = LightClient::new(block.block_header.clone(), 0.1, 1e-3);
+
+		// the block producer withholds all parity symbols
+		let outcome = client.sampling_base(&mut rng, |i| if i < 128 { Some(block.base_sample(i as usize)) } else { None });
+		match outcome {
+			SamplingOutcome::Unavailable(i) => assert!(i >= 128),
+			_ => panic!("withholding is not detected"),
+		}
 
+		// the block producer returns wrong symbols
+		let outcome = client.sampling_base(&mut rng, |i| {
+			let (mut symbol, proof, top_proof) = block.base_sample(i as usize);
+			symbol[0] ^= 1;
+			Some((symbol, proof, top_proof))
+		});
+		match outcome {
+			SamplingOutcome::InvalidProof(_) => {},
+			_ => panic!("invalid Merkle proof is not detected"),
+		}
 
+		// samples received as buffers are verified in place, a malformed buffer is an invalid proof
+		let outcome = client.sampling_base_from_buffers(&mut rng, |i| Some(block.base_sample_bytes(i as usize)));
+		assert_eq!(outcome, SamplingOutcome::Available(client.confidence(66)));
+		let outcome = client.sampling_base_from_buffers(&mut rng, |i| {
+			let mut buffer = block.base_sample_bytes(i as usize).take();
+			buffer[0] ^= 1;
+			Some(buffer.into())
+		});
+		match outcome {
+			SamplingOutcome::InvalidProof(_) => {},
+			_ => panic!("invalid Merkle proof is not detected"),
+		}
+		let outcome = client.sampling_base_from_buffers(&mut rng, |i| {
+			let buffer = block.base_sample_bytes(i as usize);
+			Some(buffer[..buffer.len() - 1].to_vec().into())
+		});
+		match outcome {
+			SamplingOutcome::InvalidProof(_) => {},
+			_ => panic!("a truncated sample is not detected"),
+		}
 
+		// the layout of CMT is taken from the header only
+		let mut header = block.block_header.clone();
+		header.cmt_params.layer_lengths[1] = 128;
+		let client = LightClient::new(header, 0.1, 1e-3);
+		assert_eq!(client.sampling_base(&mut rng, |i| Some(block.base_sample(i as usize))), SamplingOutcome::InvalidHeader);
+	}
 
-}
\ No newline at end of file
+	#[test]
+	fn test_light_client_rejects_unreachable_soundness() {
+		let mut rng = StdRng::seed_from_u64(4);
+		let block = test_block(&mut rng);
+		for &(stopping_ratio, soundness) in [(0.0, 1e-3), (-0.1, 1e-3), (0.1, 0.0), (0.1, -1.0)].iter() {
+			let client = LightClient::new(block.block_header.clone(), stopping_ratio, soundness);
+			assert_eq!(client.samples_needed(), None);
+			assert!(!client.accept(u32::max_value()));
+			assert_eq!(client.sampling_base(&mut rng, |i| Some(block.base_sample(i as usize))), SamplingOutcome::UnreachableSoundness);
+		}
+	}
+}
@@ -1,6 +1,7 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::str;
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
 
 extern crate rustc_hex as hex;
 extern crate heapsize;
@@ -10,109 +11,163 @@ extern crate bitcrypto as crypto;
 extern crate serialization as ser;
 extern crate rand;
 extern crate chain;
-
 #[macro_use]
+extern crate clap;
+
 extern crate serialization_derive;
 
-use primitives::{hash, bytes, bigint, compact};
-use ser::{deserialize, serialize};
-use bytes::Bytes;
+use primitives::hash;
 use hash::H256;
-use rand::distributions::{Distribution, Bernoulli, Uniform};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 use chain::block_header::BlockHeader;
-use chain::transaction::{Transaction, TransactionInput, TransactionOutput, OutPoint};
+use chain::transaction::Transaction;
 use chain::block::Block;
-use chain::constants::{BLOCK_SIZE, BASE_SYMBOL_SIZE, AGGREGATE, RATE, HEADER_SIZE, NUMBER_ITERATION};
-use chain::coded_merkle_roots::{Symbols, SymbolBase, SymbolUp, coded_merkle_roots};
-use chain::merkle_root::merkle_root;
-use chain::decoder::{Code, Symbol, Decoder, TreeDecoder, CodingErr, IncorrectCodingProof};
-
-// obtain a code represented by symbols from the form represented by parities
-fn convert_parity_to_symbol(parities: Vec<Vec<u64>>, n: u64) -> Vec<Vec<u64>> {
-	let mut symbols: Vec<Vec<u64>> = vec![vec![];n as usize];
-	for i in 0..parities.len(){
-		let parity = &parities[i];
-		for s in parity.iter() {
-			symbols[*s as usize].push(i as u64);
-		}
+use chain::constants::{BLOCK_SIZE, RATE, HEADER_SIZE, NUMBER_ITERATION};
+use chain::coded_merkle_roots::errors_from_correct;
+use chain::cmt_hash::HashFunction;
+use chain::layout::CmtParams;
+use chain::decoder::{Code, TreeDecoder, CodingErr, IncorrectCodingProof};
+use chain::codes::{read_codes, shared_codes};
+use chain::inactivation::DecodingMode;
+use chain::observer::{DecodeObserver, LogObserver, JsonTraceWriter};
+use chain::sampling::{SamplingParams, SamplingStats};
+use chain::simulation::{find_stopping_set, simulate_withholding};
+
+mod commands;
+
+//folder containing the reference LDPC codes
+const CODES_DIR: &str = "chain/src/LDPC_codes";
+
+//Header of the reference block, its hashes and tree parameters are set when the block is encoded
+fn reference_header() -> BlockHeader {
+	BlockHeader {
+		version: 1,
+		previous_header_hash: H256::default(),
+		merkle_root_hash: H256::default(),
+		time: 4u32,
+		bits: 5.into(),
+		nonce: 6u32,
+		coded_merkle_roots_hashes: vec![H256::default(); 8],
+		cmt_params: CmtParams::default(),
 	}
-	symbols
 }
 
-fn read_code_from_file(k: u64) -> (Code, Code) {
-    //compute number of coded symbols
-	let n = ((k as f32) / RATE ) as u64;
+//Sample transaction of the reference block
+const REFERENCE_TRANSACTION: &str = "0100000001a6b97044d03da79c005b20ea9c0e1a6d9dc12d9f7b91a5911c9030a439eed8f5000000004948304502206e21798a42fae0e854281abd38bacd1aeed3ee3738d9e1446618c4571d1090db022100e2ac980643b0b82c0e88ffdfec6b64e3e6ba35e7ba5fdd7d5d6cc8d25c6b241501ffffffff0100f2052a010000001976a914404371705fa9bd789a2fcd52d2c580b65d35549d88ac00000000";
 
-	//Read encoding matrix
-	let filename = String::from("chain/src/LDPC_codes/k=") + &k.to_string() + &String::from("_encode.txt");
-    // Open the file in read-only mode (ignoring errors).
-    let file = File::open(filename).unwrap();
-    let reader = BufReader::new(file);
-    
-    //parity equations for encoding
-    let mut parities_encoding: Vec<Vec<u64>> = vec![];
-    for (index, line) in reader.lines().enumerate() {
-        let line = line.unwrap(); // Ignore errors.
-        let parity: Vec<u64> = line.split_whitespace().map(|s| s.parse().unwrap()).collect();
-        parities_encoding.push(parity);
-    }
-
-    //Read decodeing matrix
-    let filename = String::from("chain/src/LDPC_codes/k=") + &k.to_string() + &String::from("_decode.txt");
-    // Open the file in read-only mode (ignoring errors).
-    let file = File::open(filename).unwrap();
-    let reader = BufReader::new(file);
-    
-    //parity equations for decoding
-    let mut parities_decoding: Vec<Vec<u64>> = vec![];
-    for (index, line) in reader.lines().enumerate() {
-        let line = line.unwrap(); // Ignore errors.
-        let parity: Vec<u64> = line.split_whitespace().map(|s| s.parse().unwrap()).collect();
-        parities_decoding.push(parity);
-    }
-
-    // Read the file line by line. Each line represent a parity equation.
-    // for (index, line) in reader.lines().enumerate() {
-    //     let line = line.unwrap(); // Ignore errors.
-    //     let parity: Vec<u64> = line.split_whitespace().map(|s| s.parse().unwrap()).collect();
-    //     if index < ((n - k) as usize) {
-    //     	parities_encoding.push(parity.clone());
-    //     	parities_decoding.push(parity);
-    //     }
-    //     else {//extra parity equations to help decoding
-    //     	parities_decoding.push(parity); 
-    //     }
-    // }
-     
-    //Generate one code for encoding, and one code for decoding
-    (Code {parities: parities_encoding.clone(), symbols: convert_parity_to_symbol(parities_encoding, n)}, 
-    	Code {parities: parities_decoding.clone(), symbols: convert_parity_to_symbol(parities_decoding, n)})
+//Transactions of the reference block, copies of the sample transaction filling half of BLOCK_SIZE
+fn reference_transactions() -> Vec<Transaction> {
+	let t = REFERENCE_TRANSACTION;
+	let transaction_size = String::from(t).len();
+	let num_transactions = BLOCK_SIZE / (transaction_size as u64);
+	vec![t.into();num_transactions as usize]
 }
 
-//Read all codes for all coded Merkle tree layers
-fn read_codes(k_set: Vec<u64>) -> (Vec<Code>, Vec<Code>) {
-	let mut codes_for_encoding: Vec<Code> = vec![];
-	let mut codes_for_decoding: Vec<Code> = vec![];
-	for i in k_set.iter() {
-		let (code_e, code_d) = read_code_from_file(*i);
-		codes_for_encoding.push(code_e);
-		codes_for_decoding.push(code_d);
+//Coding errors and stopping sets are printed, and every decoding step is written to the trace file if one is given
+fn decode_observer(trace: Option<&str>) -> Box<dyn DecodeObserver> {
+	match trace {
+		Some(path) => {
+			let file = File::create(path).expect("cannot create the trace file");
+			Box::new((LogObserver, JsonTraceWriter::new(BufWriter::new(file))))
+		},
+		None => Box::new(LogObserver),
 	}
-	(codes_for_encoding, codes_for_decoding)
 }
 
-fn test(block: &Block, num_samples: &Vec<u32>, codes_for_decoding: &Vec<Code>) -> Vec<Result<(), IncorrectCodingProof>> {
+fn test<R: Rng>(block: &Block, num_samples: &[u32], decoder: &mut TreeDecoder, with_replacement: bool, rng: &mut R) 
+-> (Vec<Result<(), IncorrectCodingProof>>, Vec<SamplingStats>) {
 	let mut decoding_results = vec![];
+	let mut sampling_stats = vec![];
 	//Try different sample sizes to decode
 	for s in num_samples.iter() {
-		//initiate the decoder for coded Merkle tree
-		let mut decoder: TreeDecoder = TreeDecoder::new(codes_for_decoding.to_vec(), &block.block_header.coded_merkle_roots_hashes);
-        //take s symbols with replacement unifromly at random from the base layer of CMT
-		let (symbols_all_levels, indices_all_levels) = block.sampling_to_decode(*s);
-		decoding_results.push(decoder.run_tree_decoder(symbols_all_levels, indices_all_levels));
+		//reset the decoder for coded Merkle tree, the codes are reused across trials
+		decoder.reset(&block.block_header, &[]);
+        //take s symbols unifromly at random from the base layer of CMT
+		let params = SamplingParams { base_samples: *s, with_replacement: with_replacement, layer_budgets: None };
+		let (symbols_all_levels, indices_all_levels, stats) = block.sample(&params, rng);
+		decoding_results.push(decoder.run_tree_decoder(&symbols_all_levels, &indices_all_levels));
+		sampling_stats.push(stats);
 	}
-	decoding_results
+	(decoding_results, sampling_stats)
+}
+
+//Decode the same samples with the peeling decoder and with inactivation decoding, and compare the number of samples they need
+fn compare_decoders<R: Rng>(block: &Block, num_samples: &[u32], codes_for_decoding: &[Arc<Code>], with_replacement: bool, rng: &mut R) {
+	let modes = [DecodingMode::Peeling, DecodingMode::Inactivation];
+	let mut decoders: Vec<TreeDecoder> = modes.iter()
+		.map(|mode| TreeDecoder::new_with_mode(codes_for_decoding, &block.block_header.coded_merkle_roots_hashes, *mode)
+			.with_hash_function(block.hash_function()))
+		.collect();
+	let mut successful_decoding = vec![vec![0u32; num_samples.len()]; modes.len()];
+	let mut recovered = vec![0.0f32; num_samples.len()];
+	for _ in 0..NUMBER_ITERATION {
+		for (j, s) in num_samples.iter().enumerate() {
+			let params = SamplingParams { base_samples: *s, with_replacement: with_replacement, layer_budgets: None };
+			let (symbols_all_levels, indices_all_levels, _) = block.sample(&params, rng);
+			for (m, mode) in modes.iter().enumerate() {
+				let decoder = &mut decoders[m];
+				decoder.reset(&block.block_header, &[]);
+				if decoder.run_tree_decoder(&symbols_all_levels, &indices_all_levels).is_ok() {
+					successful_decoding[m][j] += 1;
+				}
+				if *mode == DecodingMode::Inactivation {
+					recovered[j] += (decoder.inactivation_recovered.iter().sum::<u64>() as f32) / (NUMBER_ITERATION as f32);
+				}
+			}
+		}
+	}
+	for j in 0..num_samples.len() {
+		println!("With {} randomly sampled symbols, peeling decodes with probability {} and inactivation decoding with probability {}, \
+			recovering {} symbols by Gaussian elimination on average.", num_samples[j], 
+			(successful_decoding[0][j] as f32) / (NUMBER_ITERATION as f32), (successful_decoding[1][j] as f32) / (NUMBER_ITERATION as f32), 
+			recovered[j]);
+	}
+	//smallest number of samples from which every run of a decoder succeeds
+	let needed: Vec<Option<u32>> = successful_decoding.iter().map(|successes| {
+		(0..num_samples.len()).find(|j| successes[*j..].iter().all(|c| *c == NUMBER_ITERATION)).map(|j| num_samples[j])
+	}).collect();
+	match (needed[0], needed[1]) {
+		(Some(peeling), Some(inactivation)) => println!("Peeling needs {} samples and inactivation decoding needs {} samples to always decode ({} fewer).",
+			peeling, inactivation, (peeling as i64) - (inactivation as i64)),
+		(None, Some(inactivation)) => println!("Peeling does not always decode with up to {} samples, inactivation decoding needs {} samples.",
+			num_samples[num_samples.len() - 1], inactivation),
+		_ => println!("Neither decoder always decodes with up to {} samples.", num_samples[num_samples.len() - 1]),
+	}
+}
+
+//Encode a block of BLOCK_SIZE bytes, whose coded Merkle tree is hashed with hash_function
+//correct indicates if we will perform coding correctly or not on each level of the CMT
+fn encode_block<R: Rng>(header: BlockHeader, transactions: &Vec<Transaction>, codes: &Vec<Code>, correct: Vec<bool>, 
+	hash_function: HashFunction, rng: &mut R) -> Block {
+	let errors = errors_from_correct(&correct, codes, RATE);
+	Block::new_with_hash_function(header, transactions, BLOCK_SIZE as usize, HEADER_SIZE, codes, &errors, hash_function, rng)
+}
+
+//Withhold a stopping set of the code on some layer for NUMBER_ITERATION blocks, and report how often light clients detect it
+fn simulate<R: Rng>(block: &Block, codes_for_decoding: &[Arc<Code>], layer: usize, params: &SamplingParams, num_clients: u32, rng: &mut R) {
+	let mut detection_probability = 0.0;
+	let mut num_stopped = 0;
+	for _ in 0..NUMBER_ITERATION {
+		//the block producer finds a stopping set of the code on the attacked layer
+		let stopping_set = find_stopping_set(&codes_for_decoding[layer], 0.5, rng);
+		let report = simulate_withholding(block, codes_for_decoding, layer, &stopping_set, params, num_clients, rng);
+		println!("Withholding {} symbols (stopping ratio {}) on layer {}: {} out of {} light clients detect it.", 
+			stopping_set.len(), report.stopping_ratio, layer, report.detecting_clients, num_clients);
+		detection_probability += report.detection_probability() / (NUMBER_ITERATION as f32);
+		match report.decoding_result {
+			Err(ref proof) => {
+				if let CodingErr::Stopped = proof.error_type {
+					println!("Full node generates a Stopped proof on layer {} with stopping ratio {}.", proof.level, proof.stop_ratio);
+					num_stopped += 1;
+				}
+			},
+			Ok(()) => println!("Full node decodes the block despite the withholding."),
+		}
+	}
+	println!("The probability that a light client with {} samples detects the withholding is {}.", params.base_samples, detection_probability);
+	println!("{} out of {} withholding attacks generate a Stopped proof.", num_stopped, NUMBER_ITERATION);
 }
 
 fn main() {
@@ -129,44 +184,74 @@ fn main() {
 	//2. Incorrect-coding test: flip the bits of parity symbols after encoding, and use flipped symbols to construct CMT.
     //Check if the decoder correctly generates the incorrect-coding proof.
 
+	//All randomness of the tests is derived from a single seed, so that a run can be replayed with --seed
+	//Steps of a run can also be performed separately with the subcommands, see commands.rs
+	let yaml = load_yaml!("cli.yml");
+	let matches = clap::App::from_yaml(yaml).get_matches();
+	if let (name, Some(args)) = matches.subcommand() {
+		let seed: u64 = match args.value_of("seed") {
+			Some(seed) => seed.parse().expect("seed must be an unsigned 64-bit integer"),
+			None => rand::thread_rng().gen(),
+		};
+		println!("Running {} with seed {}.", name, seed);
+		commands::run(name, args, &mut StdRng::seed_from_u64(seed));
+		return;
+	}
+	let seed: u64 = match matches.value_of("seed") {
+		Some(seed) => seed.parse().expect("seed must be an unsigned 64-bit integer"),
+		None => rand::thread_rng().gen(),
+	};
+	println!("Running tests with seed {}.", seed);
+	let with_replacement = !matches.is_present("without-replacement");
+	let mode = match matches.value_of("decoder") {
+		Some("inactivation") => DecodingMode::Inactivation,
+		_ => DecodingMode::Peeling,
+	};
+	let hash_function: HashFunction = matches.value_of("hash").unwrap_or("double-sha256").parse().unwrap();
+	let mut rng = StdRng::seed_from_u64(seed);
+
 	//Initialize a block
 	//Initialize the block header
-	let header = BlockHeader {
-			version: 1,
-			previous_header_hash: H256::default(),
-			merkle_root_hash: H256::default(),
-			time: 4u32,
-			bits: 5.into(),
-			nonce: 6u32,
-			coded_merkle_roots_hashes: vec![H256::default(); 8],
-		};
+	let header = reference_header();
 
 	let header_1 = header.clone(); //header for first test
 	let header_2 = header; //header for second test
 
     // Create transactions
-	let t = "0100000001a6b97044d03da79c005b20ea9c0e1a6d9dc12d9f7b91a5911c9030a439eed8f5000000004948304502206e21798a42fae0e854281abd38bacd1aeed3ee3738d9e1446618c4571d1090db022100e2ac980643b0b82c0e88ffdfec6b64e3e6ba35e7ba5fdd7d5d6cc8d25c6b241501ffffffff0100f2052a010000001976a914404371705fa9bd789a2fcd52d2c580b65d35549d88ac00000000";
-	let transaction_size = String::from(t).len();
-	let num_transactions = BLOCK_SIZE / (transaction_size as u64);
-
-	let transactions: Vec<Transaction> = vec![t.into();num_transactions as usize];
+	let transactions: Vec<Transaction> = reference_transactions();
     
     // number of systematic symbols for the codes on the four layers of CMT
     let k_set: Vec<u64> = vec![512, 256, 128, 64];
-    let (codes_for_encoding, codes_for_decoding) = read_codes(k_set);
+    let (codes_for_encoding, codes_for_decoding) = read_codes(matches.value_of("codes").unwrap_or(CODES_DIR), k_set);
+    let codes_for_decoding = shared_codes(&codes_for_decoding);
+
+    //Compare the number of samples needed by the two decoders, instead of the tests
+    if matches.is_present("compare-decoders") {
+    	let block: Block = encode_block(header_1, &transactions, &codes_for_encoding, vec![true; codes_for_encoding.len()], hash_function, &mut rng);
+    	let num_samples: Vec<u32> = (5..21).map(|i| i * 100).collect();
+    	compare_decoders(&block, &num_samples, &codes_for_decoding, with_replacement, &mut rng);
+    	return;
+    }
 
     //Start tests
 
     //Test 1: Nornal mode, no coding error
     //block encoding
-    let block: Block = Block::new(header_1, &transactions, BLOCK_SIZE as usize, HEADER_SIZE, &codes_for_encoding, vec![true; codes_for_encoding.len()]);
+    let block: Block = encode_block(header_1, &transactions, &codes_for_encoding, vec![true; codes_for_encoding.len()], hash_function, &mut rng);
     
     //block decoding
     let num_samples = vec![1500, 1600, 1700, 1800, 1900, 2000];
     let mut successful_decoding_probability: Vec<f32> = vec![0.0;num_samples.len()];
+    let mut average_download: Vec<f32> = vec![0.0;num_samples.len()];
+    //the decoder is created once and reset for every trial
+    let observer = decode_observer(matches.value_of("trace"));
+    let mut decoder = TreeDecoder::new_with_mode(&codes_for_decoding, &block.block_header.coded_merkle_roots_hashes, mode)
+    	.with_hash_function(hash_function)
+    	.with_observer(observer);
     for i in 0..NUMBER_ITERATION { //try over NUMBER_ITERATION times, each time randomly takes num_samples symbols
-    	let decoding_results: Vec<Result<(), IncorrectCodingProof>> = test(&block, &num_samples, &codes_for_decoding); 
+    	let (decoding_results, sampling_stats) = test(&block, &num_samples, &mut decoder, with_replacement, &mut rng); 
     	for j in 0..num_samples.len() {
+    		average_download[j] += (sampling_stats[j].bytes_downloaded as f32)/(NUMBER_ITERATION as f32);
     		match &decoding_results[j] {
     			Ok(()) => {
     				successful_decoding_probability[j] += 1.0/(NUMBER_ITERATION as f32);
@@ -176,7 +261,8 @@ fn main() {
     	}
     }
     for j in 0..num_samples.len() {
-    	println!("The probability of successful decoding with {} randomly sampled symbols is {}.", num_samples[j], successful_decoding_probability[j]);
+    	println!("The probability of successful decoding with {} randomly sampled symbols is {}, downloading {} bytes on average.", 
+    		num_samples[j], successful_decoding_probability[j], average_download[j]);
     } 
     
 
@@ -189,12 +275,12 @@ fn main() {
     //error_pattern[1] = false;
     
     //block encoding with the bits of first parity symbol flipped 
-	let block: Block = Block::new(header_2, &transactions, BLOCK_SIZE as usize, HEADER_SIZE, &codes_for_encoding, error_pattern);
+	let block: Block = encode_block(header_2, &transactions, &codes_for_encoding, error_pattern, hash_function, &mut rng);
     
     //block decoding
 	let num_samples = vec![2048];
 	for i in 0..10 { //run for 10 times, each time the error should be caught
-        let decoding_results: Vec<Result<(), IncorrectCodingProof>> = test(&block, &num_samples, &codes_for_decoding);
+        let (decoding_results, _) = test(&block, &num_samples, &mut decoder, with_replacement, &mut rng);
     } 
 }
 
@@ -0,0 +1,260 @@
+use std::io::Write;
+
+// Observation of the decoding of CMT, for logging and offline debugging
+// The tree decoder and the online decoder report every step of peeling to an observer. All methods do nothing by default,
+// so an observer only implements the events it is interested in.
+pub trait DecodeObserver: Send {
+	// a received symbol on some layer is stored by the decoder
+	fn received(&mut self, _lvl: u32, _index: u64) {}
+	// a symbol is decoded from a degree-1 parity node and matches its hash
+	fn peeled(&mut self, _lvl: u32, _index: u64, _parity: u64) {}
+	// all symbols of a parity equation are known, satisfied is false if they do not sum up to zero (NotZero)
+	fn parity_checked(&mut self, _lvl: u32, _parity: u64, _satisfied: bool) {}
+	// a symbol decoded from a degree-1 parity node does not match its hash (NotHash)
+	fn hash_mismatch(&mut self, _lvl: u32, _index: u64, _parity: u64) {}
+	// a symbol of a stopping set is recovered by Gaussian elimination
+	fn inactivated(&mut self, _lvl: u32, _index: u64) {}
+	// all symbols of a layer are decoded
+	fn layer_decoded(&mut self, _lvl: u32) {}
+	// peeling cannot continue on a layer, with undecoded symbols left (Stopped)
+	fn stalled(&mut self, _lvl: u32, _undecoded: usize, _stopping_ratio: f32) {}
+}
+
+// Observer ignoring all events, used by default
+pub struct NullObserver;
+
+impl DecodeObserver for NullObserver {}
+
+// Observer printing coding errors, stopping sets and the end of decoding to the standard output
+pub struct LogObserver;
+
+impl DecodeObserver for LogObserver {
+	fn parity_checked(&mut self, lvl: u32, parity: u64, satisfied: bool) {
+		if !satisfied {
+			println!("NotZero incorrect coding detected on layer {} for parity equation #{}.", lvl, parity);
+		}
+	}
+
+	fn hash_mismatch(&mut self, lvl: u32, _index: u64, parity: u64) {
+		println!("NotHash incorrect coding detected on layer {} for parity equation #{}.", lvl, parity);
+	}
+
+	fn layer_decoded(&mut self, lvl: u32) {
+		if lvl == 0 {
+			println!("Coded Merkle tree successfully decoded.");
+		}
+	}
+
+	fn stalled(&mut self, lvl: u32, _undecoded: usize, stopping_ratio: f32) {
+		println!("Hitting a stopping set at layer {}. Decoding failed with a stopping ratio of {}.", lvl, stopping_ratio);
+	}
+}
+
+// Both observers receive every event, in order
+impl<A: DecodeObserver, B: DecodeObserver> DecodeObserver for (A, B) {
+	fn received(&mut self, lvl: u32, index: u64) {
+		self.0.received(lvl, index);
+		self.1.received(lvl, index);
+	}
+
+	fn peeled(&mut self, lvl: u32, index: u64, parity: u64) {
+		self.0.peeled(lvl, index, parity);
+		self.1.peeled(lvl, index, parity);
+	}
+
+	fn parity_checked(&mut self, lvl: u32, parity: u64, satisfied: bool) {
+		self.0.parity_checked(lvl, parity, satisfied);
+		self.1.parity_checked(lvl, parity, satisfied);
+	}
+
+	fn hash_mismatch(&mut self, lvl: u32, index: u64, parity: u64) {
+		self.0.hash_mismatch(lvl, index, parity);
+		self.1.hash_mismatch(lvl, index, parity);
+	}
+
+	fn inactivated(&mut self, lvl: u32, index: u64) {
+		self.0.inactivated(lvl, index);
+		self.1.inactivated(lvl, index);
+	}
+
+	fn layer_decoded(&mut self, lvl: u32) {
+		self.0.layer_decoded(lvl);
+		self.1.layer_decoded(lvl);
+	}
+
+	fn stalled(&mut self, lvl: u32, undecoded: usize, stopping_ratio: f32) {
+		self.0.stalled(lvl, undecoded, stopping_ratio);
+		self.1.stalled(lvl, undecoded, stopping_ratio);
+	}
+}
+
+// Observer writing every event as a line of JSON, e.g.
+// {"step":3,"event":"peeled","layer":0,"index":17,"parity":40}
+// step numbers the events from 0, so that a trace can be replayed per layer
+// Tracing is best effort: writing stops at the first I/O error, which is kept in error
+pub struct JsonTraceWriter<W: Write + Send> {
+	writer: W,
+	step: u64,
+	pub error: Option<::std::io::Error>,
+}
+
+impl<W: Write + Send> JsonTraceWriter<W> {
+	pub fn new(writer: W) -> Self {
+		JsonTraceWriter {
+			writer: writer,
+			step: 0,
+			error: None,
+		}
+	}
+
+	// the fields of an event after its name, as "key":value pairs
+	fn write_event(&mut self, event: &str, fields: &[(&str, String)]) {
+		if self.error.is_some() {
+			return;
+		}
+		let mut line = format!("{{\"step\":{},\"event\":\"{}\"", self.step, event);
+		for &(key, ref value) in fields.iter() {
+			line.push_str(&format!(",\"{}\":{}", key, value));
+		}
+		line.push_str("}\n");
+		self.step += 1;
+		if let Err(err) = self.writer.write_all(line.as_bytes()) {
+			self.error = Some(err);
+		}
+	}
+
+	pub fn into_inner(mut self) -> W {
+		let _ = self.writer.flush();
+		self.writer
+	}
+}
+
+impl<W: Write + Send> DecodeObserver for JsonTraceWriter<W> {
+	fn received(&mut self, lvl: u32, index: u64) {
+		self.write_event("received", &[("layer", lvl.to_string()), ("index", index.to_string())]);
+	}
+
+	fn peeled(&mut self, lvl: u32, index: u64, parity: u64) {
+		self.write_event("peeled", &[("layer", lvl.to_string()), ("index", index.to_string()), ("parity", parity.to_string())]);
+	}
+
+	fn parity_checked(&mut self, lvl: u32, parity: u64, satisfied: bool) {
+		self.write_event("parity_checked", &[("layer", lvl.to_string()), ("parity", parity.to_string()),
+			("satisfied", satisfied.to_string())]);
+	}
+
+	fn hash_mismatch(&mut self, lvl: u32, index: u64, parity: u64) {
+		self.write_event("hash_mismatch", &[("layer", lvl.to_string()), ("index", index.to_string()),
+			("parity", parity.to_string())]);
+	}
+
+	fn inactivated(&mut self, lvl: u32, index: u64) {
+		self.write_event("inactivated", &[("layer", lvl.to_string()), ("index", index.to_string())]);
+	}
+
+	fn layer_decoded(&mut self, lvl: u32) {
+		self.write_event("layer_decoded", &[("layer", lvl.to_string())]);
+		let _ = self.writer.flush();
+	}
+
+	fn stalled(&mut self, lvl: u32, undecoded: usize, stopping_ratio: f32) {
+		self.write_event("stalled", &[("layer", lvl.to_string()), ("undecoded", undecoded.to_string()),
+			("stopping_ratio", stopping_ratio.to_string())]);
+		let _ = self.writer.flush();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io;
+	use std::sync::{Arc, Mutex};
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use fixtures::{codes_dir, test_block};
+	use codes::{read_codes, shared_codes};
+	use decoder::{TreeDecoder, CodingErr};
+	use super::*;
+
+	// a trace buffer that can be read while the decoder owns its observer
+	#[derive(Clone)]
+	struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+	impl Write for SharedBuffer {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl SharedBuffer {
+		fn lines(&self) -> Vec<String> {
+			String::from_utf8(self.0.lock().unwrap().clone()).unwrap().lines().map(|l| l.to_string()).collect()
+		}
+	}
+
+	#[test]
+	fn test_json_trace_format() {
+		let mut trace = JsonTraceWriter::new(vec![]);
+		trace.received(1, 7);
+		trace.parity_checked(0, 3, false);
+		trace.stalled(0, 128, 0.25);
+		let lines = String::from_utf8(trace.into_inner()).unwrap();
+		assert_eq!(lines, "{\"step\":0,\"event\":\"received\",\"layer\":1,\"index\":7}\n\
+			{\"step\":1,\"event\":\"parity_checked\",\"layer\":0,\"parity\":3,\"satisfied\":false}\n\
+			{\"step\":2,\"event\":\"stalled\",\"layer\":0,\"undecoded\":128,\"stopping_ratio\":0.25}\n");
+	}
+
+	// Every symbol is either received or peeled, and every parity equation is checked once
+	#[test]
+	fn test_trace_of_tree_decoding() {
+		let mut rng = StdRng::seed_from_u64(17);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes = shared_codes(&codes);
+		let buffer = SharedBuffer(Arc::new(Mutex::new(vec![])));
+		let mut decoder = TreeDecoder::new(&codes, &block.block_header.coded_merkle_roots_hashes)
+			.with_observer(Box::new(JsonTraceWriter::new(buffer.clone())));
+		let received: Vec<Vec<u64>> = (0..2).map(|lvl| (0..block.layer_length(lvl) as u64).filter(|i| i % 4 != 1).collect()).collect();
+		let (symbols, indices) = block.collect_samples(&received);
+		assert!(decoder.run_tree_decoder(&symbols, &indices).is_ok());
+
+		let lines = buffer.lines();
+		let count = |pattern: &str| lines.iter().filter(|l| l.contains(pattern)).count();
+		for lvl in 0..2 {
+			let layer = format!("\"layer\":{},", lvl);
+			let events = |event: &str| lines.iter().filter(|l| l.contains(event) && l.contains(&layer)).count();
+			assert_eq!(events("\"received\""), received[lvl].len());
+			assert_eq!(events("\"received\"") + events("\"peeled\""), block.layer_length(lvl));
+			assert_eq!(events("\"parity_checked\""), codes[lvl].parities.len());
+		}
+		assert_eq!(count("\"satisfied\":false"), 0);
+		assert_eq!(count("\"layer_decoded\""), 2);
+		assert!(lines.last().unwrap().contains("\"event\":\"layer_decoded\",\"layer\":0"));
+		for (step, line) in lines.iter().enumerate() {
+			assert!(line.starts_with(&format!("{{\"step\":{},", step)));
+		}
+	}
+
+	#[test]
+	fn test_trace_of_stopped_decoding() {
+		let mut rng = StdRng::seed_from_u64(18);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let buffer = SharedBuffer(Arc::new(Mutex::new(vec![])));
+		let mut decoder = TreeDecoder::new(&shared_codes(&codes), &block.block_header.coded_merkle_roots_hashes)
+			.with_observer(Box::new((NullObserver, JsonTraceWriter::new(buffer.clone()))));
+		let received = vec![(0..64).collect(), (0..block.layer_length(1) as u64).collect()];
+		let (symbols, indices) = block.collect_samples(&received);
+		match decoder.run_tree_decoder(&symbols, &indices) {
+			Err(proof) => {
+				assert_eq!(proof.error_type, CodingErr::Stopped);
+				let stalled = format!("\"event\":\"stalled\",\"layer\":0,\"undecoded\":{},", proof.stop_set.len());
+				assert!(buffer.lines().last().unwrap().contains(&stalled));
+			},
+			Ok(()) => panic!("64 base symbols cannot be decoded"),
+		}
+	}
+}
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+use hash::H256;
+use cmt_hash::{CmtHasher, HashFunction};
+use decoder::{Code, Symbol, TreeDecoder, IncorrectCodingProof, CodingErr, symbol_to_up, symbol_to_hash};
+use inactivation::DecodingMode;
+
+// Online decoding of CMT at a full node that receives symbols from peers over time
+// Every symbol is verified when it arrives, either against the hashes of its layer once they are known,
+// or along its Merkle proof. Layers are decoded from the top layer down as in run_tree_decoder: peeling runs on the
+// highest layer that is not decoded yet, and symbols received on lower layers are kept until their layer is reached.
+
+// Events reported by the online decoder as symbols arrive
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeEvent {
+	Received(u32, u64), // a symbol on some layer passes verification and is stored
+	Rejected(u32, u64), // a symbol is out of range, or it or its Merkle proof does not match the known hashes
+	Duplicate(u32, u64), // the symbol is already known
+	Decoded(u32, u64), // number of symbols decoded by peeling on a layer
+	LayerDecoded(u32),
+	TreeDecoded,
+	IncorrectCoding(CodingErr, u32), // incorrect coding detected on a layer, the proof is kept in outcome
+}
+
+pub struct OnlineTreeDecoder {
+	pub tree: TreeDecoder,
+	pub active_layer: u32, // all layers above are decoded, and the hashes of this layer are known
+	pub outcome: Option<Result<(), IncorrectCodingProof>>, // set once the tree is decoded or incorrect coding is detected
+}
+
+impl OnlineTreeDecoder {
+	pub fn new(codes: &[Arc<Code>], header_hash: &Vec<H256>, mode: DecodingMode) -> Self {
+		let tree = TreeDecoder::new_with_mode(codes, header_hash, mode);
+		let top = tree.height - 1;
+		OnlineTreeDecoder {
+			tree: tree,
+			active_layer: top,
+			outcome: None,
+		}
+	}
+
+	// Verify and decode symbols with the hash function of the tree, double-SHA256 by default
+	pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+		self.tree = self.tree.with_hash_function(hash_function);
+		self
+	}
+
+	// Start decoding another block with the same codes
+	pub fn reset(&mut self, header_hash: &Vec<H256>) {
+		self.tree.reset_top(header_hash, None);
+		self.active_layer = self.tree.height - 1;
+		self.outcome = None;
+	}
+
+	// Hash of a symbol that is already known from the hashes of its layer or from its parent symbol
+	fn known_hash(&self, lvl: u32, index: u64) -> Option<H256> {
+		if lvl >= self.active_layer {
+			return Some(self.tree.hashes[lvl as usize][index as usize]);
+		}
+		let layout = &self.tree.layout;
+		let parent = &self.tree.decoders[(lvl + 1) as usize];
+		let parent_index = layout.parent(lvl, index) as usize;
+		if parent.decoded[parent_index] {
+			Some(*parent.symbol_values.hash(parent_index, layout.hash_slot(lvl, index)))
+		} else {
+			None
+		}
+	}
+
+	// Verify a symbol, using the symbols of its Merkle proof until one of them has a known hash
+	// Returned are the symbols authenticated on the way, starting with the symbol itself
+	fn verify(&self, lvl: u32, index: u64, symbol: Symbol, proof: &[Symbol]) -> Option<Vec<(u32, u64, Symbol)>> {
+		let layout = &self.tree.layout;
+		let mut authenticated = vec![(lvl, index, symbol)];
+		let (mut current_lvl, mut current_index, mut current_symbol) = (lvl, index, symbol);
+		for step in 0.. {
+			let hash = match self.tree.decoders[current_lvl as usize].hash_function.hash_symbol(&current_symbol) {
+				Some(hash) => hash,
+				None => return None,
+			};
+			if let Some(expected) = self.known_hash(current_lvl, current_index) {
+				return if expected == hash { Some(authenticated) } else { None };
+			}
+			// the hash is not known yet, check it against the parent symbol in the proof
+			let parent = match proof.get(step) {
+				Some(parent) => *parent,
+				None => return None,
+			};
+			match symbol_to_up(&parent) {
+				Some(hashes) if hashes[layout.hash_slot(current_lvl, current_index)] == hash => {},
+				_ => return None,
+			}
+			current_index = layout.parent(current_lvl, current_index);
+			current_lvl += 1;
+			current_symbol = parent;
+			authenticated.push((current_lvl, current_index, current_symbol));
+		}
+		None
+	}
+
+	// A symbol with some index on layer lvl arrives from a peer, with its Merkle proof (symbols on the upper layers)
+	// The proof can be shorter than the full path, as long as it reaches a symbol whose hash is already known
+	pub fn receive(&mut self, lvl: u32, index: u64, symbol: Symbol, proof: &[Symbol]) -> Vec<DecodeEvent> {
+		if self.outcome.is_some() {
+			return vec![];
+		}
+		if lvl >= self.tree.height || index >= self.tree.decoders[lvl as usize].n {
+			return vec![DecodeEvent::Rejected(lvl, index)];
+		}
+		match (lvl, symbol) {
+			(0, Symbol::Base(_)) => {},
+			(l, Symbol::Upper(_)) if l > 0 => {},
+			_ => return vec![DecodeEvent::Rejected(lvl, index)],
+		}
+		if self.tree.decoders[lvl as usize].decoded[index as usize] {
+			return vec![DecodeEvent::Duplicate(lvl, index)];
+		}
+		let authenticated = match self.verify(lvl, index, symbol, proof) {
+			Some(authenticated) => authenticated,
+			None => return vec![DecodeEvent::Rejected(lvl, index)],
+		};
+
+		let mut events = vec![];
+		for (l, i, s) in authenticated.into_iter() {
+			let decoder = &mut self.tree.decoders[l as usize];
+			let (new_indices, _) = decoder.symbol_update_from_reception(&[s.bytes()], &[i]);
+			if !new_indices.is_empty() {
+				self.tree.observer.received(l, i);
+				decoder.parity_update_observed(&new_indices, &mut *self.tree.observer);
+				events.push(DecodeEvent::Received(l, i));
+			}
+		}
+		self.advance(&mut events);
+		events
+	}
+
+	// Peel the active layer as far as possible, returning the number of decoded symbols
+	fn peel(&mut self, lvl: u32) -> Result<u64, IncorrectCodingProof> {
+		let before = self.tree.decoders[lvl as usize].num_decoded_symbols;
+		loop {
+			if let Some(proof) = self.tree.not_zero_proof(lvl) {
+				return Err(proof);
+			}
+			let decoder = &mut self.tree.decoders[lvl as usize];
+			if decoder.degree_1_parities.is_empty() || decoder.num_decoded_symbols == decoder.n {
+				break;
+			}
+			match decoder.symbol_update_from_degree_1_parities_observed(&self.tree.hashes[lvl as usize], &mut *self.tree.observer) {
+				Ok((indices, _)) => {
+					decoder.parity_update_observed(&indices, &mut *self.tree.observer);
+				},
+				Err((err_level, err_parity, index_set, proof_symbols)) => {
+					return Err(self.tree.generate_incorrect_coding_proof(CodingErr::NotHash, err_level,
+						err_parity, proof_symbols, index_set, vec![], 1.0));
+				},
+			}
+		}
+		Ok(self.tree.decoders[lvl as usize].num_decoded_symbols - before)
+	}
+
+	// Decode the active layer, and move down the tree as long as layers get decoded
+	fn advance(&mut self, events: &mut Vec<DecodeEvent>) {
+		loop {
+			let lvl = self.active_layer;
+			match self.peel(lvl) {
+				Ok(0) => {},
+				Ok(decoded) => events.push(DecodeEvent::Decoded(lvl, decoded)),
+				Err(proof) => {
+					events.push(DecodeEvent::IncorrectCoding(proof.error_type, proof.level));
+					self.outcome = Some(Err(proof));
+					return;
+				},
+			}
+			let decoder = &self.tree.decoders[lvl as usize];
+			if decoder.num_decoded_symbols < decoder.n {
+				return;
+			}
+			events.push(DecodeEvent::LayerDecoded(lvl));
+			self.tree.observer.layer_decoded(lvl);
+			if lvl == 0 {
+				events.push(DecodeEvent::TreeDecoded);
+				self.outcome = Some(Ok(()));
+				return;
+			}
+			//the systematic symbols of a decoded layer are the hashes of the previous layer
+			self.tree.hashes[(lvl - 1) as usize] = symbol_to_hash(&self.tree.layout, lvl - 1, &decoder.symbol_values);
+			self.active_layer = lvl - 1;
+		}
+	}
+
+	// No more symbols will arrive: decode what can still be decoded, or construct a Stopped incorrect-coding proof
+	pub fn finish(&mut self) -> Result<(), IncorrectCodingProof> {
+		while self.outcome.is_none() {
+			let lvl = self.active_layer as usize;
+			let hashes = self.tree.hashes[lvl].clone();
+			if self.tree.mode == DecodingMode::Inactivation && self.tree.inactivate(lvl, &hashes) {
+				self.advance(&mut vec![]);
+				continue;
+			}
+			let decoder = &self.tree.decoders[lvl];
+			let stopping_set: Vec<u64> = (0..decoder.n).filter(|i| !decoder.decoded[*i as usize]).collect();
+			let stopping_ratio = (stopping_set.len() as f32) / (decoder.n as f32);
+			self.tree.observer.stalled(lvl as u32, stopping_set.len(), stopping_ratio);
+			let proof = self.tree.generate_incorrect_coding_proof(CodingErr::Stopped, lvl as u32, 0u64, vec![], vec![],
+				stopping_set, stopping_ratio);
+			self.outcome = Some(Err(proof));
+		}
+		self.outcome.clone().unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+	use rand::seq::SliceRandom;
+	use block::Block;
+	use fixtures::{codes_dir, header, transactions, test_block};
+	use codes::{read_codes, shared_codes};
+	use constants::BASE_SYMBOL_SIZE;
+	use decoder::{ErrorPattern, InjectedError};
+	use super::*;
+
+	// a symbol of the block and its full Merkle proof
+	fn sample(block: &Block, lvl: usize, index: u64) -> (Symbol, Vec<Symbol>) {
+		let (_, proof_indices) = block.merkle_proof(lvl, index as u32);
+		let proof = proof_indices.iter().enumerate().map(|(j, i)| block.tree_symbol(lvl + 1 + j, *i as usize)).collect();
+		(block.tree_symbol(lvl, index as usize), proof)
+	}
+
+	// every symbol of the tree with its Merkle proof, in a random order
+	fn stream<R: Rng>(block: &Block, rng: &mut R) -> Vec<(u32, u64, Symbol, Vec<Symbol>)> {
+		let mut symbols = vec![];
+		for lvl in 0..block.coded_tree.len() {
+			for index in 0..block.layer_length(lvl) as u64 {
+				let (symbol, proof) = sample(block, lvl, index);
+				symbols.push((lvl as u32, index, symbol, proof));
+			}
+		}
+		symbols.shuffle(rng);
+		symbols
+	}
+
+	#[test]
+	fn test_online_decoding_overlaps_with_download() {
+		let mut rng = StdRng::seed_from_u64(10);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let mut decoder = OnlineTreeDecoder::new(&shared_codes(&codes), &block.block_header.coded_merkle_roots_hashes, DecodingMode::Peeling);
+
+		let symbols = stream(&block, &mut rng);
+		let mut received = 0;
+		let mut layers_decoded = vec![];
+		for (lvl, index, symbol, proof) in symbols.iter() {
+			let events = decoder.receive(*lvl, *index, *symbol, proof);
+			layers_decoded.extend(events.iter().cloned().filter(|e| match *e { DecodeEvent::LayerDecoded(_) => true, _ => false }));
+			if decoder.outcome.is_some() {
+				assert_eq!(events.last(), Some(&DecodeEvent::TreeDecoded));
+				break;
+			}
+			assert!(events.contains(&DecodeEvent::Received(*lvl, *index)) || events.contains(&DecodeEvent::Duplicate(*lvl, *index)));
+			received += 1;
+		}
+		// the tree is decoded before all symbols arrive, and the top layer before the base layer
+		assert!(received < symbols.len());
+		assert_eq!(layers_decoded, vec![DecodeEvent::LayerDecoded(1), DecodeEvent::LayerDecoded(0)]);
+		assert!(decoder.finish().is_ok());
+		for i in 0..block.layer_length(0) {
+			assert_eq!(decoder.tree.decoders[0].symbol(i as u64), Some(block.coded_tree[0].symbol(i)), "base symbol {} is not decoded", i);
+		}
+	}
+
+	#[test]
+	fn test_online_decoder_rejects_invalid_symbols() {
+		let mut rng = StdRng::seed_from_u64(11);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let mut decoder = OnlineTreeDecoder::new(&shared_codes(&codes), &block.block_header.coded_merkle_roots_hashes, DecodingMode::Peeling);
+
+		let (symbol, proof) = sample(&block, 0, 5);
+		let corrupted = match symbol {
+			Symbol::Base(mut sym) => { sym[0] ^= 1; Symbol::Base(sym) },
+			_ => unreachable!(),
+		};
+		assert_eq!(decoder.receive(0, 5, corrupted, &proof), vec![DecodeEvent::Rejected(0, 5)]);
+		// the parent of the symbol is not known yet, so a proof is needed
+		assert_eq!(decoder.receive(0, 5, symbol, &[]), vec![DecodeEvent::Rejected(0, 5)]);
+		assert_eq!(decoder.receive(0, 100000, symbol, &proof), vec![DecodeEvent::Rejected(0, 100000)]);
+		assert_eq!(decoder.receive(1, 5, symbol, &proof), vec![DecodeEvent::Rejected(1, 5)]);
+		assert_eq!(decoder.receive(0, 5, symbol, &proof), vec![DecodeEvent::Received(0, 5), DecodeEvent::Received(1, proof_index(&block, 5))]);
+		assert_eq!(decoder.receive(0, 5, symbol, &proof), vec![DecodeEvent::Duplicate(0, 5)]);
+
+		// once the parent symbol is known, a sibling does not need a proof
+		let sibling = 4;
+		let (symbol, _) = sample(&block, 0, sibling);
+		assert_eq!(decoder.receive(0, sibling, symbol, &[]), vec![DecodeEvent::Received(0, sibling)]);
+	}
+
+	fn proof_index(block: &Block, index: u64) -> u64 {
+		block.merkle_proof(0, index as u32).1[0] as u64
+	}
+
+	#[test]
+	fn test_online_decoder_detects_incorrect_coding() {
+		let mut rng = StdRng::seed_from_u64(12);
+		let (codes_for_encoding, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let errors = vec![InjectedError {layer: 0, index: 0, pattern: ErrorPattern::FlipAll}];
+		let block = Block::new_with_errors(header(), &transactions(), 128 * BASE_SYMBOL_SIZE, 256, &codes_for_encoding,
+			&errors, &mut rng);
+		let mut decoder = OnlineTreeDecoder::new(&shared_codes(&codes_for_decoding), &block.block_header.coded_merkle_roots_hashes,
+			DecodingMode::Peeling);
+		let mut detected = None;
+		for (lvl, index, symbol, proof) in stream(&block, &mut rng).iter() {
+			for event in decoder.receive(*lvl, *index, *symbol, proof) {
+				if let DecodeEvent::IncorrectCoding(error_type, level) = event {
+					detected = Some((error_type, level));
+				}
+			}
+		}
+		assert!(detected.is_some());
+		let proof = decoder.finish().err().expect("incorrect coding is not detected");
+		assert_eq!(Some((proof.error_type, proof.level)), detected);
+		assert!(block.block_header.verify_incorrect_coding_proof(&codes_for_decoding, &proof));
+	}
+
+	#[test]
+	fn test_finish_with_stopping_set() {
+		let mut rng = StdRng::seed_from_u64(9);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let n = block.layer_length(0) as u64;
+		let mut order: Vec<u64> = (0..n).collect();
+		order.shuffle(&mut rng);
+
+		let mut results = vec![];
+		for mode in [DecodingMode::Peeling, DecodingMode::Inactivation].iter() {
+			let mut decoder = OnlineTreeDecoder::new(&shared_codes(&codes), &block.block_header.coded_merkle_roots_hashes, *mode);
+			for index in 0..block.layer_length(1) as u64 {
+				decoder.receive(1, index, block.tree_symbol(1, index as usize), &[]);
+			}
+			for index in order[0..(n / 2) as usize].iter() {
+				decoder.receive(0, *index, block.tree_symbol(0, *index as usize), &[]);
+			}
+			results.push(decoder.finish().map_err(|proof| (proof.error_type, proof.level)));
+		}
+		assert_eq!(results, vec![Err((CodingErr::Stopped, 0)), Ok(())]);
+	}
+}
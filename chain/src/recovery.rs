@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use hash::H256;
+use block_header::BlockHeader;
+use cmt_hash::CmtHasher;
+use decoder::{Code, Symbol, symbol_to_up};
+use layout::CmtLayout;
+use symbol_ops::add_symbol;
+use SymbolBase;
+
+// Targeted recovery of a few base symbols of CMT, for a node that only needs some transactions
+// Recovery plans which symbols to request and which parity equations to peel, by walking the peeling graph backwards
+// from the wanted symbols: a symbol is requested if it may be available, and is otherwise recovered by the cheapest of
+// its parity equations, whose other symbols are obtained in the same way. The hashes of the symbols used on a layer
+// come from their parents on the layer above, which are planned in turn up to the header.
+// The plan is carried out from the top layer down. Symbols that are not returned or do not match their hash, and
+// equations that do not yield the hash of their symbol, are excluded, and the rest is planned again.
+
+// cost of a symbol that cannot be obtained
+const UNREACHABLE: u64 = u64::MAX;
+
+// A base symbol recovered with its verified hash
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recovered {
+	pub index: u64,
+	pub symbol: SymbolBase,
+	pub hash: H256,
+}
+
+// The symbols to request on a layer, and the peeling steps recovering the others, each after the steps it depends on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayerPlan {
+	pub requests: Vec<u64>,
+	pub peeling: Vec<(u64, u64)>, // (symbol, parity equation) pairs
+}
+
+impl LayerPlan {
+	// symbols checked against their hashes: the requested and the peeled symbols
+	pub fn verified(&self) -> Vec<u64> {
+		self.requests.iter().cloned().chain(self.peeling.iter().map(|&(s, _)| s)).collect()
+	}
+}
+
+// Plan to recover some base symbols, from the base layer up: the layers above provide the hashes of the symbols used below
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecoveryPlan {
+	pub layers: Vec<LayerPlan>,
+	pub unreachable: Vec<u64>, // wanted base indices that cannot be obtained with what is known to be available
+}
+
+impl RecoveryPlan {
+	// number of symbols to request on all layers
+	pub fn cost(&self) -> usize {
+		self.layers.iter().map(|layer| layer.requests.len()).sum()
+	}
+}
+
+pub struct RecoveryReport {
+	pub recovered: Vec<Recovered>,
+	pub failed: Vec<u64>, // requested base indices that cannot be recovered
+	pub requests: Vec<u64>, // number of symbols requested from the source on each layer
+	pub peeling_steps: u64, // number of parity equations used to recover symbols
+	pub plan: RecoveryPlan, // the last plan, which was carried out
+}
+
+pub struct TargetedRecovery<'a, F> where F: FnMut(u32, u64) -> Option<Symbol> {
+	codes: &'a Vec<Code>, // codes used for decoding on all layers of CMT
+	top_hashes: Vec<H256>, // hashes of the top layer, checked against the header
+	layout: CmtLayout,
+	hasher: &'static dyn CmtHasher,
+	fetch: F, // requests a symbol on some layer from the network, None if it is not returned
+	known: Vec<HashMap<u64, Symbol>>, // verified symbols on each layer
+	unavailable: Vec<HashSet<u64>>, // symbols not returned or not matching their hash on each layer
+	inconsistent: Vec<HashSet<u64>>, // parity equations not yielding the hash of their symbol on each layer
+	requests: Vec<u64>,
+	peeling_steps: u64,
+}
+
+// XOR of symbols of the same layer
+fn sum_symbols(symbols: &[Symbol]) -> Option<Symbol> {
+	let mut sum = match symbols.first() {
+		Some(s) => *s,
+		None => return None,
+	};
+	for s in symbols[1..].iter() {
+		if !add_symbol(&mut sum, s) {
+			return None;
+		}
+	}
+	Some(sum)
+}
+
+impl<'a, F> TargetedRecovery<'a, F> where F: FnMut(u32, u64) -> Option<Symbol> {
+	// Recover symbols of the tree committed by a block header, checking them with the hash function of the header
+	// In single-root mode, served are the hashes of the top layer served with the block. Returns None if they do not
+	// match the root, or if the codes do not fit the layout of the header
+	pub fn from_header(codes: &'a Vec<Code>, header: &BlockHeader, served: &[H256], fetch: F) -> Option<Self> {
+		let (layout, top_hashes) = match (header.layout(), header.top_hashes(served)) {
+			(Some(layout), Some(hashes)) => (layout, hashes),
+			_ => return None,
+		};
+		let height = layout.height();
+		if codes.len() != height || (0..height).any(|lvl| codes[lvl].symbols.len() as u64 != layout.n(lvl as u32)) {
+			return None;
+		}
+		Some(TargetedRecovery {
+			codes: codes,
+			top_hashes: top_hashes,
+			layout: layout,
+			hasher: header.hasher(),
+			fetch: fetch,
+			known: vec![HashMap::new(); height],
+			unavailable: vec![HashSet::new(); height],
+			inconsistent: vec![HashSet::new(); height],
+			requests: vec![0; height],
+			peeling_steps: 0,
+		})
+	}
+
+	// Hash of a symbol, read from its verified parent symbol, or from the top-layer hashes
+	fn hash_of(&self, lvl: u32, index: u64) -> Option<H256> {
+		if self.layout.is_top(lvl) {
+			return self.top_hashes.get(index as usize).cloned();
+		}
+		self.known[lvl as usize + 1].get(&self.layout.parent(lvl, index))
+			.and_then(symbol_to_up).map(|hashes| hashes[self.layout.hash_slot(lvl, index)])
+	}
+
+	// Cost of a symbol before peeling: nothing if it is known, one request if it may be available
+	fn request_cost(&self, lvl: u32, index: u64, unverifiable: &HashSet<u64>) -> u64 {
+		if self.known[lvl as usize].contains_key(&index) {
+			0
+		} else if self.unavailable[lvl as usize].contains(&index) || unverifiable.contains(&index) {
+			UNREACHABLE
+		} else {
+			1
+		}
+	}
+
+	// Cheapest plan to obtain the targets on a layer, and the targets that cannot be obtained
+	// Unverifiable symbols have no obtainable hash and can be neither requested nor peeled
+	fn plan_layer(&self, lvl: u32, targets: &[u64], unverifiable: &HashSet<u64>) -> (LayerPlan, Vec<u64>) {
+		let code = &self.codes[lvl as usize];
+		let inconsistent = &self.inconsistent[lvl as usize];
+
+		// walk back from the targets through the equations of the symbols that cannot be requested
+		let mut cost: HashMap<u64, u64> = HashMap::new();
+		let mut nodes = vec![];
+		let mut queue = vec![];
+		for t in targets.iter() {
+			if *t < code.symbols.len() as u64 && !cost.contains_key(t) {
+				cost.insert(*t, self.request_cost(lvl, *t, unverifiable));
+				nodes.push(*t);
+				queue.push(*t);
+			}
+		}
+		while let Some(s) = queue.pop() {
+			if cost[&s] != UNREACHABLE || unverifiable.contains(&s) {
+				continue;
+			}
+			for p in code.symbols[s as usize].iter().filter(|p| !inconsistent.contains(p)) {
+				for o in code.parities[*p as usize].iter() {
+					if !cost.contains_key(o) {
+						cost.insert(*o, self.request_cost(lvl, *o, unverifiable));
+						nodes.push(*o);
+						queue.push(*o);
+					}
+				}
+			}
+		}
+
+		// cheapest derivation of each symbol, by relaxing the peeling steps until no cost decreases
+		let mut peel: HashMap<u64, u64> = HashMap::new();
+		loop {
+			let mut changed = false;
+			for s in nodes.iter() {
+				if cost[s] == 0 || unverifiable.contains(s) {
+					continue;
+				}
+				for p in code.symbols[*s as usize].iter().filter(|p| !inconsistent.contains(p)) {
+					let mut sum = 0u64;
+					for o in code.parities[*p as usize].iter().filter(|o| *o != s) {
+						let c = match cost.get(o) {
+							Some(c) => *c,
+							None => self.request_cost(lvl, *o, unverifiable),
+						};
+						sum = sum.saturating_add(c);
+					}
+					if sum < cost[s] {
+						cost.insert(*s, sum);
+						peel.insert(*s, *p);
+						changed = true;
+					}
+				}
+			}
+			if !changed {
+				break;
+			}
+		}
+
+		let mut plan = LayerPlan::default();
+		let mut unreachable = vec![];
+		let mut visited = HashSet::new();
+		for t in targets.iter() {
+			match cost.get(t) {
+				Some(c) if *c != UNREACHABLE => self.collect(lvl, *t, &peel, &mut visited, &mut plan),
+				_ => unreachable.push(*t),
+			}
+		}
+		(plan, unreachable)
+	}
+
+	// Add the requests and peeling steps deriving a symbol to the plan, the steps it depends on first
+	fn collect(&self, lvl: u32, index: u64, peel: &HashMap<u64, u64>, visited: &mut HashSet<u64>, plan: &mut LayerPlan) {
+		if !visited.insert(index) || self.known[lvl as usize].contains_key(&index) {
+			return;
+		}
+		match peel.get(&index) {
+			Some(p) => {
+				for o in self.codes[lvl as usize].parities[*p as usize].iter().filter(|o| **o != index) {
+					self.collect(lvl, *o, peel, visited, plan);
+				}
+				plan.peeling.push((index, *p));
+			},
+			None => plan.requests.push(index),
+		}
+	}
+
+	// Plan the recovery of the base symbols with the given indices from what is known so far
+	pub fn plan(&self, indices: &[u64]) -> RecoveryPlan {
+		let height = self.layout.height();
+		let mut unverifiable = vec![HashSet::new(); height];
+		'replan: loop {
+			let mut layers: Vec<LayerPlan> = vec![];
+			let mut targets = indices.to_vec();
+			let mut unreachable = vec![];
+			for lvl in 0..height {
+				let (layer, missing) = self.plan_layer(lvl as u32, &targets, &unverifiable[lvl]);
+				if lvl == 0 {
+					unreachable = missing;
+				} else if !missing.is_empty() {
+					// the symbols below parents that cannot be obtained cannot be verified
+					for s in layers[lvl - 1].verified() {
+						if missing.contains(&self.layout.parent(lvl as u32 - 1, s)) {
+							unverifiable[lvl - 1].insert(s);
+						}
+					}
+					continue 'replan;
+				}
+				// parents providing the hashes of the symbols used on this layer
+				let mut parents: Vec<u64> = vec![];
+				if !self.layout.is_top(lvl as u32) {
+					for s in layer.verified() {
+						let parent = self.layout.parent(lvl as u32, s);
+						if !self.known[lvl + 1].contains_key(&parent) && !parents.contains(&parent) {
+							parents.push(parent);
+						}
+					}
+				}
+				layers.push(layer);
+				targets = parents;
+			}
+			return RecoveryPlan { layers: layers, unreachable: unreachable };
+		}
+	}
+
+	// Carry out a plan from the top layer down, false if a symbol or an equation failed and the rest must be planned again
+	fn execute(&mut self, plan: &RecoveryPlan) -> bool {
+		for (lvl, layer) in plan.layers.iter().enumerate().rev() {
+			let mut complete = true;
+			for index in layer.requests.iter() {
+				self.requests[lvl] += 1;
+				let verified = match (self.hash_of(lvl as u32, *index), (self.fetch)(lvl as u32, *index)) {
+					(Some(hash), Some(symbol)) if self.hasher.hash_symbol(&symbol) == Some(hash) => Some(symbol),
+					_ => None,
+				};
+				match verified {
+					Some(symbol) => { self.known[lvl].insert(*index, symbol); },
+					None => {
+						self.unavailable[lvl].insert(*index);
+						complete = false;
+					},
+				}
+			}
+			for &(index, p) in layer.peeling.iter() {
+				let others: Option<Vec<Symbol>> = self.codes[lvl].parities[p as usize].iter().filter(|s| **s != index)
+					.map(|s| self.known[lvl].get(s).cloned()).collect();
+				let symbol = match others {
+					Some(others) => sum_symbols(&others),
+					None => continue, // a symbol of the equation failed
+				};
+				self.peeling_steps += 1;
+				match (symbol, self.hash_of(lvl as u32, index)) {
+					(Some(symbol), Some(hash)) if self.hasher.hash_symbol(&symbol) == Some(hash) => {
+						self.known[lvl].insert(index, symbol);
+					},
+					_ => {
+						self.inconsistent[lvl].insert(p);
+						complete = false;
+					},
+				}
+			}
+			if !complete {
+				return false;
+			}
+		}
+		true
+	}
+
+	// Recover the base symbols with the given indices, planning again after every failure
+	pub fn recover(&mut self, indices: &[u64]) -> RecoveryReport {
+		let plan = loop {
+			let plan = self.plan(indices);
+			if self.execute(&plan) {
+				break plan;
+			}
+		};
+		let mut recovered = vec![];
+		let mut failed = vec![];
+		for index in indices.iter() {
+			match self.known[0].get(index) {
+				Some(Symbol::Base(symbol)) => recovered.push(Recovered { index: *index, symbol: *symbol, hash: self.hasher.hash(symbol) }),
+				_ => failed.push(*index),
+			}
+		}
+		RecoveryReport {
+			recovered: recovered,
+			failed: failed,
+			requests: self.requests.clone(),
+			peeling_steps: self.peeling_steps,
+			plan: plan,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+	use fixtures::{codes_dir, test_block};
+	use codes::read_codes;
+	use constants::BASE_SYMBOL_SIZE;
+	use super::*;
+
+	#[test]
+	fn test_recovery_of_available_symbols() {
+		let mut rng = StdRng::seed_from_u64(13);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let mut recovery = TargetedRecovery::from_header(&codes, &block.block_header, &[], 
+			|lvl, i| Some(block.tree_symbol(lvl as usize, i as usize))).unwrap();
+		// with nothing known, the plan requests the symbols and their parents
+		let plan = recovery.plan(&[3, 2, 200]);
+		assert_eq!(plan.layers[0], LayerPlan { requests: vec![3, 2, 200], peeling: vec![] });
+		assert_eq!(plan.cost(), 5);
+		let report = recovery.recover(&[3, 2, 200]);
+		assert_eq!(report.recovered.iter().map(|r| r.index).collect::<Vec<u64>>(), vec![3, 2, 200]);
+		assert!(report.failed.is_empty());
+		// symbols 2 and 3 share their parent on layer 1
+		assert_eq!(report.requests, vec![3, 2]);
+		assert_eq!(report.peeling_steps, 0);
+		assert_eq!(report.plan, plan);
+		for r in report.recovered.iter() {
+			assert_eq!(r.symbol.to_vec(), block.base_sample(r.index as usize).0.to_vec());
+		}
+	}
+
+	#[test]
+	fn test_recovery_of_withheld_and_corrupted_symbols() {
+		let mut rng = StdRng::seed_from_u64(14);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		// symbol 7 is withheld and symbol 9 is returned corrupted
+		let source = |lvl: u32, i: u64| match (lvl, i) {
+			(0, 7) => None,
+			(0, 9) => Some(Symbol::Base([0u8; BASE_SYMBOL_SIZE])),
+			_ => Some(block.tree_symbol(lvl as usize, i as usize)),
+		};
+		let mut recovery = TargetedRecovery::from_header(&codes, &block.block_header, &[], source).unwrap();
+		let report = recovery.recover(&[7, 9]);
+		assert!(report.failed.is_empty());
+		assert_eq!(report.peeling_steps, 2);
+		for r in report.recovered.iter() {
+			assert_eq!(r.symbol.to_vec(), block.base_sample(r.index as usize).0.to_vec());
+		}
+		// the last plan peels each symbol with its smallest equation
+		for &(s, p) in report.plan.layers[0].peeling.iter() {
+			let smallest = codes[0].symbols[s as usize].iter().map(|q| codes[0].parities[*q as usize].len()).min();
+			assert_eq!(Some(codes[0].parities[p as usize].len()), smallest);
+		}
+		// far fewer symbols than the whole base layer are requested
+		assert!(report.requests[0] < (block.layer_length(0) as u64) / 4);
+	}
+
+	// Withholding many base symbols requires chains of peeling steps, which the plan follows to any depth
+	#[test]
+	fn test_recovery_of_many_withheld_symbols() {
+		let mut rng = StdRng::seed_from_u64(16);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let withheld: HashSet<u64> = (0..block.layer_length(0) as u64).filter(|_| rng.gen_bool(0.3)).collect();
+		let source = |lvl: u32, i: u64| if lvl == 0 && withheld.contains(&i) { None } else { Some(block.tree_symbol(lvl as usize, i as usize)) };
+		let mut recovery = TargetedRecovery::from_header(&codes, &block.block_header, &[], source).unwrap();
+		let wanted: Vec<u64> = withheld.iter().cloned().filter(|i| *i < 32).collect();
+		let report = recovery.recover(&wanted);
+		assert!(report.failed.is_empty());
+		assert!(report.plan.unreachable.is_empty());
+		for r in report.recovered.iter() {
+			assert_eq!(r.symbol.to_vec(), block.base_sample(r.index as usize).0.to_vec());
+		}
+		// nesting of the peeling steps in the last plan: a step depends on the steps recovering the other symbols of its equation
+		let mut nesting: HashMap<u64, u32> = HashMap::new();
+		for &(s, p) in report.plan.layers[0].peeling.iter() {
+			let below = codes[0].parities[p as usize].iter().filter_map(|o| nesting.get(o)).max().cloned().unwrap_or(0);
+			nesting.insert(s, below + 1);
+		}
+		assert!(nesting.values().max().cloned().unwrap_or(0) > 2);
+	}
+
+	// In single-root mode the top-layer hashes are served with the block and checked against the root
+	#[test]
+	fn test_recovery_with_single_root() {
+		let mut rng = StdRng::seed_from_u64(17);
+		let block = test_block(&mut rng).with_single_root();
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let served = block.top_hashes();
+		let fetch = |lvl: u32, i: u64| Some(block.tree_symbol(lvl as usize, i as usize));
+		assert!(TargetedRecovery::from_header(&codes, &block.block_header, &[], fetch).is_none());
+		assert!(TargetedRecovery::from_header(&codes, &block.block_header, &served[1..], fetch).is_none());
+		// the parent of symbol 5 is withheld, and recovered on layer 1 to read the hash of symbol 5
+		let parent = recovery_parent(&block.block_header, 5);
+		let source = |lvl: u32, i: u64| if (lvl, i) == (1, parent) { None } else { Some(block.tree_symbol(lvl as usize, i as usize)) };
+		let mut recovery = TargetedRecovery::from_header(&codes, &block.block_header, &served, source).unwrap();
+		let report = recovery.recover(&[5]);
+		assert_eq!(report.recovered.len(), 1);
+		assert_eq!(report.recovered[0].symbol.to_vec(), block.base_sample(5).0.to_vec());
+		assert_eq!(report.plan.layers[1].peeling.iter().map(|&(s, _)| s).collect::<Vec<u64>>(), vec![parent]);
+	}
+
+	fn recovery_parent(header: &BlockHeader, index: u64) -> u64 {
+		header.layout().unwrap().parent(0, index)
+	}
+
+	#[test]
+	fn test_recovery_fails_without_symbols() {
+		let mut rng = StdRng::seed_from_u64(15);
+		let block = test_block(&mut rng);
+		let (_, codes) = read_codes(&codes_dir(), vec![128, 64]);
+		let source = |lvl: u32, i: u64| if lvl == 0 { None } else { Some(block.tree_symbol(lvl as usize, i as usize)) };
+		let mut recovery = TargetedRecovery::from_header(&codes, &block.block_header, &[], source).unwrap();
+		let report = recovery.recover(&[0]);
+		assert!(report.recovered.is_empty());
+		assert_eq!(report.failed, vec![0]);
+		assert_eq!(report.plan.unreachable, vec![0]);
+	}
+}
@@ -0,0 +1,187 @@
+use rand::Rng;
+use rand::seq::index;
+use rand::distributions::{Distribution, Uniform};
+use std::{cmp, io};
+use constants::{BASE_SYMBOL_SIZE, AGGREGATE, MAX_CMT_LAYERS};
+use decoder::Symbol;
+use layout::CmtLayout;
+use ser::{Serializable, Deserializable, Stream, Reader, Error, CompactInteger};
+
+// Parameters of a sampling run on a coded Merkle tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingParams {
+	pub base_samples: u32, // number of symbols drawn from the base layer
+	pub with_replacement: bool, // if false, every draw on a layer is a distinct symbol
+	// number of symbols drawn from each upper layer, starting from layer 1
+	// if None, upper layers are sampled along the Merkle proofs of the base samples, using sample_parity_sibling
+	pub layer_budgets: Option<Vec<u32>>,
+}
+
+impl SamplingParams {
+	// s draws with replacement on the base layer, upper layers follow the Merkle proofs
+	// this is the sampling performed by Block::sampling_to_decode
+	pub fn with_replacement(s: u32) -> Self {
+		SamplingParams { base_samples: s, with_replacement: true, layer_budgets: None }
+	}
+
+	// s distinct symbols on the base layer, upper layers follow the Merkle proofs
+	pub fn without_replacement(s: u32) -> Self {
+		SamplingParams { base_samples: s, with_replacement: false, layer_budgets: None }
+	}
+
+	// number of draws on layer lvl, None if the layer is sampled along the Merkle proofs
+	pub fn budget(&self, lvl: usize) -> Option<u32> {
+		if lvl == 0 {
+			return Some(self.base_samples);
+		}
+		match self.layer_budgets {
+			Some(ref budgets) => Some(budgets.get(lvl - 1).cloned().unwrap_or(0)),
+			None => None,
+		}
+	}
+}
+
+// Statistics of a sampling run, one entry per layer of CMT
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingStats {
+	pub draws: Vec<u64>, // number of symbol requests on each layer, including repeated ones
+	pub unique_symbols: Vec<u64>, // number of distinct symbols obtained on each layer
+	pub bytes_downloaded: u64, // size of the distinct symbols obtained on all layers
+}
+
+impl SamplingStats {
+	// Compute the statistics of the draws on each layer
+	// A symbol drawn more than once is only downloaded once
+	pub fn from_draws(draws: &[Vec<u64>], unique: &[Vec<u64>]) -> Self {
+		SamplingStats::from_sizes(draws, unique, symbol_size)
+	}
+
+	// from_draws for a tree with the symbol sizes of layout, e.g. a tree with another aggregation factor
+	pub fn from_layers(layout: &CmtLayout, draws: &[Vec<u64>], unique: &[Vec<u64>]) -> Self {
+		SamplingStats::from_sizes(draws, unique, |lvl| layout.symbol_size(lvl as u32))
+	}
+
+	fn from_sizes<F: Fn(usize) -> usize>(draws: &[Vec<u64>], unique: &[Vec<u64>], symbol_size: F) -> Self {
+		let mut bytes = 0u64;
+		for (lvl, indices) in unique.iter().enumerate() {
+			bytes += (indices.len() * symbol_size(lvl)) as u64;
+		}
+		SamplingStats {
+			draws: draws.iter().map(|d| d.len() as u64).collect(),
+			unique_symbols: unique.iter().map(|u| u.len() as u64).collect(),
+			bytes_downloaded: bytes,
+		}
+	}
+}
+
+// Symbols sampled from each layer of CMT, with their indices, in the form taken by TreeDecoder::run_tree_decoder
+#[derive(Debug, Clone, PartialEq)]
+pub struct Samples {
+	pub symbols: Vec<Vec<Symbol>>,
+	pub indices: Vec<Vec<u64>>,
+}
+
+impl Samples {
+	// Samples to be written out, from the views of the symbols sampled on each layer
+	pub fn from_views(symbols: &[Vec<&[u8]>], indices: Vec<Vec<u64>>) -> Samples {
+		Samples {
+			symbols: symbols.iter().enumerate()
+				.map(|(lvl, layer)| layer.iter().map(|bytes| Symbol::from_bytes(lvl as u32, bytes)).collect()).collect(),
+			indices: indices,
+		}
+	}
+
+	// Views of the sampled symbols, as read by the decoder
+	pub fn views(&self) -> Vec<Vec<&[u8]>> {
+		self.symbols.iter().map(|layer| layer.iter().map(Symbol::bytes).collect()).collect()
+	}
+}
+
+// Samples are sent as the number of layers, then the indices and the symbols of every layer
+impl Serializable for Samples {
+	fn serialize(&self, stream: &mut Stream) {
+		stream.append(&CompactInteger::from(self.indices.len()));
+		for (symbols, indices) in self.symbols.iter().zip(self.indices.iter()) {
+			stream.append_list(indices).append_list(symbols);
+		}
+	}
+}
+
+impl Deserializable for Samples {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read {
+		let num_layers = reader.read_len()?;
+		if num_layers > MAX_CMT_LAYERS {
+			return Err(Error::MalformedData);
+		}
+		let mut samples = Samples { symbols: vec![], indices: vec![] };
+		for _ in 0..num_layers {
+			let indices: Vec<u64> = reader.read_list()?;
+			let symbols: Vec<Symbol> = reader.read_list()?;
+			if indices.len() != symbols.len() {
+				return Err(Error::MalformedData);
+			}
+			samples.indices.push(indices);
+			samples.symbols.push(symbols);
+		}
+		Ok(samples)
+	}
+}
+
+// size in bytes of a symbol on layer lvl of CMT
+pub fn symbol_size(lvl: usize) -> usize {
+	if lvl == 0 { BASE_SYMBOL_SIZE } else { 32 * AGGREGATE }
+}
+
+// Draw count symbol indices out of n uniformly at random
+// Without replacement, at most n distinct indices are returned. The indices are then drawn out of the first
+// usize::MAX indices only, which limits n on 32-bit targets (layers of CMT are far smaller)
+pub fn draw_indices<R: Rng>(n: u64, count: u32, with_replacement: bool, rng: &mut R) -> Vec<u64> {
+	if n == 0 {
+		return vec![];
+	}
+	if with_replacement {
+		let die = Uniform::from(0..n);
+		(0..count).map(|_| die.sample(rng)).collect()
+	} else {
+		let n = cmp::min(n, usize::MAX as u64) as usize;
+		let amount = cmp::min(count as usize, n);
+		index::sample(rng, n, amount).into_iter().map(|i| i as u64).collect()
+	}
+}
+
+// Remove repeated indices while keeping the order of first appearance
+pub fn dedup_indices(indices: &[u64]) -> Vec<u64> {
+	let mut seen = ::std::collections::HashSet::new();
+	indices.iter().filter(|i| seen.insert(**i)).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use ser::{serialize, deserialize, Error};
+	use decoder::Symbol;
+	use constants::{BASE_SYMBOL_SIZE, AGGREGATE};
+	use super::Samples;
+
+	#[test]
+	fn test_samples_serialization() {
+		let samples = Samples {
+			symbols: vec![vec![Symbol::Base([1u8; BASE_SYMBOL_SIZE]), Symbol::Empty], vec![Symbol::Upper([2u8; 32 * AGGREGATE])]],
+			indices: vec![vec![3, 5], vec![1]],
+		};
+		let buffer = serialize(&samples);
+		assert_eq!(deserialize::<_, Samples>(buffer.as_ref()).unwrap(), samples);
+
+		// a layer with more indices than symbols
+		let samples = Samples { symbols: vec![vec![Symbol::Empty]], indices: vec![vec![1, 2]] };
+		assert_eq!(deserialize::<_, Samples>(serialize(&samples).as_ref()), Err(Error::MalformedData));
+	}
+
+	#[test]
+	fn test_samples_from_views() {
+		let (base, upper) = ([1u8; BASE_SYMBOL_SIZE], [2u8; 32 * AGGREGATE]);
+		let views: Vec<Vec<&[u8]>> = vec![vec![&base[..]], vec![&upper[..]]];
+		let samples = Samples::from_views(&views, vec![vec![3], vec![1]]);
+		assert_eq!(samples.symbols, vec![vec![Symbol::Base(base)], vec![Symbol::Upper(upper)]]);
+		assert_eq!(samples.views(), views);
+	}
+}
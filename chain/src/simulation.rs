@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use block::Block;
+use layout::CmtLayout;
+use decoder::{Code, TreeDecoder, IncorrectCodingProof};
+use sampling::{SamplingParams, draw_indices};
+
+// Symbols left undecoded when the peeling decoder of code starts from the erased symbols
+// This is the largest stopping set contained in erased (empty if peeling recovers every erased symbol)
+pub fn maximal_stopping_set(code: &Code, erased: &[u64]) -> Vec<u64> {
+	let mut is_erased = vec![false; code.symbols.len()];
+	for i in erased.iter() {
+		is_erased[*i as usize] = true;
+	}
+	// number of erased symbols connected to each parity node
+	let mut erased_degree: Vec<u32> = code.parities.iter()
+		.map(|parity| parity.iter().filter(|s| is_erased[**s as usize]).count() as u32)
+		.collect();
+	let mut degree_1_parities: Vec<u64> = (0..code.parities.len() as u64).filter(|p| erased_degree[*p as usize] == 1).collect();
+
+	while let Some(parity) = degree_1_parities.pop() {
+		if erased_degree[parity as usize] != 1 {
+			continue;
+		}
+		// the only erased symbol of this parity equation is recovered
+		let symbol = match code.parities[parity as usize].iter().find(|s| is_erased[**s as usize]) {
+			Some(s) => *s,
+			None => continue,
+		};
+		is_erased[symbol as usize] = false;
+		for p in code.symbols[symbol as usize].iter() {
+			erased_degree[*p as usize] -= 1;
+			if erased_degree[*p as usize] == 1 {
+				degree_1_parities.push(*p);
+			}
+		}
+	}
+	(0..code.symbols.len() as u64).filter(|s| is_erased[*s as usize]).collect()
+}
+
+// Find a stopping set of code, as a malicious block producer would do to make a layer undecodable
+// Start from the stopping set left by erasing a fraction erasure_fraction of the symbols,
+// then remove symbols one by one as long as a smaller stopping set remains
+// Returns an empty set if the erasures do not contain a stopping set
+pub fn find_stopping_set<R: Rng>(code: &Code, erasure_fraction: f64, rng: &mut R) -> Vec<u64> {
+	let n = code.symbols.len() as u64;
+	let erased = draw_indices(n, ((n as f64) * erasure_fraction) as u32, false, rng);
+	let mut stopping_set = maximal_stopping_set(code, &erased);
+
+	let mut candidates = stopping_set.clone();
+	candidates.shuffle(rng);
+	for symbol in candidates.iter() {
+		if !stopping_set.contains(symbol) {
+			continue;
+		}
+		let smaller: Vec<u64> = stopping_set.iter().cloned().filter(|s| s != symbol).collect();
+		let remaining = maximal_stopping_set(code, &smaller);
+		if !remaining.is_empty() {
+			stopping_set = remaining;
+		}
+	}
+	stopping_set
+}
+
+// Outcome of a withholding attack on a block
+pub struct WithholdingReport {
+	pub layer: usize, // layer of CMT on which the stopping set is withheld
+	pub stopping_ratio: f32, // fraction of the coded symbols on that layer that are withheld
+	pub num_clients: u32, // number of honest light clients sampling the block
+	pub detecting_clients: u32, // number of light clients that requested a withheld symbol
+	pub decoding_result: Result<(), IncorrectCodingProof>, // outcome of decoding at a full node, from the symbols forwarded by light clients
+}
+
+impl WithholdingReport {
+	// probability that a light client detects the withholding
+	pub fn detection_probability(&self) -> f32 {
+		(self.detecting_clients as f32) / (self.num_clients as f32)
+	}
+}
+
+// Whether a symbol with some index on layer lvl can be served while the symbols in withheld on layer layer are withheld
+// A symbol below the attacked layer cannot be served if its Merkle proof passes through a withheld symbol
+fn is_served(layout: &CmtLayout, withheld: &HashSet<u64>, layer: usize, lvl: usize, index: u64) -> bool {
+	if lvl > layer {
+		true
+	} else if lvl == layer {
+		!withheld.contains(&index)
+	} else {
+		!withheld.contains(&layout.proof_indices(lvl as u32, index)[layer - lvl - 1])
+	}
+}
+
+// Simulate a block producer that withholds the symbols in stopping_set on some layer of CMT
+// Each of num_clients light clients samples the block according to params, and detects the attack if any of its
+// requests is not answered, either because the symbol or a symbol of its Merkle proof is withheld.
+// The symbols it receives are forwarded to a full node, that tries to decode the tree
+pub fn simulate_withholding<R: Rng>(block: &Block, codes_for_decoding: &[Arc<Code>], layer: usize, stopping_set: &Vec<u64>,
+	params: &SamplingParams, num_clients: u32, rng: &mut R) -> WithholdingReport {
+	let height = block.coded_tree.len();
+	let withheld: HashSet<u64> = stopping_set.iter().cloned().collect();
+	let layout = block.layout();
+
+	// symbols received by the full node on each layer
+	let mut forwarded: Vec<Vec<u64>> = vec![vec![]; height];
+	let mut detecting_clients = 0;
+	for _ in 0..num_clients {
+		let mut draws = block.sample_indices(params, rng);
+		let requested: usize = draws.iter().map(|d| d.len()).sum();
+		for (lvl, layer_draws) in draws.iter_mut().enumerate() {
+			layer_draws.retain(|i| is_served(&layout, &withheld, layer, lvl, *i));
+		}
+		if draws.iter().map(|d| d.len()).sum::<usize>() < requested {
+			detecting_clients += 1;
+		}
+		for lvl in 0..height {
+			forwarded[lvl].append(&mut draws[lvl]);
+		}
+	}
+
+	let (symbols_all_levels, indices_all_levels) = block.collect_samples(&forwarded);
+	let mut decoder = TreeDecoder::new(codes_for_decoding, &block.block_header.coded_merkle_roots_hashes)
+		.with_hash_function(block.hash_function());
+	let decoding_result = decoder.run_tree_decoder(&symbols_all_levels, &indices_all_levels);
+
+	WithholdingReport {
+		layer: layer,
+		stopping_ratio: (stopping_set.len() as f32) / (block.layer_length(layer) as f32),
+		num_clients: num_clients,
+		detecting_clients: detecting_clients,
+		decoding_result: decoding_result,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+	use codes::{read_codes, shared_codes};
+	use fixtures::{codes_dir, test_block};
+	use decoder::CodingErr;
+	use super::*;
+
+	// every parity node connected to a stopping set is connected to it at least twice
+	fn is_stopping_set(code: &Code, set: &Vec<u64>) -> bool {
+		code.parities.iter().all(|parity| parity.iter().filter(|s| set.contains(s)).count() != 1)
+	}
+
+	#[test]
+	fn test_find_stopping_set() {
+		let mut rng = StdRng::seed_from_u64(4);
+		let (_, codes) = read_codes(&codes_dir(), vec![128]);
+		let stopping_set = find_stopping_set(&codes[0], 0.5, &mut rng);
+		assert!(!stopping_set.is_empty());
+		assert!(is_stopping_set(&codes[0], &stopping_set));
+		assert_eq!(maximal_stopping_set(&codes[0], &stopping_set), stopping_set);
+		// no symbol can be removed from the stopping set found
+		for i in stopping_set.iter() {
+			let smaller: Vec<u64> = stopping_set.iter().cloned().filter(|s| s != i).collect();
+			assert!(maximal_stopping_set(&codes[0], &smaller).is_empty());
+		}
+	}
+
+	#[test]
+	fn test_withholding_is_detected_and_stops_decoding() {
+		let mut rng = StdRng::seed_from_u64(5);
+		let block = test_block(&mut rng);
+		let (_, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes_for_decoding = shared_codes(&codes_for_decoding);
+		let stopping_set = find_stopping_set(&codes_for_decoding[0], 0.5, &mut rng);
+
+		let params = SamplingParams::with_replacement(100);
+		let report = simulate_withholding(&block, &codes_for_decoding, 0, &stopping_set, &params, 30, &mut rng);
+		assert!(report.detecting_clients > 0);
+		match report.decoding_result {
+			Err(proof) => {
+				match proof.error_type {
+					CodingErr::Stopped => {},
+					_ => panic!("withholding should stop the decoder"),
+				}
+				assert_eq!(proof.level, 0);
+				assert!(stopping_set.iter().all(|s| proof.stop_set.contains(s)));
+			},
+			Ok(()) => panic!("withheld symbols cannot be decoded"),
+		}
+	}
+
+	// Withholding on layer 1 also withholds the base symbols whose Merkle proofs pass through the withheld symbols
+	#[test]
+	fn test_withholding_on_upper_layer_hides_base_symbols() {
+		let mut rng = StdRng::seed_from_u64(6);
+		let block = test_block(&mut rng);
+		let layout = block.layout();
+		let (_, codes_for_decoding) = read_codes(&codes_dir(), vec![128, 64]);
+		let codes_for_decoding = shared_codes(&codes_for_decoding);
+		let stopping_set = find_stopping_set(&codes_for_decoding[1], 0.5, &mut rng);
+		assert!(!stopping_set.is_empty());
+		let withheld: HashSet<u64> = stopping_set.iter().cloned().collect();
+
+		// the draws of the clients are replayed from the same seed
+		let params = SamplingParams::with_replacement(5);
+		let num_clients = 40;
+		let mut replay = rng.clone();
+		let (mut on_layer, mut on_path) = (0, 0);
+		for _ in 0..num_clients {
+			let draws = block.sample_indices(&params, &mut replay);
+			if draws[1].iter().any(|i| withheld.contains(i)) {
+				on_layer += 1;
+			}
+			if draws[1].iter().any(|i| withheld.contains(i)) 
+				|| draws[0].iter().any(|i| withheld.contains(&layout.proof_indices(0, *i)[0])) {
+				on_path += 1;
+			}
+		}
+		assert!(on_path > on_layer);
+
+		let report = simulate_withholding(&block, &codes_for_decoding, 1, &stopping_set, &params, num_clients, &mut rng);
+		assert_eq!(report.detecting_clients, on_path);
+		match report.decoding_result {
+			Err(proof) => assert_eq!((proof.error_type, proof.level), (CodingErr::Stopped, 1)),
+			Ok(()) => panic!("withheld symbols cannot be decoded"),
+		}
+	}
+}
@@ -0,0 +1,369 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::Instant;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use ser::Serializable;
+use block::Block;
+use block_header::BlockHeader;
+use transaction::Transaction;
+use constants::AGGREGATE;
+use codes::{read_codes_of_rate, shared_codes};
+use coded_merkle_roots::errors_from_correct;
+use cmt_hash::HashFunction;
+use decoder::{Code, TreeDecoder, CodingErr};
+use inactivation::DecodingMode;
+use layout::CmtLayout;
+use sampling::SamplingParams;
+
+// Parameters of a sweep over reference designs
+// Every combination of a block size, a header size, a rate, an aggregation factor, a folder of codes and a number of samples
+// is a point of the sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepParams {
+	pub block_sizes: Vec<usize>, // size of the transactions of a block in bytes
+	pub header_sizes: Vec<u32>, // largest number of hashes on the top layer
+	pub rates: Vec<f32>, // coding rates of the layers, the codes in the folders must have them
+	pub aggregates: Vec<usize>, // numbers of hashes aggregated in a symbol of an upper layer
+	pub code_dirs: Vec<String>, // folders of the code files
+	pub sample_counts: Vec<u32>, // number of symbols sampled from the base layer in a trial
+	pub trials: u32, // number of decoding trials at every point
+	pub with_replacement: bool,
+	pub mode: DecodingMode,
+	pub hash_function: HashFunction,
+	pub corrupt: bool, // if true, the first parity symbol of the base layer is flipped, so that the trials find incorrect coding
+	pub seed: u64, // seed from which the padding of the blocks and the samples of all trials are drawn
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+	pub block_size: usize,
+	pub header_size: u32,
+	pub rate: f32,
+	pub aggregate: usize,
+	pub code_dir: String,
+	pub samples: u32,
+}
+
+// Results of the trials at a point of the sweep
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepResult {
+	pub point: SweepPoint,
+	pub layers: usize, // height of the coded Merkle tree
+	pub base_symbols: u64, // number of coded symbols on the base layer
+	pub trials: u32,
+	pub decoded: u32, // number of trials decoding the whole tree
+	pub stopped: u32, // number of trials stopping on a stopping set
+	pub mean_stopping_ratio: Option<f64>, // over the trials that stop
+	pub proofs: u32, // number of trials finding incorrect coding
+	pub mean_proof_size: Option<f64>, // size in bytes of the incorrect-coding proofs, over the trials that find one
+	pub mean_bytes_downloaded: f64,
+	pub encode_ms: f64, // time to build the coded Merkle tree of the block
+	pub mean_decode_ms: f64,
+}
+
+impl SweepResult {
+	pub fn decode_probability(&self) -> f64 {
+		(self.decoded as f64) / (self.trials as f64)
+	}
+}
+
+// outcome of a single decoding trial
+struct Trial {
+	decoded: bool,
+	stopping_ratio: Option<f32>,
+	proof_size: Option<usize>,
+	bytes_downloaded: u64,
+	decode_ms: f64,
+}
+
+fn mean<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+	let (sum, count) = values.fold((0.0, 0u32), |(sum, count), v| (sum + v, count + 1));
+	if count == 0 { None } else { Some(sum / (count as f64)) }
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+	start.elapsed().as_secs_f64() * 1000.0
+}
+
+impl SweepParams {
+	pub fn points(&self) -> Vec<SweepPoint> {
+		let mut points = vec![];
+		for block_size in self.block_sizes.iter() {
+			for header_size in self.header_sizes.iter() {
+				for (rate, aggregate) in self.rates.iter().flat_map(|r| self.aggregates.iter().map(move |a| (*r, *a))) {
+					for code_dir in self.code_dirs.iter() {
+						for samples in self.sample_counts.iter() {
+							points.push(SweepPoint { block_size: *block_size, header_size: *header_size, rate: rate, aggregate: aggregate,
+								code_dir: code_dir.clone(), samples: *samples });
+						}
+					}
+				}
+			}
+		}
+		points
+	}
+}
+
+// Transactions of a block of block_size bytes: copies of transaction filling half of the block as in the reference design,
+// the rest of the block is random padding
+fn block_transactions(transaction: &Transaction, block_size: usize) -> Vec<Transaction> {
+	let size = transaction.bytes().len();
+	vec![transaction.clone(); block_size / (2 * size)]
+}
+
+// Run the trials at every point of the sweep, for blocks with header and copies of transaction
+// The trials of a point run in parallel, their samples are drawn from seeds derived in order from params.seed,
+// so that a sweep gives the same results, except timings, whatever the number of threads
+// Returns an error if a rate and an aggregation factor do not give a tree for a block, or naming a missing code file
+// or a code of another rate if the codes of a tree are not in its folder
+// Incorrect-coding proofs carry upper symbols of AGGREGATE hashes, so a corrupt sweep only aggregates AGGREGATE hashes
+pub fn run_sweep(params: &SweepParams, header: &BlockHeader, transaction: &Transaction) -> Result<Vec<SweepResult>, String> {
+	if params.corrupt && params.aggregates.iter().any(|a| *a != AGGREGATE) {
+		return Err(format!("incorrect-coding proofs only carry symbols of {} hashes, set by AGGREGATE in constants.rs", AGGREGATE));
+	}
+	let mut seeds = StdRng::seed_from_u64(params.seed);
+	let mut results = vec![];
+	for block_size in params.block_sizes.iter() {
+		let transactions = block_transactions(transaction, *block_size);
+		for header_size in params.header_sizes.iter() {
+			for (rate, aggregate) in params.rates.iter().flat_map(|r| params.aggregates.iter().map(move |a| (*r, *a))) {
+				let layout = match CmtLayout::for_block_with_coding(*block_size, *header_size as u64, rate, aggregate as u64) {
+					Some(layout) => layout,
+					None => return Err(format!("a rate of {} and {} hashes in an upper symbol do not give a coded Merkle tree \
+						for a block of {} bytes", rate, aggregate, block_size)),
+				};
+				let k_set: Vec<u64> = (0..layout.height()).map(|lvl| layout.k(lvl as u32)).collect();
+				for code_dir in params.code_dirs.iter() {
+					let (codes_for_encoding, codes_for_decoding) = read_codes_of_rate(code_dir, &k_set, rate)
+						.map_err(|e| format!("the codes of a block of {} bytes are not in {}, {}", block_size, code_dir, e))?;
+					let codes_for_decoding: Vec<Arc<Code>> = shared_codes(&codes_for_decoding);
+					let mut correct = vec![true; codes_for_encoding.len()];
+					correct[0] = !params.corrupt;
+					let errors = errors_from_correct(&correct, &codes_for_encoding, rate);
+
+					let start = Instant::now();
+					let block = Block::new_with_coding(header.clone(), &transactions, *block_size, *header_size, &codes_for_encoding,
+						&errors, params.hash_function, rate, aggregate, &mut StdRng::seed_from_u64(seeds.gen()));
+					let encode_ms = elapsed_ms(start);
+
+					for samples in params.sample_counts.iter() {
+						let trial_seeds: Vec<u64> = (0..params.trials).map(|_| seeds.gen()).collect();
+						let sampling = SamplingParams { base_samples: *samples, with_replacement: params.with_replacement, layer_budgets: None };
+						let trials: Vec<Trial> = trial_seeds.par_iter()
+							.map(|seed| run_trial(&block, &codes_for_decoding, &sampling, params.mode, &mut StdRng::seed_from_u64(*seed)))
+							.collect();
+
+						results.push(SweepResult {
+							point: SweepPoint { block_size: *block_size, header_size: *header_size, rate: rate, aggregate: aggregate,
+								code_dir: code_dir.clone(), samples: *samples },
+							layers: layout.height(),
+							base_symbols: layout.n(0),
+							trials: params.trials,
+							decoded: trials.iter().filter(|t| t.decoded).count() as u32,
+							stopped: trials.iter().filter(|t| t.stopping_ratio.is_some()).count() as u32,
+							mean_stopping_ratio: mean(trials.iter().filter_map(|t| t.stopping_ratio).map(|r| r as f64)),
+							proofs: trials.iter().filter(|t| t.proof_size.is_some()).count() as u32,
+							mean_proof_size: mean(trials.iter().filter_map(|t| t.proof_size).map(|s| s as f64)),
+							mean_bytes_downloaded: mean(trials.iter().map(|t| t.bytes_downloaded as f64)).unwrap_or(0.0),
+							encode_ms: encode_ms,
+							mean_decode_ms: mean(trials.iter().map(|t| t.decode_ms)).unwrap_or(0.0),
+						});
+					}
+				}
+			}
+		}
+	}
+	Ok(results)
+}
+
+fn run_trial<R: Rng>(block: &Block, codes: &[Arc<Code>], sampling: &SamplingParams, mode: DecodingMode, rng: &mut R) -> Trial {
+	let (symbols, indices, stats) = block.sample(sampling, rng);
+	let mut decoder = TreeDecoder::new_with_layout(codes, &block.block_header.coded_merkle_roots_hashes, block.layout(), mode)
+		.with_hash_function(block.hash_function());
+	let start = Instant::now();
+	let result = decoder.run_tree_decoder(&symbols, &indices);
+	let decode_ms = elapsed_ms(start);
+	let mut trial = Trial { decoded: false, stopping_ratio: None, proof_size: None, bytes_downloaded: stats.bytes_downloaded,
+		decode_ms: decode_ms };
+	match result {
+		Ok(()) => trial.decoded = true,
+		Err(ref proof) if proof.error_type == CodingErr::Stopped => trial.stopping_ratio = Some(proof.stop_ratio),
+		Err(ref proof) => trial.proof_size = Some(proof.serialized_size()),
+	}
+	trial
+}
+
+// value of a column of the results
+enum Field {
+	Number(String),
+	Text(String),
+	Missing,
+}
+
+impl SweepResult {
+	fn fields(&self) -> Vec<(&'static str, Field)> {
+		let optional = |value: Option<f64>| value.map(|v| Field::Number(v.to_string())).unwrap_or(Field::Missing);
+		vec![
+			("block_size", Field::Number(self.point.block_size.to_string())),
+			("header_size", Field::Number(self.point.header_size.to_string())),
+			("code_dir", Field::Text(self.point.code_dir.clone())),
+			("samples", Field::Number(self.point.samples.to_string())),
+			("rate", Field::Number(self.point.rate.to_string())),
+			("aggregate", Field::Number(self.point.aggregate.to_string())),
+			("layers", Field::Number(self.layers.to_string())),
+			("base_symbols", Field::Number(self.base_symbols.to_string())),
+			("trials", Field::Number(self.trials.to_string())),
+			("decoded", Field::Number(self.decoded.to_string())),
+			("decode_probability", Field::Number(self.decode_probability().to_string())),
+			("stopped", Field::Number(self.stopped.to_string())),
+			("mean_stopping_ratio", optional(self.mean_stopping_ratio)),
+			("proofs", Field::Number(self.proofs.to_string())),
+			("mean_proof_size", optional(self.mean_proof_size)),
+			("mean_bytes_downloaded", Field::Number(self.mean_bytes_downloaded.to_string())),
+			("encode_ms", Field::Number(self.encode_ms.to_string())),
+			("mean_decode_ms", Field::Number(self.mean_decode_ms.to_string())),
+		]
+	}
+}
+
+// Write the results as CSV, with a header line and one line per point, a missing mean is an empty field
+pub fn write_csv<W: Write>(results: &[SweepResult], writer: &mut W) -> io::Result<()> {
+	let columns: Vec<&str> = match results.first() {
+		Some(result) => result.fields().into_iter().map(|(name, _)| name).collect(),
+		None => return Ok(()),
+	};
+	writeln!(writer, "{}", columns.join(","))?;
+	for result in results.iter() {
+		let values: Vec<String> = result.fields().into_iter().map(|(_, value)| match value {
+			Field::Number(v) => v,
+			Field::Text(v) => if v.contains(',') || v.contains('"') { format!("\"{}\"", v.replace('"', "\"\"")) } else { v },
+			Field::Missing => String::new(),
+		}).collect();
+		writeln!(writer, "{}", values.join(","))?;
+	}
+	Ok(())
+}
+
+// Write the results as a JSON array with one object per point, a missing mean is null
+pub fn write_json<W: Write>(results: &[SweepResult], writer: &mut W) -> io::Result<()> {
+	writeln!(writer, "[")?;
+	for (i, result) in results.iter().enumerate() {
+		let values: Vec<String> = result.fields().into_iter().map(|(name, value)| {
+			let value = match value {
+				Field::Number(v) => v,
+				Field::Text(v) => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+				Field::Missing => "null".to_string(),
+			};
+			format!("\"{}\":{}", name, value)
+		}).collect();
+		let separator = if i + 1 < results.len() { "," } else { "" };
+		writeln!(writer, "{{{}}}{}", values.join(","), separator)?;
+	}
+	writeln!(writer, "]")
+}
+
+#[cfg(test)]
+mod tests {
+	use fixtures::{codes_dir, header, transactions};
+	use constants::RATE;
+	use super::*;
+
+	fn params() -> SweepParams {
+		SweepParams {
+			block_sizes: vec![128 * 256],
+			header_sizes: vec![256],
+			rates: vec![RATE],
+			aggregates: vec![AGGREGATE],
+			code_dirs: vec![codes_dir()],
+			sample_counts: vec![10, 600],
+			trials: 4,
+			with_replacement: true,
+			mode: DecodingMode::Peeling,
+			hash_function: HashFunction::default(),
+			corrupt: false,
+			seed: 3,
+		}
+	}
+
+	// results without the timings
+	fn counts(results: &[SweepResult]) -> Vec<(u32, u32, u32, u64)> {
+		results.iter().map(|r| (r.decoded, r.stopped, r.proofs, r.mean_bytes_downloaded as u64)).collect()
+	}
+
+	#[test]
+	fn test_sweep() {
+		let transaction = transactions()[0].clone();
+		let results = run_sweep(&params(), &header(), &transaction).unwrap();
+		assert_eq!(results.len(), 2);
+		assert_eq!((results[0].layers, results[0].base_symbols), (2, 512));
+		// few samples always stop, and enough samples always decode
+		assert_eq!((results[0].decoded, results[0].stopped, results[0].proofs), (0, 4, 0));
+		assert!(results[0].mean_stopping_ratio.unwrap() > 0.0);
+		assert_eq!(results[1].decode_probability(), 1.0);
+		assert_eq!(results[1].mean_stopping_ratio, None);
+		// the results only depend on the seed
+		assert_eq!(counts(&run_sweep(&params(), &header(), &transaction).unwrap()), counts(&results));
+
+		let corrupt = SweepParams { corrupt: true, sample_counts: vec![2048], ..params() };
+		let results = run_sweep(&corrupt, &header(), &transaction).unwrap();
+		assert_eq!((results[0].decoded, results[0].proofs), (0, 4));
+		assert!(results[0].mean_proof_size.unwrap() > 0.0);
+
+		let missing = SweepParams { block_sizes: vec![256 * 256 * 4], header_sizes: vec![16], ..params() };
+		assert!(run_sweep(&missing, &header(), &transaction).unwrap_err().contains("missing"));
+	}
+
+	#[test]
+	fn test_sweep_over_rates_and_aggregates() {
+		let transaction = transactions()[0].clone();
+		let params = SweepParams { block_sizes: vec![256 * 256], header_sizes: vec![512], aggregates: vec![AGGREGATE, 16], 
+			sample_counts: vec![4000], ..params() };
+		assert_eq!(params.points().len(), 2);
+		let results = run_sweep(&params, &header(), &transaction).unwrap();
+		// upper symbols of 16 hashes halve the upper layer, which still fits in the header
+		let layers: Vec<(f32, usize, usize, u64)> = results.iter().map(|r| (r.point.rate, r.point.aggregate, r.layers, r.base_symbols)).collect();
+		assert_eq!(layers, vec![(RATE, AGGREGATE, 2, 1024), (RATE, 16, 2, 1024)]);
+		assert!(results.iter().all(|r| r.decode_probability() == 1.0));
+
+		// the codes in the folder have a rate of 1/4
+		let half = SweepParams { rates: vec![0.5], ..params.clone() };
+		assert!(run_sweep(&half, &header(), &transaction).unwrap_err().contains("is not a code of rate 0.5"));
+		let no_tree = SweepParams { aggregates: vec![4], ..params.clone() };
+		assert!(run_sweep(&no_tree, &header(), &transaction).unwrap_err().contains("do not give a coded Merkle tree"));
+		let corrupt = SweepParams { corrupt: true, ..params.clone() };
+		assert!(run_sweep(&corrupt, &header(), &transaction).unwrap_err().contains("incorrect-coding proofs"));
+	}
+
+	#[test]
+	fn test_write_results() {
+		let transaction = transactions()[0].clone();
+		let params = SweepParams { code_dirs: vec!["a,\"b\"".to_string()], ..params() };
+		let mut results = run_sweep(&SweepParams { code_dirs: vec![codes_dir()], ..params.clone() }, &header(), &transaction).unwrap();
+		for result in results.iter_mut() {
+			result.point.code_dir = params.code_dirs[0].clone();
+		}
+
+		let mut csv = vec![];
+		write_csv(&results, &mut csv).unwrap();
+		let csv = String::from_utf8(csv).unwrap();
+		let lines: Vec<&str> = csv.lines().collect();
+		assert_eq!(lines.len(), 3);
+		assert!(lines[0].starts_with("block_size,header_size,code_dir,samples,rate,aggregate,"));
+		assert!(lines[1].starts_with("32768,256,\"a,\"\"b\"\"\",10,0.25,8,2,512,4,0,0,4,"));
+		// the proof size is missing
+		assert!(lines[1].contains(",0,,"));
+
+		let mut json = vec![];
+		write_json(&results, &mut json).unwrap();
+		let json = String::from_utf8(json).unwrap();
+		let lines: Vec<&str> = json.lines().collect();
+		assert_eq!((lines[0], lines[3]), ("[", "]"));
+		assert!(lines[1].starts_with("{\"block_size\":32768,\"header_size\":256,\"code_dir\":\"a,\\\"b\\\"\",\"samples\":10,"));
+		assert!(lines[1].contains("\"mean_proof_size\":null"));
+		assert!(lines[1].ends_with("},"));
+		assert!(lines[2].ends_with("}"));
+		assert_eq!(write_csv(&[], &mut vec![]).ok(), Some(()));
+	}
+}
@@ -0,0 +1,303 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use decoder::Symbol;
+
+// Arithmetic on coded symbols over GF(2)
+// Adding symbols is XOR of their bytes, and checking a parity equation is testing a sum of symbols for zero.
+// Both run on 8-byte words, or on 16/32-byte vectors when the CPU supports SSE2/AVX2, which is detected at runtime.
+
+// Implementation of the XOR and zero-test kernels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kernel {
+	Bytes, // one byte at a time, the scalar fallback
+	Words, // u64 words
+	Sse2, // 128-bit vectors
+	Avx2, // 256-bit vectors
+}
+
+// kernel used by xor_into and is_zero, always supported by the CPU, 0 until it is detected
+static KERNEL: AtomicUsize = AtomicUsize::new(0);
+
+const KERNELS: [Kernel; 4] = [Kernel::Bytes, Kernel::Words, Kernel::Sse2, Kernel::Avx2];
+
+impl Kernel {
+	// Kernel used by xor_into and is_zero, the fastest one unless another is chosen with use_kernel
+	pub fn current() -> Kernel {
+		match KERNEL.load(Ordering::Relaxed) {
+			0 => {
+				let kernel = Kernel::fastest();
+				use_kernel(kernel);
+				kernel
+			},
+			k => KERNELS[k - 1],
+		}
+	}
+
+	// Fastest kernel supported by this CPU
+	pub fn fastest() -> Kernel {
+		Kernel::available().pop().unwrap()
+	}
+
+	// This kernel if the CPU supports it, otherwise the u64 words kernel
+	pub fn supported(self) -> Kernel {
+		if Kernel::available().contains(&self) { self } else { Kernel::Words }
+	}
+
+	// Kernels supported by this CPU, from the slowest to the fastest
+	pub fn available() -> Vec<Kernel> {
+		let mut kernels = vec![Kernel::Bytes, Kernel::Words];
+		#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+		{
+			if is_x86_feature_detected!("sse2") {
+				kernels.push(Kernel::Sse2);
+			}
+			if is_x86_feature_detected!("avx2") {
+				kernels.push(Kernel::Avx2);
+			}
+		}
+		kernels
+	}
+}
+
+// Use a kernel for xor_into and is_zero instead of the fastest one, the u64 words kernel if the CPU does not support it
+// The CPU support is checked here once, not on every symbol operation
+pub fn use_kernel(kernel: Kernel) {
+	KERNEL.store(KERNELS.iter().position(|k| *k == kernel.supported()).unwrap() + 1, Ordering::Relaxed);
+}
+
+// Kernel of the process while it is alive, the previous kernel is restored when it is dropped
+pub struct KernelGuard {
+	previous: usize,
+}
+
+// Use a kernel for xor_into and is_zero until the returned guard is dropped, e.g. to compare kernels in benchmarks
+pub fn scoped_kernel(kernel: Kernel) -> KernelGuard {
+	let guard = KernelGuard { previous: KERNEL.load(Ordering::Relaxed) };
+	use_kernel(kernel);
+	guard
+}
+
+impl Drop for KernelGuard {
+	fn drop(&mut self) {
+		KERNEL.store(self.previous, Ordering::Relaxed);
+	}
+}
+
+// dst = dst XOR src, for byte strings of the same length
+pub fn xor_into(dst: &mut [u8], src: &[u8]) {
+	xor_supported(Kernel::current(), dst, src);
+}
+
+// true if every byte is 0u8
+pub fn is_zero(bytes: &[u8]) -> bool {
+	is_zero_supported(Kernel::current(), bytes)
+}
+
+// XOR with a given kernel, which falls back to u64 words if the CPU does not support it
+pub fn xor_into_with(kernel: Kernel, dst: &mut [u8], src: &[u8]) {
+	xor_supported(kernel.supported(), dst, src);
+}
+
+// Zero test with a given kernel, which falls back to u64 words if the CPU does not support it
+pub fn is_zero_with(kernel: Kernel, bytes: &[u8]) -> bool {
+	is_zero_supported(kernel.supported(), bytes)
+}
+
+// XOR with a kernel that the CPU supports
+fn xor_supported(kernel: Kernel, dst: &mut [u8], src: &[u8]) {
+	assert_eq!(dst.len(), src.len(), "symbols of different sizes");
+	match kernel {
+		Kernel::Bytes => xor_bytes(dst, src),
+		#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+		Kernel::Sse2 => unsafe { xor_sse2(dst, src) },
+		#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+		Kernel::Avx2 => unsafe { xor_avx2(dst, src) },
+		_ => xor_words(dst, src),
+	}
+}
+
+// Zero test with a kernel that the CPU supports
+fn is_zero_supported(kernel: Kernel, bytes: &[u8]) -> bool {
+	match kernel {
+		Kernel::Bytes => is_zero_bytes(bytes),
+		#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+		Kernel::Sse2 => unsafe { is_zero_sse2(bytes) },
+		#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+		Kernel::Avx2 => unsafe { is_zero_avx2(bytes) },
+		_ => is_zero_words(bytes),
+	}
+}
+
+// Add symbol to sum, returns false and leaves sum unchanged if they are not both base or both upper symbols
+pub fn add_symbol(sum: &mut Symbol, symbol: &Symbol) -> bool {
+	match (sum, symbol) {
+		(&mut Symbol::Base(ref mut x), &Symbol::Base(ref y)) => xor_into(x, y),
+		(&mut Symbol::Upper(ref mut x), &Symbol::Upper(ref y)) => xor_into(x, y),
+		_ => return false,
+	}
+	true
+}
+
+// true if every byte of the symbol is 0u8, an empty symbol is regarded as zero
+pub fn symbol_is_zero(symbol: &Symbol) -> bool {
+	match *symbol {
+		Symbol::Base(ref sym) => is_zero(sym),
+		Symbol::Upper(ref sym) => is_zero(sym),
+		Symbol::Empty => true,
+	}
+}
+
+fn xor_bytes(dst: &mut [u8], src: &[u8]) {
+	for (d, s) in dst.iter_mut().zip(src.iter()) {
+		*d ^= *s;
+	}
+}
+
+fn is_zero_bytes(bytes: &[u8]) -> bool {
+	bytes.iter().all(|b| *b == 0u8)
+}
+
+fn load_word(bytes: &[u8]) -> u64 {
+	let mut word = [0u8; 8];
+	word.copy_from_slice(bytes);
+	u64::from_ne_bytes(word)
+}
+
+fn xor_words(dst: &mut [u8], src: &[u8]) {
+	let mut dst_words = dst.chunks_exact_mut(8);
+	let mut src_words = src.chunks_exact(8);
+	for (d, s) in (&mut dst_words).zip(&mut src_words) {
+		let word = load_word(d) ^ load_word(s);
+		d.copy_from_slice(&word.to_ne_bytes());
+	}
+	xor_bytes(dst_words.into_remainder(), src_words.remainder());
+}
+
+fn is_zero_words(bytes: &[u8]) -> bool {
+	let words = bytes.chunks_exact(8);
+	let rest = words.remainder();
+	words.fold(0u64, |acc, w| acc | load_word(w)) == 0 && is_zero_bytes(rest)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn xor_sse2(dst: &mut [u8], src: &[u8]) {
+	let n = dst.len() / 16 * 16;
+	for i in (0..n).step_by(16) {
+		let x = _mm_loadu_si128(dst.as_ptr().add(i) as *const __m128i);
+		let y = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+		_mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, _mm_xor_si128(x, y));
+	}
+	xor_words(&mut dst[n..], &src[n..]);
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn is_zero_sse2(bytes: &[u8]) -> bool {
+	let n = bytes.len() / 16 * 16;
+	let mut acc = _mm_setzero_si128();
+	for i in (0..n).step_by(16) {
+		acc = _mm_or_si128(acc, _mm_loadu_si128(bytes.as_ptr().add(i) as *const __m128i));
+	}
+	_mm_movemask_epi8(_mm_cmpeq_epi8(acc, _mm_setzero_si128())) == 0xffff && is_zero_words(&bytes[n..])
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn xor_avx2(dst: &mut [u8], src: &[u8]) {
+	let n = dst.len() / 32 * 32;
+	for i in (0..n).step_by(32) {
+		let x = _mm256_loadu_si256(dst.as_ptr().add(i) as *const __m256i);
+		let y = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+		_mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, _mm256_xor_si256(x, y));
+	}
+	xor_words(&mut dst[n..], &src[n..]);
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn is_zero_avx2(bytes: &[u8]) -> bool {
+	let n = bytes.len() / 32 * 32;
+	let mut acc = _mm256_setzero_si256();
+	for i in (0..n).step_by(32) {
+		acc = _mm256_or_si256(acc, _mm256_loadu_si256(bytes.as_ptr().add(i) as *const __m256i));
+	}
+	_mm256_testz_si256(acc, acc) == 1 && is_zero_words(&bytes[n..])
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::{Rng, SeedableRng};
+	use rand::rngs::StdRng;
+	use constants::{BASE_SYMBOL_SIZE, AGGREGATE};
+	use super::*;
+
+	// Every kernel agrees with the scalar kernel, including lengths that are not a multiple of the vector size
+	#[test]
+	fn test_kernels_agree_with_scalar() {
+		let mut rng = StdRng::seed_from_u64(19);
+		for len in [0, 1, 7, 8, 15, 16, 31, 33, 63, 100, BASE_SYMBOL_SIZE, 32 * AGGREGATE + 5].iter() {
+			let a: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+			let b: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+			let mut expected = a.clone();
+			xor_into_with(Kernel::Bytes, &mut expected, &b);
+			for kernel in KERNELS.iter() {
+				let mut sum = a.clone();
+				xor_into_with(*kernel, &mut sum, &b);
+				assert_eq!(sum, expected, "{:?} kernel on {} bytes", kernel, len);
+				xor_into_with(*kernel, &mut sum, &b);
+				assert_eq!(sum, a);
+			}
+		}
+	}
+
+	#[test]
+	fn test_zero_test_finds_any_nonzero_byte() {
+		for len in [0, 5, 16, 40, BASE_SYMBOL_SIZE].iter() {
+			let mut bytes = vec![0u8; *len];
+			for kernel in KERNELS.iter() {
+				assert!(is_zero_with(*kernel, &bytes));
+			}
+			for position in 0..*len {
+				bytes[position] = 0x10;
+				for kernel in KERNELS.iter() {
+					assert!(!is_zero_with(*kernel, &bytes), "{:?} kernel misses byte {} of {}", kernel, position, len);
+				}
+				bytes[position] = 0;
+			}
+		}
+	}
+
+	#[test]
+	fn test_add_symbols() {
+		let mut sum = Symbol::Base([3u8; BASE_SYMBOL_SIZE]);
+		assert!(add_symbol(&mut sum, &Symbol::Base([1u8; BASE_SYMBOL_SIZE])));
+		match sum {
+			Symbol::Base(sym) => assert_eq!(sym.to_vec(), vec![2u8; BASE_SYMBOL_SIZE]),
+			_ => panic!("the sum of base symbols is a base symbol"),
+		}
+		assert!(!add_symbol(&mut sum, &Symbol::Upper([1u8; 32 * AGGREGATE])));
+		assert!(!add_symbol(&mut sum, &Symbol::Empty));
+		let copy = sum;
+		assert!(add_symbol(&mut sum, &copy));
+		assert!(symbol_is_zero(&sum));
+		assert!(Kernel::available().contains(&Kernel::fastest()));
+	}
+
+	#[test]
+	fn test_scoped_kernel_is_restored() {
+		let current = Kernel::current();
+		{
+			let _guard = scoped_kernel(Kernel::Bytes);
+			assert_eq!(Kernel::current(), Kernel::Bytes);
+			let mut sum = [1u8; 40];
+			xor_into(&mut sum, &[1u8; 40]);
+			assert!(is_zero(&sum));
+		}
+		assert_eq!(Kernel::current(), current);
+		assert!(Kernel::available().contains(&Kernel::Avx2.supported()));
+	}
+}
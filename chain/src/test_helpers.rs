@@ -0,0 +1,21 @@
+//! Fixtures shared by the tests of the crate: decoding a block with some symbols missing
+
+use block::Block;
+use codes::shared_codes;
+use decoder::{Code, TreeDecoder, IncorrectCodingProof};
+
+// all symbols of the tree, except the symbol (layer, index) in missing
+pub fn samples_without(block: &Block, missing: Option<(usize, u64)>) -> (Vec<Vec<&[u8]>>, Vec<Vec<u64>>) {
+	let indices: Vec<Vec<u64>> = (0..block.coded_tree.len())
+		.map(|lvl| (0..block.layer_length(lvl) as u64).filter(|i| Some((lvl, *i)) != missing).collect())
+		.collect();
+	block.collect_samples(&indices)
+}
+
+// decode the tree from all of its symbols except missing, with a decoder in the mode of the header of the block
+pub fn decode_without(block: &Block, codes: &Vec<Code>, missing: Option<(usize, u64)>) -> Result<(), IncorrectCodingProof> {
+	let (symbols_all_levels, indices_all_levels) = samples_without(block, missing);
+	let mut decoder = TreeDecoder::from_header(&shared_codes(codes), &block.block_header, &block.top_hashes())
+		.expect("the top-layer hashes of the block match its header");
+	decoder.run_tree_decoder(&symbols_all_levels, &indices_all_levels)
+}
@@ -0,0 +1,117 @@
+use hash::H256;
+use cmt_hash::CmtHasher;
+
+// Single root over the hashes of the top layer of CMT
+// A header in single-root mode stores this root instead of the hashes of the top layer. It is the root of a binary Merkle tree
+// over the hashes in the order of their indices, hashed with the hash function of CMT, where the last node of an odd row is
+// paired with itself. A top-layer hash is served with an inclusion proof: the hash itself, then its siblings up to the root.
+
+fn node_hash(hasher: &dyn CmtHasher, left: &H256, right: &H256) -> H256 {
+	let mut data = [0u8; 64];
+	data[0..32].copy_from_slice(&**left);
+	data[32..64].copy_from_slice(&**right);
+	hasher.hash(&data)
+}
+
+fn next_row(hasher: &dyn CmtHasher, row: &[H256]) -> Vec<H256> {
+	row.chunks(2).map(|pair| node_hash(hasher, &pair[0], &pair[pair.len() - 1])).collect()
+}
+
+// Root over the hashes of the top layer
+pub fn top_root(hasher: &dyn CmtHasher, hashes: &[H256]) -> H256 {
+	assert!(!hashes.is_empty(), "the top layer has no hashes");
+	let mut row = hashes.to_vec();
+	while row.len() > 1 {
+		row = next_row(hasher, &row);
+	}
+	row[0]
+}
+
+// number of hashes in an inclusion proof for a top layer of n symbols
+pub fn top_proof_len(n: u64) -> usize {
+	let mut len = 1;
+	let mut width = n;
+	while width > 1 {
+		width = (width + 1) / 2;
+		len += 1;
+	}
+	len
+}
+
+// Inclusion proof of the hash with some index on the top layer
+pub fn top_proof(hasher: &dyn CmtHasher, hashes: &[H256], index: u64) -> Vec<H256> {
+	let mut i = index as usize;
+	let mut proof = vec![hashes[i]];
+	let mut row = hashes.to_vec();
+	while row.len() > 1 {
+		proof.push(row[(i ^ 1).min(row.len() - 1)]);
+		row = next_row(hasher, &row);
+		i /= 2;
+	}
+	proof
+}
+
+// Verify the inclusion proof of the hash with some index on a top layer of n symbols
+// Returns the proven hash, None if the proof does not lead to root
+pub fn verify_top_proof(hasher: &dyn CmtHasher, root: &H256, index: u64, n: u64, proof: &[H256]) -> Option<H256> {
+	if index >= n || proof.len() != top_proof_len(n) {
+		return None;
+	}
+	let mut current = proof[0];
+	let (mut i, mut width) = (index, n);
+	for sibling in proof[1..].iter() {
+		if i % 2 == 1 {
+			current = node_hash(hasher, sibling, &current);
+		} else {
+			// the last node of an odd row is paired with itself
+			if i + 1 == width && *sibling != current {
+				return None;
+			}
+			current = node_hash(hasher, &current, sibling);
+		}
+		i /= 2;
+		width = (width + 1) / 2;
+	}
+	if current == *root { Some(proof[0]) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+	use cmt_hash::{DoubleSha256, Blake2b};
+	use super::*;
+
+	#[test]
+	fn test_top_proofs() {
+		for n in [1u64, 2, 5, 8, 256].iter() {
+			let hashes: Vec<H256> = (0..*n).map(|i| H256::from(i as u8)).collect();
+			let root = top_root(&DoubleSha256, &hashes);
+			for index in 0..*n {
+				let proof = top_proof(&DoubleSha256, &hashes, index);
+				assert_eq!(proof.len(), top_proof_len(*n));
+				assert_eq!(verify_top_proof(&DoubleSha256, &root, index, *n, &proof), Some(hashes[index as usize]));
+				if *n > 1 {
+					assert_eq!(verify_top_proof(&DoubleSha256, &root, (index + 1) % n, *n, &proof), None);
+				}
+				assert_eq!(verify_top_proof(&Blake2b, &root, index, *n, &proof).is_some(), *n == 1);
+			}
+		}
+		assert_eq!(top_proof_len(256), 9);
+
+		let hashes: Vec<H256> = (0..5u8).map(H256::from).collect();
+		let root = top_root(&DoubleSha256, &hashes);
+		// the unpaired hash is paired with itself on every row
+		let last = node_hash(&DoubleSha256, &hashes[4], &hashes[4]);
+		let last = node_hash(&DoubleSha256, &last, &last);
+		assert_eq!(root, node_hash(&DoubleSha256, &top_root(&DoubleSha256, &hashes[0..4]), &last));
+
+		// a proof with a wrong hash, a truncated proof, and a proof of the unpaired hash with a made-up sibling
+		let mut proof = top_proof(&DoubleSha256, &hashes, 2);
+		proof[0] = H256::from(9u8);
+		assert_eq!(verify_top_proof(&DoubleSha256, &root, 2, 5, &proof), None);
+		let proof = top_proof(&DoubleSha256, &hashes, 2);
+		assert_eq!(verify_top_proof(&DoubleSha256, &root, 2, 5, &proof[0..3]), None);
+		let mut proof = top_proof(&DoubleSha256, &hashes, 4);
+		proof[1] = H256::from(9u8);
+		assert_eq!(verify_top_proof(&DoubleSha256, &root, 4, 5, &proof), None);
+	}
+}
@@ -7,6 +7,7 @@ use std::hash::Hasher;
 use rcrypto::sha1::Sha1;
 use rcrypto::sha2::Sha256;
 use rcrypto::ripemd160::Ripemd160;
+use rcrypto::blake2b::Blake2b;
 use siphasher::sip::SipHasher24;
 use primitives::hash::{H32, H160, H256};
 
@@ -155,6 +156,16 @@ pub fn dhash256(input: &[u8]) -> H256 {
 	result
 }
 
+/// BLAKE2b with a 256-bit output
+#[inline]
+pub fn blake2b256(input: &[u8]) -> H256 {
+	let mut result = H256::default();
+	let mut hasher = Blake2b::new(32);
+	hasher.input(input);
+	hasher.result(&mut *result);
+	result
+}
+
 /// SipHash-2-4
 #[inline]
 pub fn siphash24(key0: u64, key1: u64, input: &[u8]) -> u64 {
@@ -174,7 +185,7 @@ pub fn checksum(data: &[u8]) -> H32 {
 #[cfg(test)]
 mod tests {
 	use primitives::bytes::Bytes;
-	use super::{ripemd160, sha1, sha256, dhash160, dhash256, siphash24, checksum};
+	use super::{ripemd160, sha1, sha256, dhash160, dhash256, blake2b256, siphash24, checksum};
 
 	#[test]
 	fn test_ripemd160() {
@@ -216,6 +227,13 @@ mod tests {
 		assert_eq!(result, expected);
 	}
 
+	#[test]
+	fn test_blake2b256() {
+		let expected = "324dcf027dd4a30a932c441f365a25e86b173defa4b8e58948253471b81b72cf".into();
+		let result = blake2b256(b"hello");
+		assert_eq!(result, expected);
+	}
+
 	#[test]
 	fn test_siphash24() {
 		let expected = 0x74f839c593dc67fd_u64;
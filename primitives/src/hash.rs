@@ -1,6 +1,6 @@
 //! Fixed-size hashes
 
-use std::{fmt, ops, cmp, str};
+use std::{fmt, ops, cmp, str, slice};
 use hex::{ToHex, FromHex, FromHexError};
 use std::hash::{Hash, Hasher};
 
@@ -150,6 +150,20 @@ macro_rules! impl_hash {
 			pub fn is_zero(&self) -> bool {
 				self.0.iter().all(|b| *b == 0)
 			}
+
+			/// Views bytes in place as a hash.
+			pub fn from_bytes_ref(bytes: &[u8; $size]) -> &Self {
+				// a hash is a repr(C) wrapper of its bytes, with their size and alignment
+				unsafe { &*(bytes as *const [u8; $size] as *const Self) }
+			}
+
+			/// Views consecutive bytes in place as hashes.
+			///
+			/// Panics if the number of bytes is not a multiple of the size of a hash.
+			pub fn slice_from_bytes(bytes: &[u8]) -> &[Self] {
+				assert_eq!(bytes.len() % $size, 0, "bytes are not a whole number of hashes");
+				unsafe { slice::from_raw_parts(bytes.as_ptr() as *const Self, bytes.len() / $size) }
+			}
 		}
 	}
 }
@@ -0,0 +1,279 @@
+//! Deserialization borrowing from a byte buffer instead of copying out of it.
+
+use std::mem;
+use std::convert::TryInto;
+use hash::H256;
+use compact_integer::CompactInteger;
+use reader::{Reader, ReaderLimits, Deserializable, Error};
+
+/// Deserializes a structure borrowing from `buffer`.
+pub fn deserialize_borrowed<'a, T>(buffer: &'a [u8]) -> Result<T, Error> where T: Deserialize<'a> {
+	let mut reader = SliceReader::new(buffer);
+	let result = try!(reader.read());
+
+	if reader.is_finished() {
+		Ok(result)
+	} else {
+		Err(Error::UnreadData)
+	}
+}
+
+/// Structures that can be read from a byte buffer, borrowing from it.
+///
+/// Uses the same wire format as `Deserializable`.
+pub trait Deserialize<'a>: Sized {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error>;
+}
+
+/// Reader of structures borrowing from a byte buffer.
+#[derive(Debug)]
+pub struct SliceReader<'a> {
+	buffer: &'a [u8],
+	limits: ReaderLimits,
+	allocated: usize,
+	depth: usize,
+}
+
+impl<'a> SliceReader<'a> {
+	pub fn new(buffer: &'a [u8]) -> Self {
+		SliceReader {
+			buffer: buffer,
+			limits: ReaderLimits::default(),
+			allocated: 0,
+			depth: 0,
+		}
+	}
+
+	pub fn with_limits(mut self, limits: ReaderLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	pub fn read<T>(&mut self) -> Result<T, Error> where T: Deserialize<'a> {
+		if self.depth >= self.limits.max_depth {
+			return Err(Error::LimitExceeded);
+		}
+
+		self.depth += 1;
+		let result = T::deserialize_borrowed(self);
+		self.depth -= 1;
+		result
+	}
+
+	/// Reads an owned structure from the buffer.
+	pub fn read_owned<T>(&mut self) -> Result<T, Error> where T: Deserializable {
+		// reading from a slice advances it
+		let limits = self.limits;
+		let mut reader = Reader::from_read(&mut self.buffer).with_limits(limits);
+		reader.read()
+	}
+
+	/// Reads the next `len` bytes.
+	pub fn read_slice(&mut self, len: usize) -> Result<&'a [u8], Error> {
+		if self.buffer.len() < len {
+			return Err(Error::UnexpectedEnd);
+		}
+
+		let (result, rest) = self.buffer.split_at(len);
+		self.buffer = rest;
+		Ok(result)
+	}
+
+	/// Reads the next `N` bytes.
+	pub fn read_array<const N: usize>(&mut self) -> Result<&'a [u8; N], Error> {
+		let bytes = try!(self.read_slice(N));
+		Ok(bytes.try_into().expect("slice has N bytes"))
+	}
+
+	/// Reads the length prefix of a list, failing if it exceeds the limits.
+	pub fn read_len(&mut self) -> Result<usize, Error> {
+		let len: usize = try!(self.read_owned::<CompactInteger>()).into();
+		if len > self.limits.max_list_len {
+			return Err(Error::LimitExceeded);
+		}
+
+		Ok(len)
+	}
+
+	/// Reads a list into a vector. Its items may still borrow from the buffer.
+	pub fn read_list<T>(&mut self) -> Result<Vec<T>, Error> where T: Deserialize<'a> {
+		let len = try!(self.read_len());
+		let bytes = len.saturating_mul(mem::size_of::<T>());
+		match self.allocated.checked_add(bytes) {
+			Some(allocated) if allocated <= self.limits.max_bytes => self.allocated = allocated,
+			_ => return Err(Error::LimitExceeded),
+		}
+
+		let mut result = Vec::with_capacity(len);
+		for _ in 0..len {
+			result.push(try!(self.read()));
+		}
+
+		Ok(result)
+	}
+
+	pub fn read_list_max<T>(&mut self, max: usize) -> Result<Vec<T>, Error> where T: Deserialize<'a> {
+		let rest = self.buffer;
+		let len = try!(self.read_len());
+		if len > max {
+			return Err(Error::MalformedData);
+		}
+
+		self.buffer = rest;
+		self.read_list()
+	}
+
+	/// Bytes that are not read yet.
+	pub fn remaining(&self) -> &'a [u8] {
+		self.buffer
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.buffer.is_empty()
+	}
+}
+
+macro_rules! impl_deserialize_owned {
+	($($name: ty),*) => {
+		$(
+			impl<'a> Deserialize<'a> for $name {
+				fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+					reader.read_owned()
+				}
+			}
+		)*
+	}
+}
+
+impl_deserialize_owned!(bool, i32, i64, u8, u16, u32, u64, f32, CompactInteger);
+
+/// Borrowed form of `Bytes`.
+impl<'a> Deserialize<'a> for &'a [u8] {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let len = try!(reader.read_len());
+		reader.read_slice(len)
+	}
+}
+
+/// Borrowed form of `[u8; N]`.
+impl<'a, const N: usize> Deserialize<'a> for &'a [u8; N] {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		reader.read_array()
+	}
+}
+
+impl<'a> Deserialize<'a> for &'a H256 {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		reader.read_array().map(H256::from_bytes_ref)
+	}
+}
+
+/// Borrowed form of `[H256; N]`.
+impl<'a, const N: usize> Deserialize<'a> for &'a [H256; N] {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let bytes = try!(reader.read_slice(N * 32));
+		Ok(H256::slice_from_bytes(bytes).try_into().expect("slice has N hashes"))
+	}
+}
+
+/// Borrowed form of `Vec<H256>`.
+impl<'a> Deserialize<'a> for &'a [H256] {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let len = try!(reader.read_len());
+		let bytes = try!(len.checked_mul(32).ok_or(Error::LimitExceeded));
+		let bytes = try!(reader.read_slice(bytes));
+		Ok(H256::slice_from_bytes(bytes))
+	}
+}
+
+/// Borrowed form of `Vec<[H256; N]>`.
+impl<'a, const N: usize> Deserialize<'a> for &'a [[H256; N]] {
+	fn deserialize_borrowed(reader: &mut SliceReader<'a>) -> Result<Self, Error> {
+		let len = try!(reader.read_len());
+		let bytes = try!(len.checked_mul(N * 32).ok_or(Error::LimitExceeded));
+		let bytes = try!(reader.read_slice(bytes));
+		Ok(H256::slice_from_bytes(bytes).as_chunks::<N>().0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bytes::Bytes;
+	use hash::H256;
+	use {serialize, serialize_list, Stream, Error, ReaderLimits};
+	use super::{deserialize_borrowed, SliceReader};
+
+	#[test]
+	fn test_borrowed_bytes_and_arrays() {
+		let mut stream = Stream::default();
+		stream
+			.append(&Bytes::from(vec![1u8, 2, 3]))
+			.append(&[4u8; 4])
+			.append(&5u32);
+		let buffer = stream.out();
+
+		let mut reader = SliceReader::new(&buffer);
+		let bytes: &[u8] = reader.read().unwrap();
+		assert_eq!(bytes, &[1u8, 2, 3]);
+		// the bytes are not copied
+		assert_eq!(bytes.as_ptr(), buffer[1..].as_ptr());
+		let array: &[u8; 4] = reader.read().unwrap();
+		assert_eq!(array, &[4u8; 4]);
+		assert_eq!(reader.read::<u32>().unwrap(), 5);
+		assert!(reader.is_finished());
+		assert_eq!(reader.read::<u8>().unwrap_err(), Error::UnexpectedEnd);
+
+		assert_eq!(deserialize_borrowed::<&[u8]>(&[2u8, 7]).unwrap_err(), Error::UnexpectedEnd);
+		assert_eq!(deserialize_borrowed::<&[u8]>(&[1u8, 7, 8]).unwrap_err(), Error::UnreadData);
+	}
+
+	#[test]
+	fn test_borrowed_hashes() {
+		let hashes: Vec<H256> = (0..4u8).map(H256::from).collect();
+		let buffer = serialize_list(&hashes);
+		let borrowed: &[H256] = deserialize_borrowed(&buffer).unwrap();
+		assert_eq!(borrowed, &hashes[..]);
+		assert_eq!(borrowed.as_ptr() as *const u8, buffer[1..].as_ptr());
+
+		let buffer = serialize(&hashes[0]);
+		assert_eq!(deserialize_borrowed::<&H256>(&buffer).unwrap(), &hashes[0]);
+
+		// a list of symbols of two hashes each, and a single symbol
+		let symbols = vec![[hashes[0], hashes[1]], [hashes[2], hashes[3]]];
+		let mut stream = Stream::default();
+		stream.append_list::<[H256; 2], _>(&symbols).append(&symbols[1]);
+		let buffer = stream.out();
+		let mut reader = SliceReader::new(&buffer);
+		let borrowed: &[[H256; 2]] = reader.read().unwrap();
+		assert_eq!(borrowed, &symbols[..]);
+		let symbol: &[H256; 2] = reader.read().unwrap();
+		assert_eq!(symbol, &symbols[1]);
+		assert!(reader.is_finished());
+
+		// a list shorter than its length prefix, and one beyond the limits
+		assert_eq!(deserialize_borrowed::<&[H256]>(&buffer[..40]).unwrap_err(), Error::UnexpectedEnd);
+		let huge = [0xffu8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+		assert_eq!(deserialize_borrowed::<&[[H256; 2]]>(&huge).unwrap_err(), Error::LimitExceeded);
+
+		// without limits, the size of such a list overflows
+		let unlimited = || SliceReader::new(&huge).with_limits(ReaderLimits::unlimited());
+		assert_eq!(unlimited().read::<&[H256]>().unwrap_err(), Error::LimitExceeded);
+		assert_eq!(unlimited().read::<&[[H256; 2]]>().unwrap_err(), Error::LimitExceeded);
+	}
+
+	#[test]
+	fn test_borrowed_lists() {
+		let items: Vec<Bytes> = vec![vec![1u8].into(), vec![2u8, 3].into()];
+		let buffer = serialize_list(&items);
+		let borrowed: Vec<&[u8]> = SliceReader::new(&buffer).read_list().unwrap();
+		assert_eq!(borrowed, vec![&[1u8][..], &[2u8, 3][..]]);
+		assert_eq!(SliceReader::new(&buffer).read_list_max::<&[u8]>(1).unwrap_err(), Error::MalformedData);
+
+		let limits = ReaderLimits { max_bytes: 16, ..ReaderLimits::default() };
+		assert_eq!(SliceReader::new(&buffer).with_limits(limits).read_list::<&[u8]>().unwrap_err(), Error::LimitExceeded);
+		let limits = ReaderLimits { max_depth: 1, ..ReaderLimits::default() };
+		assert_eq!(SliceReader::new(&buffer).with_limits(limits).read::<u8>().unwrap(), 2);
+		let limits = ReaderLimits { max_depth: 0, ..ReaderLimits::default() };
+		assert_eq!(SliceReader::new(&buffer).with_limits(limits).read::<u8>().unwrap_err(), Error::LimitExceeded);
+	}
+}
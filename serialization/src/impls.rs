@@ -233,6 +233,49 @@ impl_ser_for_hash!(H264, 33);
 impl_ser_for_hash!(H512, 64);
 impl_ser_for_hash!(H520, 65);
 
+// Fixed-size arrays are written without a length prefix, the length is part of the type
+impl<const N: usize> Serializable for [u8; N] {
+	fn serialize(&self, stream: &mut Stream) {
+		stream.append_slice(self);
+	}
+
+	#[inline]
+	fn serialized_size(&self) -> usize {
+		N
+	}
+}
+
+impl<const N: usize> Deserializable for [u8; N] {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where T: io::Read {
+		let mut result = [0u8; N];
+		try!(reader.read_slice(&mut result));
+		Ok(result)
+	}
+}
+
+impl<const N: usize> Serializable for [H256; N] {
+	fn serialize(&self, stream: &mut Stream) {
+		for hash in self.iter() {
+			stream.append(hash);
+		}
+	}
+
+	#[inline]
+	fn serialized_size(&self) -> usize {
+		32 * N
+	}
+}
+
+impl<const N: usize> Deserializable for [H256; N] {
+	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where T: io::Read {
+		let mut result = [H256::default(); N];
+		for hash in result.iter_mut() {
+			*hash = try!(reader.read());
+		}
+		Ok(result)
+	}
+}
+
 impl Serializable for Bytes {
 	fn serialize(&self, stream: &mut Stream) {
 		stream
@@ -248,8 +291,9 @@ impl Serializable for Bytes {
 
 impl Deserializable for Bytes {
 	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where T: io::Read {
-		let len = try!(reader.read::<CompactInteger>());
-		let mut bytes = Bytes::new_with_len(len.into());
+		let len = try!(reader.read_len());
+		try!(reader.reserve(len));
+		let mut bytes = Bytes::new_with_len(len);
 		try!(reader.read_slice(&mut bytes));
 		Ok(bytes)
 	}
@@ -270,7 +314,8 @@ impl Deserializable for Compact {
 #[cfg(test)]
 mod tests {
 	use bytes::Bytes;
-	use {serialize, deserialize, deserialize_iterator, Stream, Reader, Error};
+	use hash::H256;
+	use {serialize, deserialize, deserialize_with_limits, deserialize_iterator, Serializable, Stream, Reader, ReaderLimits, Error, List};
 
 	#[test]
 	fn test_reader_read() {
@@ -291,6 +336,22 @@ mod tests {
 		assert_eq!(Error::UnexpectedEnd, reader.read::<u8>().unwrap_err());
 	}
 
+	#[test]
+	fn test_fixed_size_arrays() {
+		let bytes = [7u8; 5];
+		assert_eq!(serialize(&bytes), vec![7u8; 5].into());
+		assert_eq!(bytes.serialized_size(), 5);
+		assert_eq!(deserialize::<_, [u8; 5]>(&[7u8; 5] as &[u8]).unwrap(), bytes);
+		assert_eq!(deserialize::<_, [u8; 5]>(&[7u8; 4] as &[u8]).unwrap_err(), Error::UnexpectedEnd);
+
+		let hashes = [H256::from(1), H256::from(2)];
+		let buffer = serialize(&hashes);
+		assert_eq!(buffer.len(), 64);
+		assert_eq!(&buffer[0..32], &*H256::from(1));
+		assert_eq!(deserialize::<_, [H256; 2]>(&buffer as &[u8]).unwrap(), hashes);
+		assert_eq!(deserialize::<_, [H256; 1]>(&buffer as &[u8]).unwrap_err(), Error::UnreadData);
+	}
+
 	#[test]
 	fn test_reader_iterator() {
 		let buffer = vec![
@@ -366,4 +427,40 @@ mod tests {
 		stream.append_slice(&slice);
 		assert_eq!(stream.out(), "64000000".into());
 	}
+
+	#[test]
+	fn test_reader_limits() {
+		// a length prefix of 2^32 - 1 items followed by a single item
+		let huge: Bytes = "feffffffff0100000000000000".into();
+		assert_eq!(deserialize::<_, List<u64>>(huge.as_ref()).unwrap_err(), Error::LimitExceeded);
+		assert_eq!(deserialize::<_, Bytes>(huge.as_ref()).unwrap_err(), Error::LimitExceeded);
+
+		let limits = ReaderLimits { max_list_len: 2, max_bytes: 24, max_depth: 3 };
+		let raw: Bytes = serialize(&List::from(vec![1u64, 2]));
+		assert_eq!(deserialize_with_limits::<_, List<u64>>(raw.as_ref(), limits).unwrap().into(), vec![1u64, 2]);
+		let raw: Bytes = serialize(&List::from(vec![1u64, 2, 3]));
+		assert_eq!(deserialize_with_limits::<_, List<u64>>(raw.as_ref(), limits).unwrap_err(), Error::LimitExceeded);
+
+		// the allocations of all lists count towards max_bytes
+		let nested = List::from(vec![List::from(vec![1u64, 2]), List::from(vec![3u64])]);
+		let raw: Bytes = serialize(&nested);
+		assert_eq!(deserialize_with_limits::<_, List<List<u64>>>(raw.as_ref(), limits).unwrap_err(), Error::LimitExceeded);
+		let limits = ReaderLimits { max_bytes: 1024, ..limits };
+		let result: Vec<Vec<u64>> = deserialize_with_limits::<_, List<List<u64>>>(raw.as_ref(), limits).unwrap().into()
+			.into_iter().map(List::into).collect();
+		assert_eq!(result, vec![vec![1u64, 2], vec![3]]);
+
+		// a list of lists of u64 is nested three deep
+		let limits = ReaderLimits { max_depth: 2, ..limits };
+		assert_eq!(deserialize_with_limits::<_, List<List<u64>>>(raw.as_ref(), limits).unwrap_err(), Error::LimitExceeded);
+		assert_eq!(deserialize_with_limits::<_, List<u64>>(&[1u8, 7, 0, 0, 0, 0, 0, 0, 0] as &[u8], limits).unwrap().into(), vec![7u64]);
+
+		// a proxy reader shares the limits and allocations of its reader
+		let limits = ReaderLimits { max_bytes: 16, ..ReaderLimits::default() };
+		let raw: Bytes = "0201020301020304".into();
+		let mut reader = Reader::new(raw.as_ref()).with_limits(limits);
+		assert_eq!(reader.read_with_proxy::<Bytes, _>(|_| ()).unwrap(), "0102".into());
+		assert_eq!(reader.reserve(14), Ok(()));
+		assert_eq!(reader.read::<Bytes>().unwrap_err(), Error::LimitExceeded);
+	}
 }
@@ -1,6 +1,7 @@
 extern crate byteorder;
 extern crate primitives;
 
+mod borrowed;
 mod compact_integer;
 mod impls;
 mod list;
@@ -9,9 +10,12 @@ mod stream;
 
 pub use primitives::{hash, bytes, compact};
 
+pub use borrowed::{Deserialize, SliceReader, deserialize_borrowed};
 pub use compact_integer::CompactInteger;
 pub use list::List;
-pub use reader::{Reader, Deserializable, deserialize, deserialize_iterator, ReadIterator, Error};
+pub use reader::{
+	Reader, ReaderLimits, Deserializable, deserialize, deserialize_with_limits, deserialize_iterator, ReadIterator, Error,
+};
 pub use stream::{
 	Stream, Serializable, serialize, serialize_with_flags, serialize_list, serialized_list_size,
 	serialized_list_size_with_flags, SERIALIZE_TRANSACTION_WITNESS,
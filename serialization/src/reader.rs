@@ -1,4 +1,4 @@
-use std::{io, marker};
+use std::{io, marker, mem};
 use compact_integer::CompactInteger;
 
 pub fn deserialize<R, T>(buffer: R) -> Result<T, Error> where R: io::Read, T: Deserializable {
@@ -12,6 +12,18 @@ pub fn deserialize<R, T>(buffer: R) -> Result<T, Error> where R: io::Read, T: De
 	}
 }
 
+/// Deserializes a structure, failing with `Error::LimitExceeded` if it exceeds the limits.
+pub fn deserialize_with_limits<R, T>(buffer: R, limits: ReaderLimits) -> Result<T, Error> where R: io::Read, T: Deserializable {
+	let mut reader = Reader::from_read(buffer).with_limits(limits);
+	let result = try!(reader.read());
+
+	if reader.is_finished() {
+		Ok(result)
+	} else {
+		Err(Error::UnreadData)
+	}
+}
+
 pub fn deserialize_iterator<R, T>(buffer: R) -> ReadIterator<R, T> where R: io::Read, T: Deserializable {
 	ReadIterator {
 		reader: Reader::from_read(buffer),
@@ -24,6 +36,8 @@ pub enum Error {
 	MalformedData,
 	UnexpectedEnd,
 	UnreadData,
+	/// Data exceeds the limits of the reader.
+	LimitExceeded,
 }
 
 impl From<io::Error> for Error {
@@ -36,20 +50,53 @@ pub trait Deserializable {
 	fn deserialize<T>(reader: &mut Reader<T>) -> Result<Self, Error> where Self: Sized, T: io::Read;
 }
 
+/// Limits of a reader, protecting it from length prefixes that would make it allocate
+/// much more memory than the data it reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReaderLimits {
+	/// Maximum number of items of a list.
+	pub max_list_len: usize,
+	/// Maximum number of bytes allocated for lists and byte strings, in total.
+	pub max_bytes: usize,
+	/// Maximum nesting of structures.
+	pub max_depth: usize,
+}
+
+impl Default for ReaderLimits {
+	fn default() -> Self {
+		ReaderLimits {
+			max_list_len: 0x0200_0000,
+			max_bytes: 0x0800_0000,
+			max_depth: 64,
+		}
+	}
+}
+
+impl ReaderLimits {
+	/// Limits that are never exceeded.
+	pub fn unlimited() -> Self {
+		ReaderLimits {
+			max_list_len: usize::max_value(),
+			max_bytes: usize::max_value(),
+			max_depth: usize::max_value(),
+		}
+	}
+}
+
 /// Bitcoin structures reader.
 #[derive(Debug)]
 pub struct Reader<T> {
 	buffer: T,
 	peeked: Option<u8>,
+	limits: ReaderLimits,
+	allocated: usize,
+	depth: usize,
 }
 
 impl<'a> Reader<&'a [u8]> {
 	/// Convenient way of creating for slice of bytes
 	pub fn new(buffer: &'a [u8]) -> Self {
-		Reader {
-			buffer: buffer,
-			peeked: None,
-		}
+		Reader::from_read(buffer)
 	}
 }
 
@@ -77,16 +124,63 @@ impl<R> Reader<R> where R: io::Read {
 		Reader {
 			buffer: read,
 			peeked: None,
+			limits: ReaderLimits::default(),
+			allocated: 0,
+			depth: 0,
 		}
 	}
 
+	pub fn with_limits(mut self, limits: ReaderLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	pub fn limits(&self) -> &ReaderLimits {
+		&self.limits
+	}
+
 	pub fn read<T>(&mut self) -> Result<T, Error> where T: Deserializable {
-		T::deserialize(self)
+		if self.depth >= self.limits.max_depth {
+			return Err(Error::LimitExceeded);
+		}
+
+		self.depth += 1;
+		let result = T::deserialize(self);
+		self.depth -= 1;
+		result
 	}
 
 	pub fn read_with_proxy<T, F>(&mut self, proxy: F) -> Result<T, Error> where T: Deserializable, F: FnMut(&[u8]) {
-		let mut reader = Reader::from_read(Proxy::new(self, proxy));
-		T::deserialize(&mut reader)
+		let (limits, allocated, depth) = (self.limits, self.allocated, self.depth);
+		let mut reader = Reader::from_read(Proxy::new(&mut *self, proxy)).with_limits(limits);
+		reader.allocated = allocated;
+		reader.depth = depth;
+		let result = T::deserialize(&mut reader);
+		let allocated = reader.allocated;
+		self.allocated = allocated;
+		result
+	}
+
+	/// Reads the length prefix of a list, failing if it exceeds the limits.
+	pub fn read_len(&mut self) -> Result<usize, Error> {
+		// the prefix is part of the list, so it is not nested any deeper than the items
+		let len: usize = try!(CompactInteger::deserialize(self)).into();
+		if len > self.limits.max_list_len {
+			return Err(Error::LimitExceeded);
+		}
+
+		Ok(len)
+	}
+
+	/// Accounts for the allocation of `bytes` bytes, failing if the total exceeds the limits.
+	pub fn reserve(&mut self, bytes: usize) -> Result<(), Error> {
+		match self.allocated.checked_add(bytes) {
+			Some(allocated) if allocated <= self.limits.max_bytes => {
+				self.allocated = allocated;
+				Ok(())
+			},
+			_ => Err(Error::LimitExceeded),
+		}
 	}
 
 	pub fn skip_while(&mut self, predicate: &Fn(u8) -> bool) -> Result<(), Error> {
@@ -112,7 +206,8 @@ impl<R> Reader<R> where R: io::Read {
 	}
 
 	pub fn read_list<T>(&mut self) -> Result<Vec<T>, Error> where T: Deserializable {
-		let len: usize = try!(self.read::<CompactInteger>()).into();
+		let len = try!(self.read_len());
+		try!(self.reserve(len.saturating_mul(mem::size_of::<T>())));
 		let mut result = Vec::with_capacity(len);
 
 		for _ in 0..len {
@@ -123,11 +218,12 @@ impl<R> Reader<R> where R: io::Read {
 	}
 
 	pub fn read_list_max<T>(&mut self, max: usize) -> Result<Vec<T>, Error> where T: Deserializable {
-		let len: usize = try!(self.read::<CompactInteger>()).into();
+		let len = try!(self.read_len());
 		if len > max {
 			return Err(Error::MalformedData);
 		}
 
+		try!(self.reserve(len * mem::size_of::<T>()));
 		let mut result = Vec::with_capacity(len);
 
 		for _ in 0..len {
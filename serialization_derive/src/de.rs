@@ -1,26 +1,19 @@
 use {syn, quote};
+use tag::variant_tags;
 
 pub fn impl_deserializable(ast: &syn::DeriveInput) -> quote::Tokens {
-	let body = match ast.body {
-		syn::Body::Struct(ref s) => s,
-		_ => panic!("#[derive(Deserializable)] is only defined for structs."),
-	};
+	let name = &ast.ident;
 
-	let stmts: Vec<_> = match *body {
-		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(deserialize_field_map).collect(),
-		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(deserialize_field_map).collect(),
-		syn::VariantData::Unit => panic!("#[derive(Deserializable)] is not defined for Unit structs."),
+	let body = match ast.body {
+		syn::Body::Struct(ref body) => deserialize_struct(name, body),
+		syn::Body::Enum(ref variants) => deserialize_enum(name, variants),
 	};
 
-	let name = &ast.ident;
-
 	let dummy_const = syn::Ident::new(format!("_IMPL_DESERIALIZABLE_FOR_{}", name));
 	let impl_block = quote! {
 		impl serialization::Deserializable for #name {
 			fn deserialize<T>(reader: &mut serialization::Reader<T>) -> Result<Self, serialization::Error> where T: io::Read {
-				let result = #name {
-					#(#stmts)*
-				};
+				let result = #body;
 
 				Ok(result)
 			}
@@ -37,6 +30,42 @@ pub fn impl_deserializable(ast: &syn::DeriveInput) -> quote::Tokens {
 	}
 }
 
+fn deserialize_struct(name: &syn::Ident, body: &syn::VariantData) -> quote::Tokens {
+	let stmts: Vec<_> = match *body {
+		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(deserialize_field_map).collect(),
+		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(deserialize_field_map).collect(),
+		syn::VariantData::Unit => panic!("#[derive(Deserializable)] is not defined for Unit structs."),
+	};
+
+	quote! {
+		#name {
+			#(#stmts)*
+		}
+	}
+}
+
+// The tag selects the variant whose fields follow, an unknown tag is malformed data.
+fn deserialize_enum(name: &syn::Ident, variants: &[syn::Variant]) -> quote::Tokens {
+	let tags = variant_tags(variants);
+
+	let arms: Vec<_> = variants.iter().zip(tags).map(|(variant, tag)| {
+		let ident = &variant.ident;
+		let stmts: Vec<_> = variant.data.fields().iter().enumerate().map(deserialize_field_map).collect();
+		quote! {
+			#tag => #name::#ident {
+				#(#stmts)*
+			},
+		}
+	}).collect();
+
+	quote! {
+		match u64::from(reader.read::<serialization::CompactInteger>()?) {
+			#(#arms)*
+			_ => return Err(serialization::Error::MalformedData),
+		}
+	}
+}
+
 fn deserialize_field_map(tuple: (usize, &syn::Field)) -> quote::Tokens {
 	deserialize_field(tuple.0, tuple.1)
 }
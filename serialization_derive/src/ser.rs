@@ -1,21 +1,10 @@
 use {syn, quote};
+use tag::{variant_tags, field_bindings};
 
 pub fn impl_serializable(ast: &syn::DeriveInput) -> quote::Tokens {
-	let body = match ast.body {
-		syn::Body::Struct(ref s) => s,
-		_ => panic!("#[derive(Serializable)] is only defined for structs."),
-	};
-
-	let stmts: Vec<_> = match *body {
-		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(serialize_field_map).collect(),
-		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(serialize_field_map).collect(),
-		syn::VariantData::Unit => panic!("#[derive(Serializable)] is not defined for Unit structs."),
-	};
-
-	let size_stmts: Vec<_> = match *body {
-		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(serialize_field_size_map).collect(),
-		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(serialize_field_size_map).collect(),
-		syn::VariantData::Unit => panic!("#[derive(Serializable)] is not defined for Unit structs."),
+	let (stmts, size) = match ast.body {
+		syn::Body::Struct(ref body) => serialize_struct(body),
+		syn::Body::Enum(ref variants) => serialize_enum(&ast.ident, variants),
 	};
 
 	let name = &ast.ident;
@@ -24,11 +13,11 @@ pub fn impl_serializable(ast: &syn::DeriveInput) -> quote::Tokens {
 	let impl_block = quote! {
 		impl serialization::Serializable for #name {
 			fn serialize(&self, stream: &mut serialization::Stream) {
-				#(#stmts)*
+				#stmts
 			}
 
 			fn serialized_size(&self) -> usize {
-				#(#size_stmts)+*
+				#size
 			}
 		}
 	};
@@ -42,18 +31,69 @@ pub fn impl_serializable(ast: &syn::DeriveInput) -> quote::Tokens {
 	}
 }
 
-fn serialize_field_size_map(tuple: (usize, &syn::Field)) -> quote::Tokens {
-	serialize_field_size(tuple.0, tuple.1)
-}
+fn serialize_struct(body: &syn::VariantData) -> (quote::Tokens, quote::Tokens) {
+	let stmts: Vec<_> = match *body {
+		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(serialize_field_map).collect(),
+		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(serialize_field_map).collect(),
+		syn::VariantData::Unit => panic!("#[derive(Serializable)] is not defined for Unit structs."),
+	};
 
-fn serialize_field_size(index: usize, field: &syn::Field) -> quote::Tokens {
-	let ident = match field.ident {
-		Some(ref ident) => ident.to_string(),
-		None => index.to_string(),
+	let size_stmts: Vec<_> = match *body {
+		syn::VariantData::Struct(ref fields) => fields.iter().enumerate().map(serialize_field_size_map).collect(),
+		syn::VariantData::Tuple(ref fields) => fields.iter().enumerate().map(serialize_field_size_map).collect(),
+		syn::VariantData::Unit => panic!("#[derive(Serializable)] is not defined for Unit structs."),
 	};
 
-	let id = syn::Ident::new(format!("self.{}", ident));
+	(quote! { #(#stmts)* }, quote! { #(#size_stmts)+* })
+}
+
+// Every variant writes its tag, then its fields in order.
+fn serialize_enum(name: &syn::Ident, variants: &[syn::Variant]) -> (quote::Tokens, quote::Tokens) {
+	let tags = variant_tags(variants);
+
+	let mut arms = Vec::with_capacity(variants.len());
+	let mut size_arms = Vec::with_capacity(variants.len());
+	for (variant, tag) in variants.iter().zip(tags) {
+		let fields = variant.data.fields();
+		let bindings = field_bindings(fields);
+		let pattern = variant_pattern(name, variant, &bindings);
+		// fields are bound by reference
+		let stmts: Vec<_> = fields.iter().zip(bindings.iter())
+			.map(|(field, binding)| serialize_field(syn::Ident::new(format!("(*{})", binding)), field))
+			.collect();
+		let size_stmts: Vec<_> = fields.iter().zip(bindings.iter())
+			.map(|(field, binding)| serialize_field_size(syn::Ident::new(format!("(*{})", binding)), field))
+			.collect();
+
+		arms.push(quote! {
+			#pattern => {
+				stream.append(&serialization::CompactInteger::from(#tag));
+				#(#stmts)*
+			},
+		});
+		size_arms.push(quote! {
+			#pattern => serialization::Serializable::serialized_size(&serialization::CompactInteger::from(#tag)) #(+ #size_stmts)*,
+		});
+	}
+
+	(quote! { match *self { #(#arms)* } }, quote! { match *self { #(#size_arms)* } })
+}
+
+fn variant_pattern(name: &syn::Ident, variant: &syn::Variant, bindings: &[syn::Ident]) -> quote::Tokens {
+	let ident = &variant.ident;
+	let fields: Vec<_> = variant.data.fields().iter().enumerate().map(|(index, field)| match field.ident {
+		Some(ref ident) => ident.clone(),
+		None => syn::Ident::new(index),
+	}).collect();
+
+	quote! { #name::#ident { #(#fields: ref #bindings),* } }
+}
+
+fn serialize_field_size_map(tuple: (usize, &syn::Field)) -> quote::Tokens {
+	serialize_field_size(self_field(tuple.0, tuple.1), tuple.1)
+}
 
+fn serialize_field_size(id: syn::Ident, field: &syn::Field) -> quote::Tokens {
 	match field.ty {
 		syn::Ty::Path(_, ref path) => {
 			let ident = &path.segments.first().expect("there must be at least 1 segment").ident;
@@ -68,17 +108,10 @@ fn serialize_field_size(index: usize, field: &syn::Field) -> quote::Tokens {
 }
 
 fn serialize_field_map(tuple: (usize, &syn::Field)) -> quote::Tokens {
-	serialize_field(tuple.0, tuple.1)
+	serialize_field(self_field(tuple.0, tuple.1), tuple.1)
 }
 
-fn serialize_field(index: usize, field: &syn::Field) -> quote::Tokens {
-	let ident = match field.ident {
-		Some(ref ident) => ident.to_string(),
-		None => index.to_string(),
-	};
-
-	let id = syn::Ident::new(format!("self.{}", ident));
-
+fn serialize_field(id: syn::Ident, field: &syn::Field) -> quote::Tokens {
 	match field.ty {
 		syn::Ty::Path(_, ref path) => {
 			let ident = &path.segments.first().expect("there must be at least 1 segment").ident;
@@ -92,3 +125,11 @@ fn serialize_field(index: usize, field: &syn::Field) -> quote::Tokens {
 	}
 }
 
+fn self_field(index: usize, field: &syn::Field) -> syn::Ident {
+	let ident = match field.ident {
+		Some(ref ident) => ident.to_string(),
+		None => index.to_string(),
+	};
+
+	syn::Ident::new(format!("self.{}", ident))
+}
@@ -0,0 +1,53 @@
+use syn;
+
+// Tags of enum variants, serialized as a compact integer before the fields of a variant.
+// A variant is tagged with `#[serialization(tag = N)]`, or with its explicit discriminant `= N`,
+// otherwise with the tag of the previous variant plus one, starting at 0.
+pub fn variant_tags(variants: &[syn::Variant]) -> Vec<u64> {
+	let mut tags: Vec<u64> = Vec::with_capacity(variants.len());
+	for variant in variants {
+		let tag = match (attribute_tag(variant), &variant.discriminant) {
+			(Some(tag), _) => tag,
+			(None, &Some(syn::ConstExpr::Lit(syn::Lit::Int(value, _)))) => value,
+			(None, &Some(_)) => panic!("discriminant of {} must be an integer literal, or use #[serialization(tag = N)]", variant.ident),
+			(None, &None) => tags.last().map_or(0, |tag| tag + 1),
+		};
+
+		if tags.contains(&tag) {
+			panic!("tag {} of {} is used by another variant", tag, variant.ident);
+		}
+		tags.push(tag);
+	}
+	tags
+}
+
+fn attribute_tag(variant: &syn::Variant) -> Option<u64> {
+	let mut tag = None;
+	for attr in variant.attrs.iter().filter(|attr| attr.name() == "serialization") {
+		let items = match attr.value {
+			syn::MetaItem::List(_, ref items) => items,
+			_ => panic!("expected #[serialization(tag = N)] on {}", variant.ident),
+		};
+
+		for item in items {
+			match *item {
+				syn::NestedMetaItem::MetaItem(syn::MetaItem::NameValue(ref name, syn::Lit::Int(value, _))) if name == "tag" => {
+					if tag.is_some() {
+						panic!("{} has more than one tag", variant.ident);
+					}
+					tag = Some(value);
+				},
+				_ => panic!("expected #[serialization(tag = N)] on {}", variant.ident),
+			}
+		}
+	}
+	tag
+}
+
+// Names binding the fields of a variant in match patterns.
+pub fn field_bindings(fields: &[syn::Field]) -> Vec<syn::Ident> {
+	fields.iter().enumerate().map(|(index, field)| match field.ident {
+		Some(ref ident) => ident.clone(),
+		None => syn::Ident::new(format!("field{}", index)),
+	}).collect()
+}
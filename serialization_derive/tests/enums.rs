@@ -0,0 +1,118 @@
+extern crate serialization;
+#[macro_use]
+extern crate serialization_derive;
+
+use serialization::{serialize, deserialize, Serializable, Error};
+use serialization::bytes::Bytes;
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum CodingErr {
+	NotZero,
+	NotHash,
+	Stopped,
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum Symbol {
+	Base(Bytes),
+	Upper(Bytes),
+	Empty,
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum TreeEncoding {
+	None = 4,
+	Full,
+	Parity = 9,
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum Message {
+	#[serialization(tag = 1)]
+	Ping,
+	Request { level: u32, indices: Vec<u64> },
+	#[serialization(tag = 300)]
+	Symbols(u32, Vec<Symbol>),
+	Error(CodingErr),
+}
+
+#[test]
+fn test_unit_variants() {
+	let values = [CodingErr::NotZero, CodingErr::NotHash, CodingErr::Stopped];
+	for (tag, value) in values.iter().enumerate() {
+		let result = serialize(value);
+		assert_eq!(result, vec![tag as u8].into());
+		assert_eq!(value.serialized_size(), 1);
+		assert_eq!(&deserialize::<_, CodingErr>(result.as_ref()).unwrap(), value);
+	}
+
+	assert_eq!(deserialize::<_, CodingErr>(&[3u8][..]), Err(Error::MalformedData));
+	assert_eq!(deserialize::<_, CodingErr>(&[][..]), Err(Error::UnexpectedEnd));
+}
+
+#[test]
+fn test_discriminants() {
+	assert_eq!(serialize(&TreeEncoding::None), vec![4u8].into());
+	assert_eq!(serialize(&TreeEncoding::Full), vec![5u8].into());
+	assert_eq!(serialize(&TreeEncoding::Parity), vec![9u8].into());
+	assert_eq!(deserialize::<_, TreeEncoding>(&[9u8][..]).unwrap(), TreeEncoding::Parity);
+	assert_eq!(deserialize::<_, TreeEncoding>(&[6u8][..]), Err(Error::MalformedData));
+}
+
+#[test]
+fn test_tuple_variants() {
+	let symbol = Symbol::Upper(vec![7u8, 8, 9].into());
+	let expected: Bytes = vec![
+		// tag
+		1u8,
+		// bytes
+		3, 7, 8, 9,
+	].into();
+
+	assert_eq!(serialize(&symbol), expected);
+	assert_eq!(symbol.serialized_size(), expected.len());
+	assert_eq!(deserialize::<_, Symbol>(expected.as_ref()).unwrap(), symbol);
+
+	assert_eq!(serialize(&Symbol::Empty), vec![2u8].into());
+	assert_eq!(deserialize::<_, Symbol>(&[2u8][..]).unwrap(), Symbol::Empty);
+	// a variant without its fields
+	assert_eq!(deserialize::<_, Symbol>(&[0u8][..]), Err(Error::UnexpectedEnd));
+}
+
+#[test]
+fn test_explicit_tags() {
+	let messages = vec![
+		(Message::Ping, vec![1u8]),
+		(Message::Request { level: 2, indices: vec![5, 6] }, vec![
+			// tag follows the tag of Ping
+			2u8,
+			2, 0, 0, 0,
+			2,
+			5, 0, 0, 0, 0, 0, 0, 0,
+			6, 0, 0, 0, 0, 0, 0, 0,
+		]),
+		(Message::Symbols(4, vec![Symbol::Base(vec![1u8].into()), Symbol::Empty]), vec![
+			// tag 300 as a compact integer
+			0xfd, 0x2c, 0x01,
+			4, 0, 0, 0,
+			2,
+			0, 1, 1,
+			2,
+		]),
+		(Message::Error(CodingErr::Stopped), vec![
+			// tag follows the tag of Symbols
+			0xfd, 0x2d, 0x01,
+			2,
+		]),
+	];
+
+	for (message, expected) in messages {
+		let expected: Bytes = expected.into();
+		assert_eq!(serialize(&message), expected);
+		assert_eq!(message.serialized_size(), expected.len());
+		assert_eq!(deserialize::<_, Message>(expected.as_ref()).unwrap(), message);
+	}
+
+	assert_eq!(deserialize::<_, Message>(&[0u8][..]), Err(Error::MalformedData));
+	assert_eq!(deserialize::<_, Message>(&[3u8][..]), Err(Error::MalformedData));
+}